@@ -11,17 +11,16 @@ KDIR := /lib/modules/$(shell uname -r)/build
 # Current directory
 PWD := $(shell pwd)
 
-# Rust compiler and flags
-RUSTC := rustc
-RUSTFLAGS := -O
-
-# Rust source files
-RUST_SRC_DEV := device_driver.rs
-RUST_SRC_SYSFS := sysfs.rs
+# Cargo and build output (the Rust clients live in a Cargo workspace)
+CARGO := cargo
+RUST_TARGET_DIR := target/release
 
 # Binary output names
-RUST_BIN_DEV := device_driver
-RUST_BIN_SYSFS := sysfs
+RUST_BIN_CTL := pwmledctl
+RUST_BIN_DAEMON := pwmledd
+
+# Clients replaced by pwmledctl, removed on install
+RUST_BIN_LEGACY := device_driver sysfs
 
 # Default target builds everything
 all: module rust_apps
@@ -46,29 +45,26 @@ uninstall_module:
 	depmod -a
 
 # Rust application targets
-rust_apps: $(RUST_BIN_DEV) $(RUST_BIN_SYSFS)
-
-$(RUST_BIN_DEV): $(RUST_SRC_DEV)
-	$(RUSTC) $(RUSTFLAGS) -o $@ $<
-
-$(RUST_BIN_SYSFS): $(RUST_SRC_SYSFS)
-	$(RUSTC) $(RUSTFLAGS) -o $@ $<
+rust_apps:
+	$(CARGO) build --release --workspace
 
 clean_rust:
-	rm -f $(RUST_BIN_DEV) $(RUST_BIN_SYSFS)
+	$(CARGO) clean
 
 # Combined targets
 clean: clean_module clean_rust
 
 install: install_module
 	# Install the binaries to /usr/local/bin (requires sudo)
-	install -m 755 $(RUST_BIN_DEV) /usr/local/bin/
-	install -m 755 $(RUST_BIN_SYSFS) /usr/local/bin/
+	install -m 755 $(RUST_TARGET_DIR)/$(RUST_BIN_CTL) /usr/local/bin/
+	install -m 755 $(RUST_TARGET_DIR)/$(RUST_BIN_DAEMON) /usr/local/bin/
+	rm -f $(addprefix /usr/local/bin/,$(RUST_BIN_LEGACY))
 
 uninstall: uninstall_module
 	# Remove the binaries from /usr/local/bin
-	rm -f /usr/local/bin/$(RUST_BIN_DEV)
-	rm -f /usr/local/bin/$(RUST_BIN_SYSFS)
+	rm -f /usr/local/bin/$(RUST_BIN_CTL)
+	rm -f /usr/local/bin/$(RUST_BIN_DAEMON)
+	rm -f $(addprefix /usr/local/bin/,$(RUST_BIN_LEGACY))
 
 
 obj-m := $(MODULE_NAME).o
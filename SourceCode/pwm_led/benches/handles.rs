@@ -0,0 +1,73 @@
+//! Compares opening the interface files on every access with keeping them
+//! open (`keep_open`), on the fake sysfs tree and on a plain file standing
+//! in for the character device. Run with `cargo bench -p pwm_led`.
+//!
+//! One pass is a speed reading and, on sysfs, a full frame written, like a
+//! step of the control loop. A plain file cannot stand in for the device's
+//! command side, as the command would overwrite the speed line, so the
+//! character device is only read.
+
+use std::fs;
+use std::hint::black_box;
+use std::process;
+use std::time::{Duration, Instant};
+
+use pwm_led::{CharDevBackend, DutyFrame, FakeSysfsBackend, LedBackend, SysfsBackend};
+
+const ITERATIONS: u32 = 20_000;
+
+fn time_passes(backend: &mut dyn LedBackend, write: bool) -> Duration {
+    let frames: Vec<DutyFrame> = (0..100)
+        .map(|duty| DutyFrame::from(vec![duty; backend.led_count()]))
+        .collect();
+
+    // Warm up the page cache and the allocator
+    for _ in 0..100 {
+        black_box(backend.read_speed().unwrap());
+    }
+
+    let start = Instant::now();
+    for pass in 0..ITERATIONS {
+        black_box(backend.read_speed().unwrap());
+        if write {
+            backend
+                .write_duties(&frames[pass as usize % frames.len()])
+                .unwrap();
+        }
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn report(name: &str, reopen: Duration, kept_open: Duration) {
+    println!(
+        "{:<8} reopen {:>8.2?}/pass   kept open {:>8.2?}/pass   {:.1}x faster",
+        name,
+        reopen,
+        kept_open,
+        reopen.as_secs_f64() / kept_open.as_secs_f64()
+    );
+}
+
+fn main() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(4.0).unwrap();
+    let reopen = time_passes(&mut SysfsBackend::with_path(fake.path()).unwrap(), true);
+    let kept_open = time_passes(
+        &mut SysfsBackend::with_path(fake.path())
+            .unwrap()
+            .keep_open()
+            .unwrap(),
+        true,
+    );
+    report("sysfs", reopen, kept_open);
+
+    let device = std::env::temp_dir().join(format!("pwm_led-bench-{}", process::id()));
+    fs::write(&device, "Button Press Speed: 4 presses/second\n").unwrap();
+    let reopen = time_passes(&mut CharDevBackend::with_path(&device), false);
+    let kept_open = time_passes(
+        &mut CharDevBackend::with_path(&device).keep_open().unwrap(),
+        false,
+    );
+    report("chardev", reopen, kept_open);
+    fs::remove_file(&device).unwrap();
+}
@@ -0,0 +1,324 @@
+//! Async backends and control loop (`tokio` feature), for callers running on
+//! a tokio executor. Files are accessed through `tokio::fs`, so a slow read
+//! or write never blocks the executor thread.
+//!
+//! The backends behave like their blocking counterparts: same validation,
+//! same errors, same text formats.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use futures_util::stream::{self, Stream};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep_until;
+
+use crate::backend::{check_duty, check_frame, check_led, DEFAULT_LED_COUNT};
+use crate::chardev::{format_command, parse_speed_line, DEVICE_PATH};
+use crate::config::Config;
+use crate::control::ControlState;
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+use crate::speed::{parse_speed, speed_from_interval_ns};
+use crate::sysfs::{duty_path, INTERVAL_ATTRIBUTE, SYSFS_PATH};
+
+/// Async counterpart of `LedBackend`. LEDs are addressed by zero-based
+/// index.
+pub trait AsyncLedBackend: Send {
+    /// Number of LED channels behind this backend.
+    fn led_count(&self) -> usize;
+
+    /// Reads the current button press speed in presses/second.
+    fn read_speed(&mut self) -> impl Future<Output = Result<f64>> + Send;
+
+    /// Reads the duty cycle of one LED.
+    fn read_duty(&mut self, led: usize) -> impl Future<Output = Result<u32>> + Send;
+
+    /// Reads the duty cycles of all LEDs.
+    fn read_duties(&mut self) -> impl Future<Output = Result<DutyFrame>> + Send {
+        async move {
+            let mut frame = Vec::with_capacity(self.led_count());
+            for led in 0..self.led_count() {
+                frame.push(self.read_duty(led).await?);
+            }
+            Ok(DutyFrame::from(frame))
+        }
+    }
+
+    /// Writes the duty cycle of one LED, leaving the others untouched.
+    fn write_duty(&mut self, led: usize, duty: u32) -> impl Future<Output = Result<()>> + Send;
+
+    /// Writes the duty cycles of all LEDs as one update. The frame must have
+    /// exactly `led_count` channels.
+    fn write_duties(&mut self, frame: &DutyFrame) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Async `SysfsBackend`.
+#[derive(Debug)]
+pub struct AsyncSysfsBackend {
+    base: PathBuf,
+    channels: usize,
+    // The attribute `read_speed` reads, settled when opening
+    speed_path: PathBuf,
+}
+
+impl AsyncSysfsBackend {
+    /// Opens the default sysfs directory.
+    pub async fn new() -> Result<Self> {
+        Self::with_path(SYSFS_PATH).await
+    }
+
+    /// Opens the attributes found in another directory, counting the
+    /// `ledN_duty` channels like `SysfsBackend::with_path`. Which speed
+    /// attribute is read is settled here, as in `SysfsBackend::keep_open`.
+    pub async fn with_path(base: impl Into<PathBuf>) -> Result<Self> {
+        let base = base.into();
+        let mut channels = 0;
+        while exists(&duty_path(&base, channels)).await {
+            channels += 1;
+        }
+        if channels == 0 {
+            return Err(ControllerError::DeviceMissing {
+                path: duty_path(&base, 0),
+            });
+        }
+        // Prefers the averaged press interval, like `SysfsBackend`
+        let interval_path = base.join(INTERVAL_ATTRIBUTE);
+        let speed_path = if exists(&interval_path).await {
+            interval_path
+        } else {
+            base.join("button_speed")
+        };
+        Ok(AsyncSysfsBackend {
+            base,
+            channels,
+            speed_path,
+        })
+    }
+
+    /// Directory holding the attributes.
+    pub fn path(&self) -> &Path {
+        &self.base
+    }
+}
+
+async fn exists(path: &Path) -> bool {
+    fs::try_exists(path).await.unwrap_or(false)
+}
+
+async fn read_text(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .await
+        .map_err(|e| ControllerError::from_io(path, e))
+}
+
+async fn read_attribute<T: FromStr>(path: &Path) -> Result<T> {
+    let buffer = read_text(path).await?;
+    buffer
+        .trim()
+        .parse()
+        .map_err(|_| ControllerError::malformed(path, &buffer))
+}
+
+impl AsyncLedBackend for AsyncSysfsBackend {
+    fn led_count(&self) -> usize {
+        self.channels
+    }
+
+    async fn read_speed(&mut self) -> Result<f64> {
+        let path = &self.speed_path;
+        if path.ends_with(INTERVAL_ATTRIBUTE) {
+            let interval_ns: u64 = read_attribute(path).await?;
+            return Ok(speed_from_interval_ns(interval_ns));
+        }
+
+        let buffer = read_text(path).await?;
+        parse_speed(buffer.trim()).ok_or_else(|| ControllerError::malformed(path, &buffer))
+    }
+
+    async fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.channels)?;
+        read_attribute(&duty_path(&self.base, led)).await
+    }
+
+    async fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.channels)?;
+        check_duty(led, duty)?;
+        let path = duty_path(&self.base, led);
+        let io_err = |e| ControllerError::from_io(&path, e);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .map_err(io_err)?;
+        file.write_all(duty.to_string().as_bytes())
+            .await
+            .map_err(io_err)?;
+        file.flush().await.map_err(io_err)
+    }
+
+    // Validated first so a bad value cannot leave the frame half applied.
+    async fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.channels)?;
+        for (led, &duty) in frame.iter().enumerate() {
+            self.write_duty(led, duty).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Async `CharDevBackend`. Like the blocking one it remembers the last
+/// frame written, as the device cannot report duty cycles.
+#[derive(Debug)]
+pub struct AsyncCharDevBackend {
+    path: PathBuf,
+    channels: usize,
+    last: Option<DutyFrame>,
+}
+
+impl AsyncCharDevBackend {
+    /// Creates a backend for the default device path.
+    pub fn new() -> Self {
+        Self::with_path(DEVICE_PATH)
+    }
+
+    /// Creates a backend for a three-channel device node at another path.
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        Self::with_channels(path, DEFAULT_LED_COUNT)
+    }
+
+    /// Creates a backend for a device node driving `channels` LEDs.
+    pub fn with_channels(path: impl Into<PathBuf>, channels: usize) -> Self {
+        AsyncCharDevBackend {
+            path: path.into(),
+            channels,
+            last: None,
+        }
+    }
+
+    fn last_frame(&self) -> Result<&DutyFrame> {
+        self.last.as_ref().ok_or(ControllerError::Unsupported(
+            "the character device cannot report duty cycles before they are written",
+        ))
+    }
+}
+
+impl Default for AsyncCharDevBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncLedBackend for AsyncCharDevBackend {
+    fn led_count(&self) -> usize {
+        self.channels
+    }
+
+    async fn read_speed(&mut self) -> Result<f64> {
+        let buffer = read_text(&self.path).await?;
+        parse_speed_line(&buffer).ok_or_else(|| ControllerError::malformed(&self.path, &buffer))
+    }
+
+    async fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.channels)?;
+        Ok(self.last_frame()?[led])
+    }
+
+    async fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.channels)?;
+        let mut frame = self.last_frame()?.clone();
+        frame[led] = duty;
+        self.write_duties(&frame).await
+    }
+
+    async fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.channels)?;
+        let io_err = |e| ControllerError::from_io(&self.path, e);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .await
+            .map_err(io_err)?;
+        // The module applies the whole command in one go
+        file.write_all(format_command(frame).as_bytes())
+            .await
+            .map_err(io_err)?;
+        file.flush().await.map_err(io_err)?;
+        self.last = Some(frame.clone());
+        Ok(())
+    }
+}
+
+/// Reads the speed every `interval`, the first time straight away, and
+/// yields every sample. Failed readings are yielded as errors without
+/// ending the stream.
+pub fn speed_samples<B: AsyncLedBackend + ?Sized>(
+    backend: &mut B,
+    interval: Duration,
+) -> impl Stream<Item = Result<f64>> + '_ {
+    stream::unfold(
+        (backend, None::<Instant>),
+        move |(backend, last_read)| async move {
+            if let Some(last_read) = last_read {
+                sleep_until((last_read + interval).into()).await;
+            }
+            let read_at = Instant::now();
+            let sample = backend.read_speed().await;
+            Some((sample, (backend, Some(read_at))))
+        },
+    )
+}
+
+/// Async `run_every`: maps the speed with `config` and fades towards each
+/// new target until the backend fails or `shutdown` completes, polling the
+/// speed between `MIN_POLL_INTERVAL` and `interval` like the blocking loop.
+///
+/// Shutting down is only noticed between two frames, so the LEDs are never
+/// left with half a frame written. Returns `Ok` once `shutdown` completes.
+pub async fn run_until<B, F>(
+    backend: &mut B,
+    config: &Config,
+    interval: Duration,
+    shutdown: F,
+) -> Result<()>
+where
+    B: AsyncLedBackend + ?Sized,
+    F: Future<Output = ()>,
+{
+    if config.mapping.channels() != backend.led_count() {
+        return Err(ControllerError::ChannelMismatch {
+            expected: backend.led_count(),
+            found: config.mapping.channels(),
+        });
+    }
+    tokio::pin!(shutdown);
+
+    // Fade from whatever the LEDs show now, if the backend can tell us
+    let initial = backend.read_duties().await.ok();
+    let mut state = ControlState::new(config, interval, initial);
+    loop {
+        let read_at = Instant::now();
+        let speed = backend.read_speed().await?;
+        state.take_speed(speed, read_at);
+
+        // Fade until the next reading
+        loop {
+            let now = Instant::now();
+            if let Some(frame) = state.next_frame(now) {
+                backend.write_duties(&frame).await?;
+            }
+            let wait = state.wait(now);
+            tokio::select! {
+                biased;
+                () = &mut shutdown => return Ok(()),
+                () = sleep_until((now + wait.unwrap_or_default()).into()) => {}
+            }
+            if wait.is_none() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Transport-independent access to the LED controller.
+
+use std::fmt;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::buttons::ButtonSpeed;
+use crate::chardev::{self, CharDevBackend};
+use crate::config::Config;
+use crate::error::{ControllerError, Result, MAX_DUTY};
+use crate::frame::DutyFrame;
+use crate::ledclass::LedClassBackend;
+use crate::pwmchip::PwmChipBackend;
+use crate::softpwm::SoftPwmBackend;
+use crate::speed::{SpeedFile, SpeedSource};
+use crate::sysfs::{self, SysfsBackend};
+use crate::watch::SpeedNotifier;
+
+/// Number of LEDs driven by the stock kernel module.
+pub const DEFAULT_LED_COUNT: usize = 3;
+
+/// A transport to the LED controller: reads the button speed and reads or
+/// writes LED duty cycles. LEDs are addressed by zero-based index.
+pub trait LedBackend {
+    /// Number of LED channels behind this backend.
+    fn led_count(&self) -> usize;
+
+    /// Reads the current button press speed in presses/second, with
+    /// sub-integer resolution where the interface provides it.
+    fn read_speed(&mut self) -> Result<f64>;
+
+    /// Reads the duty cycle of one LED.
+    fn read_duty(&mut self, led: usize) -> Result<u32>;
+
+    /// Reads the duty cycles of all LEDs.
+    fn read_duties(&mut self) -> Result<DutyFrame> {
+        (0..self.led_count())
+            .map(|led| self.read_duty(led))
+            .collect()
+    }
+
+    /// Writes the duty cycle of one LED, leaving the others untouched.
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()>;
+
+    /// Writes the duty cycles of all LEDs as one update. The frame must have
+    /// exactly `led_count` channels.
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()>;
+
+    /// Opens a way to wait for the speed to change, for interfaces that can
+    /// signal it. Without one, callers poll `read_speed`.
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        None
+    }
+
+    /// Hands back what the backend took over for a long-running loop, such
+    /// as LED triggers. Called once the loop exits; one-shot commands skip
+    /// it so what they wrote stays.
+    fn release(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: LedBackend + ?Sized> LedBackend for Box<B> {
+    fn led_count(&self) -> usize {
+        (**self).led_count()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        (**self).read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        (**self).read_duty(led)
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        (**self).write_duty(led, duty)
+    }
+
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        (**self).write_duties(frame)
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        (**self).speed_notifier()
+    }
+
+    fn release(&mut self) -> Result<()> {
+        (**self).release()
+    }
+}
+
+/// Which transport to use, chosen at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendKind {
+    /// Sysfs if the attributes exist, otherwise the character device.
+    #[default]
+    Auto,
+    CharDev,
+    Sysfs,
+    /// Hardware PWM channels under `/sys/class/pwm`, as set up by `[pwm]`.
+    /// Never picked by `Auto`.
+    PwmChip,
+    /// LED class devices under `/sys/class/leds`, as listed in `[leds]`.
+    /// Never picked by `Auto`.
+    LedClass,
+    /// Software PWM on the GPIO lines of `[softpwm]`, toggled from a
+    /// thread of this process. Never picked by `Auto`.
+    SoftPwm,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(BackendKind::Auto),
+            "chardev" => Ok(BackendKind::CharDev),
+            "sysfs" => Ok(BackendKind::Sysfs),
+            "pwmchip" => Ok(BackendKind::PwmChip),
+            "ledclass" => Ok(BackendKind::LedClass),
+            "softpwm" => Ok(BackendKind::SoftPwm),
+            _ => Err(format!(
+                "unknown backend '{}', expected chardev, sysfs, pwmchip, ledclass, softpwm or auto",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::Auto => "auto",
+            BackendKind::CharDev => "chardev",
+            BackendKind::Sysfs => "sysfs",
+            BackendKind::PwmChip => "pwmchip",
+            BackendKind::LedClass => "ledclass",
+            BackendKind::SoftPwm => "softpwm",
+        })
+    }
+}
+
+/// Where to find the kernel module's interfaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendPaths {
+    pub device: PathBuf,
+    pub sysfs: PathBuf,
+}
+
+impl Default for BackendPaths {
+    fn default() -> Self {
+        BackendPaths {
+            device: chardev::DEVICE_PATH.into(),
+            sysfs: sysfs::SYSFS_PATH.into(),
+        }
+    }
+}
+
+impl BackendKind {
+    /// Picks a concrete transport for `Auto`: sysfs if its directory exists,
+    /// otherwise the character device. Other kinds are returned as is.
+    pub fn resolve(self, paths: &BackendPaths) -> Result<BackendKind> {
+        match self {
+            BackendKind::Auto if paths.sysfs.is_dir() => Ok(BackendKind::Sysfs),
+            BackendKind::Auto if paths.device.exists() => Ok(BackendKind::CharDev),
+            BackendKind::Auto => Err(ControllerError::DeviceMissing {
+                path: paths.sysfs.clone(),
+            }),
+            kind => Ok(kind),
+        }
+    }
+}
+
+/// Opens the backend of the given kind at its default location.
+pub fn open(kind: BackendKind) -> Result<Box<dyn LedBackend + Send>> {
+    open_with(kind, &BackendPaths::default())
+}
+
+/// Opens the backend of the given kind at `paths`, keeping its files open.
+/// `PwmChip`, `LedClass` and `SoftPwm` get the default `[pwm]`, `[leds]`
+/// and `[softpwm]` setup.
+pub fn open_with(kind: BackendKind, paths: &BackendPaths) -> Result<Box<dyn LedBackend + Send>> {
+    open_configured(kind, paths, &Config::default())
+}
+
+/// Like `open_with`, with the `PwmChip`, `LedClass` and `SoftPwm` setup
+/// taken from `config`. With a `[buttons]` section they measure the speed
+/// from the buttons themselves.
+pub fn open_configured(
+    kind: BackendKind,
+    paths: &BackendPaths,
+    config: &Config,
+) -> Result<Box<dyn LedBackend + Send>> {
+    match kind.resolve(paths)? {
+        BackendKind::CharDev => Ok(Box::new(
+            CharDevBackend::with_path(&paths.device).keep_open()?,
+        )),
+        BackendKind::PwmChip => {
+            let pwm = &config.pwm;
+            Ok(Box::new(PwmChipBackend::open(
+                &pwm.chip,
+                &pwm.channels,
+                pwm.period_ns,
+                speed_source(config, &pwm.speed)?,
+            )?))
+        }
+        BackendKind::LedClass => {
+            let leds = &config.leds;
+            Ok(Box::new(LedClassBackend::open(
+                &leds.path,
+                &leds.names,
+                leds.disable_triggers,
+                speed_source(config, &leds.speed)?,
+            )?))
+        }
+        BackendKind::SoftPwm => {
+            let softpwm = &config.softpwm;
+            Ok(Box::new(SoftPwmBackend::open(
+                &softpwm.chip,
+                &softpwm.lines,
+                softpwm.period(),
+                softpwm.priority,
+                speed_source(config, &softpwm.speed)?,
+            )?))
+        }
+        _ => Ok(Box::new(
+            SysfsBackend::with_path(&paths.sysfs)?.keep_open()?,
+        )),
+    }
+}
+
+// The buttons of `[buttons]` if there is one, else the backend's speed file.
+fn speed_source(config: &Config, file: &Path) -> Result<Box<dyn SpeedSource + Send>> {
+    match &config.buttons {
+        Some(buttons) => Ok(Box::new(ButtonSpeed::from_config(buttons)?)),
+        None => Ok(Box::new(SpeedFile::new(file))),
+    }
+}
+
+// Rejects LED indices the backend does not have.
+pub(crate) fn check_led(led: usize, count: usize) -> Result<()> {
+    if led < count {
+        Ok(())
+    } else {
+        Err(ControllerError::LedOutOfRange { led, count })
+    }
+}
+
+// Rejects duty cycles the kernel module would refuse anyway.
+pub(crate) fn check_duty(led: usize, duty: u32) -> Result<()> {
+    if duty <= MAX_DUTY {
+        Ok(())
+    } else {
+        Err(ControllerError::DutyOutOfRange { led, duty })
+    }
+}
+
+// Reads a file kept open from offset 0, which makes sysfs and the character
+// device generate their contents again.
+pub(crate) fn read_from_start(file: &File, path: &Path) -> Result<String> {
+    let mut buffer = vec![0; 128];
+    let mut len = 0;
+    loop {
+        let read = file
+            .read_at(&mut buffer[len..], len as u64)
+            .map_err(|e| ControllerError::from_io(path, e))?;
+        len += read;
+        // Attributes arrive in one read; only a full buffer needs another
+        if len < buffer.len() {
+            break;
+        }
+        buffer.resize(len * 2, 0);
+    }
+    buffer.truncate(len);
+    String::from_utf8(buffer)
+        .map_err(|e| ControllerError::malformed(path, &String::from_utf8_lossy(e.as_bytes())))
+}
+
+// Validates a whole frame before any of it is written.
+pub(crate) fn check_frame(frame: &DutyFrame, count: usize) -> Result<()> {
+    if frame.channels() != count {
+        return Err(ControllerError::ChannelMismatch {
+            expected: count,
+            found: frame.channels(),
+        });
+    }
+    frame
+        .iter()
+        .enumerate()
+        .try_for_each(|(led, &duty)| check_duty(led, duty))
+}
@@ -0,0 +1,160 @@
+//! Button press speed measured in userspace from GPIO edges with a
+//! `SpeedEstimator`, the way the kernel module's interrupt handlers measure
+//! it, so the LEDs can run without the module.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::estimator::SpeedEstimator;
+use crate::gpio::{monotonic_ns, Edge, InputLines, LineEventSource, GPIO_CHIP_PATH};
+use crate::speed::SpeedSource;
+use crate::watch::SpeedNotifier;
+
+/// `[buttons]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonConfig {
+    /// GPIO chip the buttons are on.
+    pub chip: PathBuf,
+    /// Line offsets of button 1 and button 2.
+    pub lines: [u32; 2],
+    /// Edge counted as a press. The module counts rising edges.
+    pub edge: Edge,
+    /// Bounces shorter than this are filtered by the kernel; 0, like the
+    /// module, counts every edge.
+    pub debounce_us: u32,
+    /// Average the intervals of this many milliseconds instead of the
+    /// module's running average, so the speed drops back to 0 once the
+    /// buttons rest that long.
+    pub window_ms: Option<u64>,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        ButtonConfig {
+            chip: PathBuf::from(GPIO_CHIP_PATH),
+            lines: [23, 24],
+            edge: Edge::Rising,
+            debounce_us: 0,
+            window_ms: None,
+        }
+    }
+}
+
+impl ButtonConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.lines[0] == self.lines[1] {
+            return Err(format!(
+                "both buttons are on line {}; they must differ",
+                self.lines[0]
+            ));
+        }
+        if self.window_ms == Some(0) {
+            return Err("window_ms must be above 0".into());
+        }
+        Ok(())
+    }
+
+    /// The estimator this section asks for.
+    pub fn estimator(&self) -> SpeedEstimator {
+        match self.window_ms {
+            Some(window_ms) => SpeedEstimator::windowed(Duration::from_millis(window_ms)),
+            None => SpeedEstimator::new(),
+        }
+    }
+}
+
+/// Speed of the two buttons, measured from their line events.
+///
+/// Events are taken in whenever the speed is read. Their kernel timestamps
+/// make that as exact as handling each one as it comes, as long as the
+/// kernel's event buffer (16 per line) is read before it fills up.
+pub struct ButtonSpeed {
+    events: Box<dyn LineEventSource>,
+    lines: [u32; 2],
+    estimator: SpeedEstimator,
+}
+
+impl ButtonSpeed {
+    /// Measures the speed from the events of `lines`, button 1 then
+    /// button 2, like the module. Events of other lines are ignored.
+    pub fn new(events: Box<dyn LineEventSource>, lines: [u32; 2]) -> Self {
+        Self::with_estimator(events, lines, SpeedEstimator::new())
+    }
+
+    /// Like `new`, feeding the presses to `estimator`. A windowed one
+    /// expires intervals against `CLOCK_MONOTONIC`, the clock of the
+    /// kernel's event timestamps.
+    pub fn with_estimator(
+        events: Box<dyn LineEventSource>,
+        lines: [u32; 2],
+        estimator: SpeedEstimator,
+    ) -> Self {
+        ButtonSpeed {
+            events,
+            lines,
+            estimator,
+        }
+    }
+
+    /// Requests the lines of a `[buttons]` section.
+    pub fn from_config(config: &ButtonConfig) -> Result<Self> {
+        let lines =
+            InputLines::request(&config.chip, &config.lines, config.edge, config.debounce_us)?;
+        Ok(Self::with_estimator(
+            Box::new(lines),
+            config.lines,
+            config.estimator(),
+        ))
+    }
+
+    /// Takes in the events that arrived since the last call.
+    pub fn update(&mut self) -> Result<()> {
+        for event in self.events.read_events()? {
+            if self.lines.contains(&event.offset) {
+                self.estimator.press(event.offset, event.timestamp_ns);
+            }
+        }
+        Ok(())
+    }
+
+    /// Average time between alternating presses, truncated to whole
+    /// nanoseconds like the module's `press_interval_ns`; 0 until two
+    /// alternating presses.
+    pub fn press_interval_ns(&self) -> u64 {
+        self.estimator.press_interval_ns()
+    }
+
+    /// Every press counted so far, alternating or not.
+    pub fn presses(&self) -> u64 {
+        self.estimator.presses()
+    }
+}
+
+impl fmt::Debug for ButtonSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ButtonSpeed")
+            .field("lines", &self.lines)
+            .field("estimator", &self.estimator)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SpeedSource for ButtonSpeed {
+    fn read_speed(&mut self) -> Result<f64> {
+        self.update()?;
+        self.estimator.expire(monotonic_ns());
+        Ok(self.estimator.speed())
+    }
+
+    // Wakes on every edge, not only alternating presses; reading the speed
+    // again is cheap
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        self.events.event_notifier()
+    }
+}
@@ -0,0 +1,171 @@
+//! Access to the kernel module through its character device.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+use crate::backend::{check_frame, check_led, read_from_start, LedBackend, DEFAULT_LED_COUNT};
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+use crate::speed::parse_speed;
+
+/// Path to character device.
+pub const DEVICE_PATH: &str = "/dev/pwm_led_controller";
+
+/// Prefix of the line the device returns on read.
+const SPEED_PREFIX: &str = "Button Press Speed:";
+
+/// Backend using the `/dev/pwm_led_controller` text protocol.
+///
+/// The device takes every duty at once as space-separated numbers and cannot
+/// report them, so the last written frame is remembered for `read_duty` and
+/// `write_duty`. It cannot report its channel count either; the stock module
+/// has three, other builds are opened with `with_channels`.
+///
+/// Each access opens the device again unless `keep_open` was called.
+#[derive(Debug)]
+pub struct CharDevBackend {
+    path: PathBuf,
+    channels: usize,
+    last: Option<DutyFrame>,
+    device: Option<File>,
+}
+
+impl CharDevBackend {
+    /// Creates a backend for the default device path.
+    pub fn new() -> Self {
+        Self::with_path(DEVICE_PATH)
+    }
+
+    /// Creates a backend for a three-channel device node at another path.
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        Self::with_channels(path, DEFAULT_LED_COUNT)
+    }
+
+    /// Creates a backend for a device node driving `channels` LEDs.
+    pub fn with_channels(path: impl Into<PathBuf>, channels: usize) -> Self {
+        CharDevBackend {
+            path: path.into(),
+            channels,
+            last: None,
+            device: None,
+        }
+    }
+
+    /// Opens the device once for reading and writing and keeps it open,
+    /// re-reading the speed with `pread` at offset 0.
+    ///
+    /// Modules that only prepare the speed message when the device is
+    /// opened answer such a re-read with nothing; the device is then opened
+    /// again for that reading.
+    pub fn keep_open(mut self) -> Result<Self> {
+        self.device = Some(self.open_device()?);
+        Ok(self)
+    }
+
+    fn open_device(&self) -> Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| ControllerError::from_io(&self.path, e))
+    }
+
+    fn last_frame(&self) -> Result<&DutyFrame> {
+        self.last.as_ref().ok_or(ControllerError::Unsupported(
+            "the character device cannot report duty cycles before they are written",
+        ))
+    }
+}
+
+impl Default for CharDevBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses the device output, e.g. "Button Press Speed: 3 presses/second".
+/// Decimal speeds such as "3.75" are accepted as well.
+pub fn parse_speed_line(line: &str) -> Option<f64> {
+    let rest = line.trim().strip_prefix(SPEED_PREFIX)?;
+    let mut words = rest.split_whitespace();
+    let speed = parse_speed(words.next()?)?;
+    match words.next() {
+        Some("presses/second") => Some(speed),
+        _ => None,
+    }
+}
+
+/// Formats the write command for a frame, e.g. "10 0 0".
+pub fn format_command(frame: &DutyFrame) -> String {
+    frame
+        .iter()
+        .map(|duty| duty.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl LedBackend for CharDevBackend {
+    fn led_count(&self) -> usize {
+        self.channels
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        if let Some(device) = &self.device {
+            let mut buffer = read_from_start(device, &self.path)?;
+            if buffer.is_empty() {
+                let device = self.open_device()?;
+                buffer = read_from_start(&device, &self.path)?;
+                self.device = Some(device);
+            }
+            return parse_speed_line(&buffer)
+                .ok_or_else(|| ControllerError::malformed(&self.path, &buffer));
+        }
+
+        let io_err = |e| ControllerError::from_io(&self.path, e);
+
+        // Open device file for reading
+        let mut file = File::open(&self.path).map_err(io_err)?;
+        let mut buffer = String::new();
+
+        // Read device output
+        file.read_to_string(&mut buffer).map_err(io_err)?;
+
+        parse_speed_line(&buffer).ok_or_else(|| ControllerError::malformed(&self.path, &buffer))
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.channels)?;
+        Ok(self.last_frame()?[led])
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.channels)?;
+        let mut frame = self.last_frame()?.clone();
+        frame[led] = duty;
+        self.write_duties(&frame)
+    }
+
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.channels)?;
+        let io_err = |e| ControllerError::from_io(&self.path, e);
+        let command = format_command(frame);
+        if let Some(device) = &self.device {
+            device.write_all_at(command.as_bytes(), 0).map_err(io_err)?;
+            self.last = Some(frame.clone());
+            return Ok(());
+        }
+
+        // Open device file for writing
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .map_err(io_err)?;
+
+        // Write command to device file, the module applies it in one go
+        file.write_all(command.as_bytes()).map_err(io_err)?;
+        self.last = Some(frame.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Runtime configuration loaded from a TOML file.
+//!
+//! The file is optional: without one the built-in default profile is used.
+//! Its path comes from `--config PATH` or the `PWM_LED_CONFIG` variable.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::buttons::ButtonConfig;
+use crate::daemon::DaemonConfig;
+use crate::fade::FadeConfig;
+use crate::filter::FilterConfig;
+use crate::ledclass::LedClassConfig;
+use crate::mapping::Mapping;
+use crate::mqtt::MqttConfig;
+use crate::pwmchip::PwmConfig;
+use crate::shutdown::ShutdownConfig;
+use crate::softpwm::SoftPwmConfig;
+
+/// Environment variable holding the config file path.
+pub const CONFIG_ENV: &str = "PWM_LED_CONFIG";
+
+/// Name of the profile described by `[mapping]`.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Everything that can be tuned per installation.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mapping: Mapping,
+    pub fade: FadeConfig,
+    pub filter: FilterConfig,
+    /// Extra mapping profiles the daemon can switch to, by name. `[mapping]`
+    /// is the profile called `default`.
+    pub profiles: BTreeMap<String, Mapping>,
+    pub daemon: DaemonConfig,
+    /// MQTT bridge of the daemon, off without a `[mqtt]` section.
+    pub mqtt: Option<MqttConfig>,
+    pub shutdown: ShutdownConfig,
+    /// Chip and channels of `--backend pwmchip`.
+    pub pwm: PwmConfig,
+    /// LEDs of `--backend ledclass`.
+    pub leds: LedClassConfig,
+    /// GPIO lines of `--backend softpwm`.
+    pub softpwm: SoftPwmConfig,
+    /// Buttons read from GPIO by the `pwmchip`, `ledclass` and `softpwm`
+    /// backends, instead of their `speed` file.
+    pub buttons: Option<ButtonConfig>,
+}
+
+/// Errors while locating, reading or validating the config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Bad command line usage.
+    Usage(String),
+    /// The file could not be read.
+    Io { path: PathBuf, source: io::Error },
+    /// The file is not valid TOML or has unknown keys.
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// The file parsed but describes an unusable setup.
+    Invalid { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Usage(msg) => write!(f, "{}", msg),
+            ConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Invalid { path, reason } => {
+                write!(f, "{}: invalid config: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// Parses and validates a config from TOML text. `path` is only used
+    /// in error messages.
+    pub fn from_toml(text: &str, path: &Path) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        config.validate().map_err(|reason| ConfigError::Invalid {
+            path: path.to_path_buf(),
+            reason,
+        })?;
+        Ok(config)
+    }
+
+    /// Reads, parses and validates the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&text, path)
+    }
+
+    /// Loads the config named by `--config` in `args` (program name already
+    /// skipped) or by `PWM_LED_CONFIG`, falling back to the defaults.
+    pub fn from_args_or_env<I>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        Self::load_or_default(config_arg(args)?.as_deref())
+    }
+
+    /// Loads the config at `path`, or the one named by `PWM_LED_CONFIG`,
+    /// falling back to the defaults when neither is given.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match env::var_os(CONFIG_ENV) {
+                Some(path) => Self::load(Path::new(&path)),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+
+    /// Checks every section, returning the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        self.mapping
+            .validate()
+            .map_err(|e| format!("[mapping] {}", e))?;
+        self.fade.validate().map_err(|e| format!("[fade] {}", e))?;
+        self.filter
+            .validate()
+            .map_err(|e| format!("[filter] {}", e))?;
+        for (name, mapping) in &self.profiles {
+            if name == DEFAULT_PROFILE {
+                return Err(format!(
+                    "[profiles.{}] is reserved for [mapping]",
+                    DEFAULT_PROFILE
+                ));
+            }
+            mapping
+                .validate()
+                .map_err(|e| format!("[profiles.{}] {}", name, e))?;
+            if mapping.channels() != self.mapping.channels() {
+                return Err(format!(
+                    "[profiles.{}] has {} LEDs but [mapping] has {}",
+                    name,
+                    mapping.channels(),
+                    self.mapping.channels()
+                ));
+            }
+        }
+        self.daemon
+            .validate()
+            .map_err(|e| format!("[daemon] {}", e))?;
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate().map_err(|e| format!("[mqtt] {}", e))?;
+        }
+        self.shutdown
+            .validate()
+            .map_err(|e| format!("[shutdown] {}", e))?;
+        self.pwm.validate().map_err(|e| format!("[pwm] {}", e))?;
+        self.leds.validate().map_err(|e| format!("[leds] {}", e))?;
+        self.softpwm
+            .validate()
+            .map_err(|e| format!("[softpwm] {}", e))?;
+        if let Some(buttons) = &self.buttons {
+            buttons.validate().map_err(|e| format!("[buttons] {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The mapping profile called `name`, `default` being `[mapping]`.
+    pub fn profile(&self, name: &str) -> Option<&Mapping> {
+        if name == DEFAULT_PROFILE {
+            Some(&self.mapping)
+        } else {
+            self.profiles.get(name)
+        }
+    }
+
+    /// Names of every mapping profile, `default` first.
+    pub fn profile_names(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_PROFILE.to_string())
+            .chain(self.profiles.keys().cloned())
+            .collect()
+    }
+}
+
+// Extracts `--config PATH` / `--config=PATH`, rejecting anything else.
+fn config_arg<I>(args: I) -> Result<Option<PathBuf>, ConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let value = args
+                .next()
+                .ok_or_else(|| ConfigError::Usage("--config needs a path".into()))?;
+            path = Some(PathBuf::from(value));
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(PathBuf::from(value));
+        } else {
+            return Err(ConfigError::Usage(format!(
+                "unknown argument '{}' (usage: [--config PATH])",
+                arg
+            )));
+        }
+    }
+    Ok(path)
+}
@@ -0,0 +1,246 @@
+//! The control loop shared by both clients.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::backend::LedBackend;
+use crate::config::Config;
+use crate::error::{ControllerError, Result};
+use crate::fade::Fader;
+use crate::filter::FilterChain;
+use crate::frame::DutyFrame;
+use crate::mapping::Mapping;
+use crate::watch::{wait_for_change, AdaptiveInterval, SpeedNotifier, MIN_POLL_INTERVAL};
+
+/// Time to wait between two speed readings of the control loop.
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Longest the loop sleeps without checking whether it should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// One speed reading of the control loop, for callers that report it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// Raw speed, in presses/second.
+    pub speed: f64,
+    /// Speed after smoothing.
+    pub filtered_speed: f64,
+    /// Frame the LEDs fade towards.
+    pub target: DutyFrame,
+}
+
+/// Runs one iteration: reads the speed, maps it to duty cycles with
+/// `mapping` and writes them back. Returns the duties that were written.
+pub fn step<B: LedBackend + ?Sized>(backend: &mut B, mapping: &Mapping) -> Result<DutyFrame> {
+    // Read current button press speed
+    let speed = backend.read_speed()?;
+
+    // Map speed to LED duty cycles
+    let frame = mapping.map(speed);
+
+    // Update LED duty cycles
+    backend.write_duties(&frame)?;
+    Ok(frame)
+}
+
+// What the control loop keeps from one tick to the next: the filters, the
+// fader and the poll interval. It does no I/O, so the blocking loop, the
+// async one in `asynchronous` and the service's share it and only differ in
+// how they read, write and wait.
+pub(crate) struct ControlState {
+    mapping: Mapping,
+    filters: FilterChain,
+    fader: Fader,
+    update_interval: Duration,
+    polling: AdaptiveInterval,
+    last_speed: Option<f64>,
+    next_read: Instant,
+}
+
+impl ControlState {
+    // Fades from `initial`, what the LEDs show now if the backend can tell.
+    pub(crate) fn new(config: &Config, interval: Duration, initial: Option<DutyFrame>) -> Self {
+        let mut fader = Fader::new(config.fade.clone());
+        if let Some(frame) = initial {
+            fader.jump_to(frame);
+        }
+        ControlState {
+            mapping: config.mapping.clone(),
+            filters: FilterChain::new(&config.filter),
+            fader,
+            update_interval: config.fade.update_interval(),
+            polling: AdaptiveInterval::new(MIN_POLL_INTERVAL, interval),
+            last_speed: None,
+            next_read: Instant::now(),
+        }
+    }
+
+    // Filters and maps a speed read at `read_at`, fades towards the new
+    // target and works out when the next reading is due.
+    pub(crate) fn take_speed(&mut self, speed: f64, read_at: Instant) -> Reading {
+        let reading = self.observe(speed, read_at);
+        self.fader.retarget(reading.target.clone(), Instant::now());
+        reading
+    }
+
+    // Like `take_speed`, but leaves the fade alone, for when something
+    // other than the speed decides the duties.
+    pub(crate) fn observe(&mut self, speed: f64, read_at: Instant) -> Reading {
+        let filtered_speed = self.filters.smooth(speed);
+        let target = self.filters.map(&self.mapping, filtered_speed);
+        self.polling
+            .update(self.last_speed.is_some_and(|last| last != speed));
+        self.last_speed = Some(speed);
+        self.next_read = read_at + self.polling.current();
+        Reading {
+            speed,
+            filtered_speed,
+            target,
+        }
+    }
+
+    // Schedules the next reading after one at `read_at` that failed.
+    pub(crate) fn skip_read(&mut self, read_at: Instant) {
+        self.next_read = read_at + self.polling.current();
+    }
+
+    // Makes the next reading due at `now`, after the speed or the commands
+    // changed.
+    pub(crate) fn read_now(&mut self, now: Instant) {
+        self.next_read = now;
+    }
+
+    // Maps later readings with `mapping`.
+    pub(crate) fn set_mapping(&mut self, mapping: &Mapping) {
+        self.mapping = mapping.clone();
+    }
+
+    // Fades from where the LEDs are at `now` towards `target`.
+    pub(crate) fn retarget(&mut self, target: DutyFrame, now: Instant) {
+        self.fader.retarget(target, now);
+    }
+
+    // The frame the LEDs fade towards.
+    pub(crate) fn target(&self) -> Option<&DutyFrame> {
+        self.fader.target()
+    }
+
+    // The frame to write at `now`, if the fade moved on.
+    pub(crate) fn next_frame(&mut self, now: Instant) -> Option<DutyFrame> {
+        self.fader.next_frame(now)
+    }
+
+    // How long to wait after writing the frame for `now`: until the next
+    // fade step or the next reading, whichever comes first. `None` once the
+    // next reading is due.
+    pub(crate) fn wait(&self, now: Instant) -> Option<Duration> {
+        let remaining = self.until_read(now)?;
+        if self.fader.is_fading(now) {
+            Some(self.update_interval.min(remaining))
+        } else {
+            Some(remaining)
+        }
+    }
+
+    // How long until the next reading, ignoring the fade. `None` once it
+    // is due.
+    pub(crate) fn until_read(&self, now: Instant) -> Option<Duration> {
+        (now < self.next_read).then(|| self.next_read - now)
+    }
+}
+
+/// Runs the control loop with `config` until the backend fails, fading
+/// towards a new target after each speed reading.
+///
+/// The speed is read as soon as the backend signals a change, and at least
+/// every `interval`. Without signals it is polled, down to every
+/// `MIN_POLL_INTERVAL` while it keeps changing.
+pub fn run_every<B: LedBackend + ?Sized>(
+    backend: &mut B,
+    config: &Config,
+    interval: Duration,
+) -> Result<()> {
+    run_until_stopped(backend, config, interval, &AtomicBool::new(false))
+}
+
+/// Like `run_every`, but also returns `Ok` once `stop` is set. It is
+/// checked between two frames, so the LEDs are never left with half a
+/// frame written, and at least every 50 ms while the loop sleeps.
+pub fn run_until_stopped<B: LedBackend + ?Sized>(
+    backend: &mut B,
+    config: &Config,
+    interval: Duration,
+    stop: &AtomicBool,
+) -> Result<()> {
+    run_reporting(backend, config, interval, stop, |_| {})
+}
+
+/// Like `run_until_stopped`, but hands every speed reading to `report`
+/// before fading towards it, so the caller decides what to show of them.
+/// Errors end the loop as its `Err`; only a failing speed notifier is
+/// written to stderr, as the loop keeps going by polling instead.
+pub fn run_reporting<B, R>(
+    backend: &mut B,
+    config: &Config,
+    interval: Duration,
+    stop: &AtomicBool,
+    mut report: R,
+) -> Result<()>
+where
+    B: LedBackend + ?Sized,
+    R: FnMut(&Reading),
+{
+    check_channels(backend, &config.mapping)?;
+
+    // Fade from whatever the LEDs show now, if the backend can tell us
+    let initial = backend.read_duties().ok();
+    let mut state = ControlState::new(config, interval, initial);
+    let mut notifier = backend.speed_notifier();
+    while !stop.load(Ordering::SeqCst) {
+        let read_at = Instant::now();
+        let speed = backend.read_speed()?;
+        report(&state.take_speed(speed, read_at));
+        fade_until_next_read(backend, &mut state, &mut notifier, stop)?;
+    }
+    Ok(())
+}
+
+// Writes the fader's frames until the next reading is due, until `notifier`
+// signals a speed change or until `stop` is set.
+fn fade_until_next_read<B: LedBackend + ?Sized>(
+    backend: &mut B,
+    state: &mut ControlState,
+    notifier: &mut Option<Box<dyn SpeedNotifier>>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if let Some(frame) = state.next_frame(now) {
+            backend.write_duties(&frame)?;
+        }
+        let Some(timeout) = state.wait(now) else {
+            return Ok(());
+        };
+        if wait_for_change(notifier, timeout.min(STOP_CHECK_INTERVAL)) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Runs the control loop with the default `REFRESH_INTERVAL`.
+pub fn run<B: LedBackend + ?Sized>(backend: &mut B, config: &Config) -> Result<()> {
+    run_every(backend, config, REFRESH_INTERVAL)
+}
+
+/// Fails if `mapping` does not describe exactly the LEDs `backend` drives.
+pub fn check_channels<B: LedBackend + ?Sized>(backend: &B, mapping: &Mapping) -> Result<()> {
+    if mapping.channels() == backend.led_count() {
+        Ok(())
+    } else {
+        Err(ControllerError::ChannelMismatch {
+            expected: backend.led_count(),
+            found: mapping.channels(),
+        })
+    }
+}
@@ -0,0 +1,119 @@
+//! Response curves that correct duty cycles for perceived brightness.
+//!
+//! The eye is far more sensitive to changes at low brightness than at high
+//! brightness, so a linear duty ramp seems to jump at the bottom and plateau
+//! at the top. A curve is applied per LED after the speed mapping and maps
+//! 0-100% duty onto 0-100% duty.
+
+use serde::Deserialize;
+
+use crate::error::MAX_DUTY;
+
+/// Shape applied to an LED's duty cycle. Selected in the config file with
+/// e.g. `curve = { type = "gamma", exponent = 2.2 }`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ResponseCurve {
+    /// Duty passes through unchanged.
+    #[default]
+    Linear,
+    /// `out = in ^ exponent`; 2.2 is a common choice.
+    Gamma { exponent: f64 },
+    /// Logarithmic dimming curve, `out = (base ^ in - 1) / (base - 1)`, as
+    /// used by DALI ballasts. Larger bases stay dimmer for longer.
+    Logarithmic { base: f64 },
+    /// Treats the input as CIE 1931 lightness L* and outputs the matching
+    /// relative luminance.
+    Cie1931,
+    /// Logistic curve centred on `midpoint` (0.0-1.0), rescaled so 0 and
+    /// 100% stay fixed. Higher `steepness` gives a sharper S.
+    SCurve { steepness: f64, midpoint: f64 },
+    /// User-supplied table of output duties for inputs spread evenly from
+    /// 0 to 100%, interpolated linearly between entries.
+    Lut { table: Vec<u32> },
+}
+
+impl ResponseCurve {
+    /// Checks the curve parameters, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ResponseCurve::Linear | ResponseCurve::Cie1931 => Ok(()),
+            ResponseCurve::Gamma { exponent } => {
+                if exponent.is_finite() && *exponent > 0.0 {
+                    Ok(())
+                } else {
+                    Err(format!("gamma exponent {} must be above 0", exponent))
+                }
+            }
+            ResponseCurve::Logarithmic { base } => {
+                if base.is_finite() && *base > 1.0 {
+                    Ok(())
+                } else {
+                    Err(format!("logarithmic base {} must be above 1", base))
+                }
+            }
+            ResponseCurve::SCurve {
+                steepness,
+                midpoint,
+            } => {
+                if !steepness.is_finite() || *steepness <= 0.0 {
+                    Err(format!("s_curve steepness {} must be above 0", steepness))
+                } else if !(0.0..=1.0).contains(midpoint) {
+                    Err(format!("s_curve midpoint {} is outside 0.0-1.0", midpoint))
+                } else {
+                    Ok(())
+                }
+            }
+            ResponseCurve::Lut { table } => {
+                if table.len() < 2 {
+                    Err("lut table needs at least 2 entries".into())
+                } else if let Some(duty) = table.iter().find(|&&duty| duty > MAX_DUTY) {
+                    Err(format!("lut entry {} is above {}%", duty, MAX_DUTY))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Applies the curve to a duty cycle in percent.
+    pub fn apply(&self, duty: u32) -> u32 {
+        let duty = duty.min(MAX_DUTY);
+        let x = duty as f64 / MAX_DUTY as f64;
+        let y = match self {
+            ResponseCurve::Linear => return duty,
+            ResponseCurve::Gamma { exponent } => x.powf(*exponent),
+            ResponseCurve::Logarithmic { base } => (base.powf(x) - 1.0) / (base - 1.0),
+            ResponseCurve::Cie1931 => cie1931(x * 100.0),
+            ResponseCurve::SCurve {
+                steepness,
+                midpoint,
+            } => {
+                let logistic = |x: f64| 1.0 / (1.0 + (-steepness * (x - midpoint)).exp());
+                let (low, high) = (logistic(0.0), logistic(1.0));
+                (logistic(x) - low) / (high - low)
+            }
+            ResponseCurve::Lut { table } => return lookup(table, x),
+        };
+        (y * MAX_DUTY as f64).round().clamp(0.0, MAX_DUTY as f64) as u32
+    }
+}
+
+// Relative luminance (0.0-1.0) for CIE 1931 lightness L* (0-100).
+fn cie1931(lightness: f64) -> f64 {
+    if lightness <= 8.0 {
+        lightness / 903.3
+    } else {
+        ((lightness + 16.0) / 116.0).powi(3)
+    }
+}
+
+// Interpolates between the evenly spaced table entries around `x`.
+fn lookup(table: &[u32], x: f64) -> u32 {
+    let last = table.len() - 1;
+    let scaled = x * last as f64;
+    let index = (scaled.floor() as usize).min(last - 1);
+    let fraction = scaled - index as f64;
+    let (a, b) = (table[index] as f64, table[index + 1] as f64);
+    (a + (b - a) * fraction).round() as u32
+}
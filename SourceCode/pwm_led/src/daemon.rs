@@ -0,0 +1,326 @@
+//! The `pwmledd` socket protocol: one JSON request per line on a Unix
+//! domain socket, each answered by one JSON response line. Comes with the
+//! server side and a blocking client.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::control::REFRESH_INTERVAL;
+use crate::frame::DutyFrame;
+use crate::service::{Service, Status};
+
+/// Default socket the daemon listens on.
+pub const SOCKET_PATH: &str = "/run/pwmledd.sock";
+
+/// `[daemon]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub socket: PathBuf,
+    /// Longest time between two speed readings. The speed is read as soon
+    /// as the module signals a change, and polled more often while it keeps
+    /// changing.
+    pub poll_interval_ms: u64,
+    /// Address of the HTTP API, off when unset. Needs the `http` feature.
+    pub http: Option<SocketAddr>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            socket: PathBuf::from(SOCKET_PATH),
+            poll_interval_ms: REFRESH_INTERVAL.as_millis() as u64,
+            http: None,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.poll_interval_ms == 0 {
+            return Err("poll_interval_ms must be above 0".into());
+        }
+        Ok(())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+/// One command, e.g. `{"cmd":"set_profile","profile":"night"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    SetOverride { duties: DutyFrame },
+    ClearOverride,
+    SetProfile { profile: String },
+    Pause,
+    Resume,
+}
+
+/// Answer to one request: the status after the command, or why it failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    fn status(status: Status) -> Self {
+        Response {
+            ok: true,
+            status: Some(status),
+            error: None,
+        }
+    }
+
+    fn error(error: impl fmt::Display) -> Self {
+        Response {
+            ok: false,
+            status: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Runs `request` against `service`.
+pub fn handle(service: &Service, request: Request) -> Response {
+    let result = match request {
+        Request::Status => Ok(service.status()),
+        Request::SetOverride { duties } => service.set_override(duties),
+        Request::ClearOverride => Ok(service.clear_override()),
+        Request::SetProfile { profile } => service.set_profile(&profile),
+        Request::Pause => Ok(service.pause()),
+        Request::Resume => Ok(service.resume()),
+    };
+    match result {
+        Ok(status) => Response::status(status),
+        Err(err) => Response::error(err),
+    }
+}
+
+/// Accepts clients on a Unix socket, one thread per connection.
+#[derive(Debug)]
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    service: Service,
+}
+
+impl Server {
+    /// Listens on `path`. A socket file left behind by a daemon that is no
+    /// longer running is replaced; a live one is an `AddrInUse` error.
+    pub fn bind(path: &Path, service: Service) -> io::Result<Server> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use by another daemon", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        Ok(Server {
+            listener,
+            path: path.to_path_buf(),
+            service,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serves clients until the socket itself fails. A client that cannot
+    /// be accepted is logged and skipped.
+    pub fn serve(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let Some(stream) = next_client(stream)? else {
+                continue;
+            };
+            let service = self.service.clone();
+            thread::spawn(move || {
+                if let Err(err) = serve_client(stream, &service) {
+                    eprintln!("Client error: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+// Pause after a failed accept, so running out of descriptors does not turn
+// the accept loop into a busy loop.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+// Passes on an accepted client. Errors that only concern one connection or
+// a passing lack of resources (EMFILE, ECONNABORTED, ...) are logged and
+// give `None`; only those meaning the listening socket is unusable are
+// returned.
+pub(crate) fn next_client<S>(accepted: io::Result<S>) -> io::Result<Option<S>> {
+    match accepted {
+        Ok(stream) => Ok(Some(stream)),
+        Err(err)
+            if matches!(
+                err.raw_os_error(),
+                Some(libc::EBADF | libc::EINVAL | libc::ENOTSOCK | libc::EOPNOTSUPP)
+            ) =>
+        {
+            Err(err)
+        }
+        Err(err) => {
+            eprintln!("Error: cannot accept a client: {}", err);
+            thread::sleep(ACCEPT_RETRY);
+            Ok(None)
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Answers requests from one client until it hangs up.
+fn serve_client(stream: UnixStream, service: &Service) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => handle(service, request),
+            Err(err) => Response::error(format!("bad request: {}", err)),
+        };
+        let mut text = serde_json::to_string(&response)?;
+        text.push('\n');
+        writer.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Errors seen by a `Client`.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The socket could not be reached or the connection broke.
+    Io(io::Error),
+    /// The daemon sent something that is not a response.
+    Protocol(serde_json::Error),
+    /// The daemon refused the command.
+    Daemon(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "daemon connection: {}", err),
+            ClientError::Protocol(err) => write!(f, "bad response from daemon: {}", err),
+            ClientError::Daemon(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(err) => Some(err),
+            ClientError::Protocol(err) => Some(err),
+            ClientError::Daemon(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ClientError::Protocol(err)
+    }
+}
+
+/// Blocking connection to a running daemon.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Client, ClientError> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client { reader, writer })
+    }
+
+    /// Sends `request` and waits for the answer.
+    pub fn request(&mut self, request: &Request) -> Result<Response, ClientError> {
+        let mut text = serde_json::to_string(request)?;
+        text.push('\n');
+        self.writer.write_all(text.as_bytes())?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    pub fn status(&mut self) -> Result<Status, ClientError> {
+        self.command(Request::Status)
+    }
+
+    pub fn set_override(&mut self, duties: impl Into<DutyFrame>) -> Result<Status, ClientError> {
+        self.command(Request::SetOverride {
+            duties: duties.into(),
+        })
+    }
+
+    pub fn clear_override(&mut self) -> Result<Status, ClientError> {
+        self.command(Request::ClearOverride)
+    }
+
+    pub fn set_profile(&mut self, profile: &str) -> Result<Status, ClientError> {
+        self.command(Request::SetProfile {
+            profile: profile.to_string(),
+        })
+    }
+
+    pub fn pause(&mut self) -> Result<Status, ClientError> {
+        self.command(Request::Pause)
+    }
+
+    pub fn resume(&mut self) -> Result<Status, ClientError> {
+        self.command(Request::Resume)
+    }
+
+    // Sends a command and unwraps the status or the daemon's error.
+    fn command(&mut self, request: Request) -> Result<Status, ClientError> {
+        let response = self.request(&request)?;
+        match (response.ok, response.status, response.error) {
+            (true, Some(status), _) => Ok(status),
+            (_, _, Some(error)) => Err(ClientError::Daemon(error)),
+            _ => Err(ClientError::Daemon("response without status".into())),
+        }
+    }
+}
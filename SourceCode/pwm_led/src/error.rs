@@ -0,0 +1,136 @@
+//! Errors reported by the backends and the control loop.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `EINVAL`, returned by the kernel module when it refuses a duty write.
+const EINVAL: i32 = 22;
+
+/// Highest duty cycle the kernel module accepts.
+pub const MAX_DUTY: u32 = 100;
+
+/// Everything that can go wrong talking to the LED controller.
+#[derive(Debug)]
+pub enum ControllerError {
+    /// The device node or sysfs attribute does not exist, usually because
+    /// the kernel module is not loaded.
+    DeviceMissing { path: PathBuf },
+    /// The interface exists but we are not allowed to open it.
+    PermissionDenied { path: PathBuf },
+    /// The interface answered with something we could not parse.
+    MalformedResponse { path: PathBuf, response: String },
+    /// A duty cycle above `MAX_DUTY` was about to be written.
+    DutyOutOfRange { led: usize, duty: u32 },
+    /// An LED index the controller does not have.
+    LedOutOfRange { led: usize, count: usize },
+    /// A duty frame with a different number of channels than the backend.
+    ChannelMismatch { expected: usize, found: usize },
+    /// The kernel module refused a write with `EINVAL`.
+    KernelRejected { path: PathBuf },
+    /// A mapping profile that is not in the config.
+    UnknownProfile(String),
+    /// The backend cannot do this operation.
+    Unsupported(&'static str),
+    /// The control loop did not stop and apply the shutdown policy in time,
+    /// usually because a write to the device hung.
+    ShutdownTimedOut { timeout: Duration },
+    /// Any other I/O failure.
+    Io { path: PathBuf, source: io::Error },
+}
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, ControllerError>;
+
+impl ControllerError {
+    /// Classifies an I/O error that happened while accessing `path`.
+    pub fn from_io(path: impl AsRef<Path>, err: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        if err.raw_os_error() == Some(EINVAL) {
+            return ControllerError::KernelRejected { path };
+        }
+        match err.kind() {
+            ErrorKind::NotFound => ControllerError::DeviceMissing { path },
+            ErrorKind::PermissionDenied => ControllerError::PermissionDenied { path },
+            _ => ControllerError::Io { path, source: err },
+        }
+    }
+
+    /// Builds a `MalformedResponse` for the text read from `path`.
+    pub fn malformed(path: impl AsRef<Path>, response: &str) -> Self {
+        ControllerError::MalformedResponse {
+            path: path.as_ref().to_path_buf(),
+            response: response.trim_end().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ControllerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerError::DeviceMissing { path } => write!(
+                f,
+                "{} does not exist (is the pwm_led_controller module loaded?)",
+                path.display()
+            ),
+            ControllerError::PermissionDenied { path } => {
+                write!(f, "permission denied opening {} (try sudo)", path.display())
+            }
+            ControllerError::MalformedResponse { path, response } => {
+                write!(
+                    f,
+                    "unexpected response from {}: {:?}",
+                    path.display(),
+                    response
+                )
+            }
+            ControllerError::DutyOutOfRange { led, duty } => write!(
+                f,
+                "duty cycle {}% for LED{} is out of range (0-{})",
+                duty,
+                led + 1,
+                MAX_DUTY
+            ),
+            ControllerError::LedOutOfRange { led, count } => {
+                write!(
+                    f,
+                    "LED index {} out of range (0-{})",
+                    led,
+                    count.saturating_sub(1)
+                )
+            }
+            ControllerError::ChannelMismatch { expected, found } => write!(
+                f,
+                "duty frame has {} channels but the backend drives {}",
+                found, expected
+            ),
+            ControllerError::KernelRejected { path } => {
+                write!(
+                    f,
+                    "kernel module rejected the write to {} (EINVAL)",
+                    path.display()
+                )
+            }
+            ControllerError::UnknownProfile(name) => {
+                write!(f, "no mapping profile named '{}'", name)
+            }
+            ControllerError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            ControllerError::ShutdownTimedOut { timeout } => write!(
+                f,
+                "the LEDs were not settled within {} ms of shutting down",
+                timeout.as_millis()
+            ),
+            ControllerError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for ControllerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ControllerError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! The kernel module's button speed measurement, from `button1_handler` and
+//! `button2_handler`, as a plain type that can be fed and tested anywhere.
+//!
+//! Only alternating presses count: the time from a press of one button to
+//! the next press of the other is one interval, and the speed is one second
+//! over the average interval. A press of the same button again just moves
+//! the start of the next interval.
+//!
+//! The module's comment promises an average "over the last 10 seconds", but
+//! its running average covers every interval since it loaded, rescaled to
+//! weigh as 20 samples once it passes 100. `SpeedEstimator::new` keeps that
+//! behaviour; `SpeedEstimator::windowed` averages the intervals of a time
+//! window instead.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::speed::speed_from_interval_ns;
+
+// Samples the running average is scaled back to, and the count that
+// triggers it, as in the module.
+const RESCALED_SAMPLES: u64 = 20;
+const RESCALE_AFTER: u64 = 100;
+
+#[derive(Debug, Clone)]
+enum Averaging {
+    Running {
+        samples: u64,
+        total_ns: u64,
+    },
+    // Each interval with the timestamp of the press that ended it
+    Window {
+        window_ns: u64,
+        intervals: VecDeque<(u64, u64)>,
+    },
+}
+
+/// Button press speed from `(button, timestamp)` events.
+#[derive(Debug, Clone)]
+pub struct SpeedEstimator {
+    averaging: Averaging,
+    last_button: Option<u32>,
+    last_press_ns: u64,
+    presses: u64,
+    average_ns: u64,
+}
+
+impl Default for SpeedEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpeedEstimator {
+    /// The module's running average, truncated to whole nanoseconds at each
+    /// step like its `do_div`.
+    pub fn new() -> Self {
+        Self::with_averaging(Averaging::Running {
+            samples: 0,
+            total_ns: 0,
+        })
+    }
+
+    /// The average of the intervals that ended within `window` of the
+    /// latest press, or of the time passed to `expire`.
+    pub fn windowed(window: Duration) -> Self {
+        Self::with_averaging(Averaging::Window {
+            window_ns: window.as_nanos().min(u128::from(u64::MAX)) as u64,
+            intervals: VecDeque::new(),
+        })
+    }
+
+    fn with_averaging(averaging: Averaging) -> Self {
+        SpeedEstimator {
+            averaging,
+            last_button: None,
+            last_press_ns: 0,
+            presses: 0,
+            average_ns: 0,
+        }
+    }
+
+    /// Counts a press of `button` at `timestamp_ns`, on a clock that never
+    /// goes back. Returns whether it alternated with the previous press and
+    /// so updated the average.
+    pub fn press(&mut self, button: u32, timestamp_ns: u64) -> bool {
+        let alternating = self.last_button.is_some_and(|last| last != button);
+        if alternating {
+            let interval_ns = timestamp_ns.saturating_sub(self.last_press_ns);
+            self.add_interval(interval_ns, timestamp_ns);
+        }
+        self.last_button = Some(button);
+        self.last_press_ns = timestamp_ns;
+        self.presses += 1;
+        alternating
+    }
+
+    fn add_interval(&mut self, interval_ns: u64, timestamp_ns: u64) {
+        match &mut self.averaging {
+            Averaging::Running { samples, total_ns } => {
+                *total_ns = total_ns.wrapping_add(interval_ns);
+                *samples += 1;
+                self.average_ns = *total_ns / *samples;
+                *total_ns = self.average_ns * *samples;
+                // Rescaled before the total can overflow
+                if *samples > RESCALE_AFTER {
+                    *total_ns = self.average_ns * RESCALED_SAMPLES;
+                    *samples = RESCALED_SAMPLES;
+                }
+            }
+            Averaging::Window { intervals, .. } => {
+                intervals.push_back((timestamp_ns, interval_ns));
+                self.expire(timestamp_ns);
+            }
+        }
+    }
+
+    /// Drops the intervals that ended more than the window before `now_ns`,
+    /// so the speed falls back to 0 once the buttons rest for a whole
+    /// window. Does nothing to the module's running average, which holds
+    /// its last value.
+    pub fn expire(&mut self, now_ns: u64) {
+        if let Averaging::Window {
+            window_ns,
+            intervals,
+        } = &mut self.averaging
+        {
+            let oldest = now_ns.saturating_sub(*window_ns);
+            while intervals.front().is_some_and(|&(at, _)| at < oldest) {
+                intervals.pop_front();
+            }
+            let total: u128 = intervals.iter().map(|&(_, ns)| u128::from(ns)).sum();
+            self.average_ns = match intervals.len() {
+                0 => 0,
+                n => (total / n as u128) as u64,
+            };
+        }
+    }
+
+    /// Average interval between alternating presses in nanoseconds, like
+    /// the module's `press_interval_ns`; 0 until there is one.
+    pub fn press_interval_ns(&self) -> u64 {
+        self.average_ns
+    }
+
+    /// Speed in presses/second, 0 until two alternating presses.
+    pub fn speed(&self) -> f64 {
+        speed_from_interval_ns(self.average_ns)
+    }
+
+    /// Every press counted so far, alternating or not.
+    pub fn presses(&self) -> u64 {
+        self.presses
+    }
+}
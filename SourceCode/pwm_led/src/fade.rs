@@ -0,0 +1,177 @@
+//! Smooth transitions between duty frames.
+//!
+//! Instead of jumping to each new target, the control loop asks a `Fader`
+//! for intermediate frames at the configured update rate. A new target that
+//! arrives mid-fade cancels the running fade and starts the next one from
+//! wherever the LEDs are at that moment.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::error::MAX_DUTY;
+use crate::frame::DutyFrame;
+
+/// How the fade progresses over its duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    /// Constant rate of change.
+    #[default]
+    Linear,
+    /// Starts slow, ends fast.
+    EaseIn,
+    /// Starts fast, ends slow.
+    EaseOut,
+    /// Slow at both ends.
+    EaseInOut,
+    /// Grows like `2^(10t)`: the change made so far, plus 1/1023 of the
+    /// whole, doubles every tenth of the fade, which looks even to the eye
+    /// on LEDs.
+    Exponential,
+}
+
+impl Easing {
+    /// Progress (0.0-1.0) after fraction `t` (0.0-1.0) of the fade.
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Exponential => (2f64.powf(10.0 * t) - 1.0) / 1023.0,
+        }
+    }
+}
+
+/// `[fade]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FadeConfig {
+    /// Time to reach a new target, 0 to jump straight to it.
+    pub duration_ms: u64,
+    /// Time between two intermediate frames.
+    pub update_interval_ms: u64,
+    pub easing: Easing,
+}
+
+impl Default for FadeConfig {
+    fn default() -> Self {
+        FadeConfig {
+            duration_ms: 0,
+            update_interval_ms: 20,
+            easing: Easing::Linear,
+        }
+    }
+}
+
+impl FadeConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.update_interval_ms == 0 {
+            return Err("update_interval_ms must be above 0".into());
+        }
+        Ok(())
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_millis(self.update_interval_ms)
+    }
+}
+
+/// Interpolates from the current frame to the latest target over time.
+#[derive(Debug)]
+pub struct Fader {
+    config: FadeConfig,
+    from: Option<DutyFrame>,
+    to: Option<DutyFrame>,
+    started: Instant,
+    last_emitted: Option<DutyFrame>,
+    force_emit: bool,
+}
+
+impl Fader {
+    pub fn new(config: FadeConfig) -> Self {
+        Fader {
+            config,
+            from: None,
+            to: None,
+            started: Instant::now(),
+            last_emitted: None,
+            force_emit: false,
+        }
+    }
+
+    /// Records that the LEDs are already at `frame`, e.g. the duties read
+    /// back from the backend at startup, so the first fade starts there.
+    pub fn jump_to(&mut self, frame: DutyFrame) {
+        self.from = Some(frame.clone());
+        self.to = Some(frame.clone());
+        self.last_emitted = Some(frame);
+        self.force_emit = false;
+    }
+
+    /// Starts a fade to `target`, cancelling any fade in progress. The new
+    /// fade starts from the frame the old one had reached at `now`.
+    pub fn retarget(&mut self, target: DutyFrame, now: Instant) {
+        let current = self.sample(now);
+        self.from = match current {
+            Some(frame) if frame.channels() == target.channels() => Some(frame),
+            _ => Some(target.clone()),
+        };
+        self.to = Some(target);
+        self.started = now;
+        self.force_emit = true;
+    }
+
+    /// The latest target, if any.
+    pub fn target(&self) -> Option<&DutyFrame> {
+        self.to.as_ref()
+    }
+
+    /// Whether the fade is still moving at `now`.
+    pub fn is_fading(&self, now: Instant) -> bool {
+        self.from != self.to && now.duration_since(self.started) < self.config.duration()
+    }
+
+    /// Interpolated frame at `now`, or `None` before the first target.
+    pub fn sample(&self, now: Instant) -> Option<DutyFrame> {
+        let (from, to) = (self.from.as_ref()?, self.to.as_ref()?);
+        let duration = self.config.duration();
+        let elapsed = now.saturating_duration_since(self.started);
+        if duration.is_zero() || elapsed >= duration {
+            return Some(to.clone());
+        }
+
+        let progress = self
+            .config
+            .easing
+            .apply(elapsed.as_secs_f64() / duration.as_secs_f64());
+        let frame = from
+            .iter()
+            .zip(to.iter())
+            .map(|(&a, &b)| {
+                let value = a as f64 + (b as f64 - a as f64) * progress;
+                (value.round() as u32).min(MAX_DUTY)
+            })
+            .collect();
+        Some(frame)
+    }
+
+    /// Frame to write at `now`: `None` when nothing changed since the last
+    /// emitted frame. The first frame after a new target is always emitted.
+    pub fn next_frame(&mut self, now: Instant) -> Option<DutyFrame> {
+        let frame = self.sample(now)?;
+        if !self.force_emit && self.last_emitted.as_ref() == Some(&frame) {
+            return None;
+        }
+        self.force_emit = false;
+        self.last_emitted = Some(frame.clone());
+        Some(frame)
+    }
+}
@@ -0,0 +1,709 @@
+//! Backends that run without the kernel module, for tests and demos.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::{check_frame, check_led, LedBackend, DEFAULT_LED_COUNT};
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+use crate::gpio::{LineEvent, LineEventSource, LineSink};
+use crate::mqtt::{topic_matches, Packet, Will};
+use crate::sysfs::{SysfsBackend, INTERVAL_ATTRIBUTE};
+use crate::watch::SpeedNotifier;
+
+/// In-memory backend that replays a scripted speed sequence and records
+/// every duty update.
+///
+/// Once the script is used up `read_speed` fails with an `Io` error of kind
+/// `UnexpectedEof`, which ends the control loop.
+#[derive(Debug)]
+pub struct MockBackend {
+    speeds: VecDeque<f64>,
+    frame: DutyFrame,
+    writes: Vec<DutyFrame>,
+    notifier: Option<Receiver<()>>,
+}
+
+impl MockBackend {
+    /// Creates a three-channel mock that will report the given speeds in
+    /// order.
+    pub fn new(speeds: impl IntoIterator<Item = f64>) -> Self {
+        Self::with_channels(DEFAULT_LED_COUNT, speeds)
+    }
+
+    /// Creates a mock with `channels` LEDs.
+    pub fn with_channels(channels: usize, speeds: impl IntoIterator<Item = f64>) -> Self {
+        MockBackend {
+            speeds: speeds.into_iter().collect(),
+            frame: DutyFrame::off(channels),
+            writes: Vec::new(),
+            notifier: None,
+        }
+    }
+
+    /// Makes the mock signal speed changes like sysfs does: each `()` sent
+    /// on the returned sender wakes whoever waits on its `speed_notifier`.
+    pub fn notify_with(&mut self) -> Sender<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.notifier = Some(receiver);
+        sender
+    }
+
+    /// Appends speeds to the script.
+    pub fn push_speeds(&mut self, speeds: impl IntoIterator<Item = f64>) {
+        self.speeds.extend(speeds);
+    }
+
+    /// Current duty cycles.
+    pub fn duties(&self) -> &DutyFrame {
+        &self.frame
+    }
+
+    /// Every duty frame written so far, oldest first. Single-LED writes are
+    /// recorded as the full frame they produced.
+    pub fn writes(&self) -> &[DutyFrame] {
+        &self.writes
+    }
+}
+
+impl LedBackend for MockBackend {
+    fn led_count(&self) -> usize {
+        self.frame.channels()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.speeds.pop_front().ok_or_else(|| ControllerError::Io {
+            path: "<mock>".into(),
+            source: io::Error::new(ErrorKind::UnexpectedEof, "speed script exhausted"),
+        })
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.led_count())?;
+        Ok(self.frame[led])
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.led_count())?;
+        let mut frame = self.frame.clone();
+        frame[led] = duty;
+        self.write_duties(&frame)
+    }
+
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.led_count())?;
+        self.frame = frame.clone();
+        self.writes.push(frame.clone());
+        Ok(())
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        let receiver = self.notifier.take()?;
+        Some(Box::new(receiver))
+    }
+}
+
+/// A `SysfsBackend` pointed at a temporary directory laid out like
+/// `/sys/kernel/pwm_led_controller`. The directory is removed on drop.
+#[derive(Debug)]
+pub struct FakeSysfsBackend {
+    dir: PathBuf,
+    inner: SysfsBackend,
+}
+
+impl FakeSysfsBackend {
+    /// Creates the directory with `button_speed` at 0 and three
+    /// `ledN_duty` attributes at 0.
+    pub fn new() -> io::Result<Self> {
+        Self::with_channels(DEFAULT_LED_COUNT)
+    }
+
+    /// Creates the directory with `channels` duty attributes.
+    pub fn with_channels(channels: usize) -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "pwm_led_fake_sysfs-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+
+        fs::write(dir.join("button_speed"), "0\n")?;
+        for led in 0..channels {
+            fs::write(dir.join(format!("led{}_duty", led + 1)), "0\n")?;
+        }
+        let inner = SysfsBackend::with_path(&dir)
+            .map_err(|e| io::Error::new(ErrorKind::NotFound, e.to_string()))?;
+        Ok(FakeSysfsBackend { dir, inner })
+    }
+
+    /// Directory holding the fake attributes.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sets the speed the next `read_speed` will see.
+    pub fn set_speed(&self, speed: f64) -> io::Result<()> {
+        fs::write(self.dir.join("button_speed"), format!("{}\n", speed))
+    }
+
+    /// Creates, updates or (with `None`) removes the `press_interval_ns`
+    /// attribute newer modules export.
+    pub fn set_interval_ns(&self, interval_ns: Option<u64>) -> io::Result<()> {
+        let path = self.dir.join(INTERVAL_ATTRIBUTE);
+        match interval_ns {
+            Some(interval_ns) => fs::write(path, format!("{}\n", interval_ns)),
+            None => match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Reads back the duty cycles currently stored in the attribute files.
+    pub fn duties(&self) -> io::Result<DutyFrame> {
+        let mut duties = DutyFrame::off(self.inner.led_count());
+        for (led, duty) in duties.iter_mut().enumerate() {
+            let text = fs::read_to_string(self.dir.join(format!("led{}_duty", led + 1)))?;
+            *duty = text.trim().parse().map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("bad led{}_duty: {:?}", led + 1, text),
+                )
+            })?;
+        }
+        Ok(duties)
+    }
+}
+
+impl LedBackend for FakeSysfsBackend {
+    fn led_count(&self) -> usize {
+        self.inner.led_count()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.inner.read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        self.inner.read_duty(led)
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        self.inner.write_duty(led, duty)
+    }
+
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        self.inner.write_duties(frame)
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        self.inner.speed_notifier()
+    }
+}
+
+impl Drop for FakeSysfsBackend {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A temporary directory laid out like `/sys/class/pwm/pwmchipN`, removed
+/// on drop. Writing `export` does nothing by itself; `export` creates the
+/// channel directory the way the kernel would.
+#[derive(Debug)]
+pub struct FakePwmChip {
+    dir: PathBuf,
+}
+
+impl FakePwmChip {
+    /// Creates a chip with `npwm` channels, none of them exported.
+    pub fn new(npwm: u32) -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "pwm_led_fake_pwmchip-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("npwm"), format!("{}\n", npwm))?;
+        fs::write(dir.join("export"), "")?;
+        fs::write(dir.join("unexport"), "")?;
+        Ok(FakePwmChip { dir })
+    }
+
+    /// The chip's directory.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Creates `pwmN` disabled, with a period and duty cycle of 0.
+    pub fn export(&self, channel: u32) -> io::Result<()> {
+        let dir = self.dir.join(format!("pwm{}", channel));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("period"), "0\n")?;
+        fs::write(dir.join("duty_cycle"), "0\n")?;
+        fs::write(dir.join("enable"), "0\n")?;
+        fs::write(dir.join("polarity"), "normal\n")
+    }
+
+    /// What was last written to `export`, if anything.
+    pub fn export_request(&self) -> io::Result<Option<u32>> {
+        let text = fs::read_to_string(self.dir.join("export"))?;
+        Ok(text.trim().parse().ok())
+    }
+
+    /// Reads a numeric attribute of channel `channel`, such as `period`,
+    /// `duty_cycle` or `enable`.
+    pub fn attribute(&self, channel: u32, name: &str) -> io::Result<u64> {
+        let path = self.dir.join(format!("pwm{}", channel)).join(name);
+        let text = fs::read_to_string(&path)?;
+        text.trim().parse().map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("bad {}: {:?}", path.display(), text),
+            )
+        })
+    }
+}
+
+impl Drop for FakePwmChip {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A temporary directory laid out like `/sys/class/leds`, removed on drop.
+/// Unlike the kernel, writing `trigger` stores the text as is.
+#[derive(Debug)]
+pub struct FakeLedClass {
+    dir: PathBuf,
+}
+
+impl FakeLedClass {
+    /// Creates an empty directory; add LEDs with `add`.
+    pub fn new() -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "pwm_led_fake_leds-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(FakeLedClass { dir })
+    }
+
+    /// The directory holding the LEDs.
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Adds an LED at brightness 0, with `trigger` active among the usual
+    /// choices.
+    pub fn add(&self, name: &str, max_brightness: u32, trigger: &str) -> io::Result<()> {
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("brightness"), "0\n")?;
+        fs::write(dir.join("max_brightness"), format!("{}\n", max_brightness))?;
+        let mut triggers = vec!["none", "timer", "heartbeat"];
+        if !triggers.contains(&trigger) {
+            triggers.push(trigger);
+        }
+        let triggers: Vec<String> = triggers
+            .into_iter()
+            .map(|name| {
+                if name == trigger {
+                    format!("[{}]", name)
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect();
+        fs::write(dir.join("trigger"), triggers.join(" ") + "\n")
+    }
+
+    /// Current `brightness` of an LED.
+    pub fn brightness(&self, name: &str) -> io::Result<u32> {
+        let text = fs::read_to_string(self.dir.join(name).join("brightness"))?;
+        text.trim().parse().map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("bad brightness of {}: {:?}", name, text),
+            )
+        })
+    }
+
+    /// Contents of an LED's `trigger` attribute.
+    pub fn trigger(&self, name: &str) -> io::Result<String> {
+        Ok(fs::read_to_string(self.dir.join(name).join("trigger"))?
+            .trim()
+            .to_string())
+    }
+}
+
+impl Drop for FakeLedClass {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Line events pushed by hand, standing in for GPIO lines requested from a
+/// chip. Clones share the events, so a test can keep one to push presses
+/// while a `ButtonSpeed` reads another.
+#[derive(Debug, Clone, Default)]
+pub struct MockLineEvents {
+    events: Arc<Mutex<VecDeque<LineEvent>>>,
+    notifier: Arc<Mutex<Option<Sender<()>>>>,
+}
+
+impl MockLineEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a rising edge on `offset`, as a button press.
+    pub fn press(&self, offset: u32, timestamp_ns: u64) {
+        self.push(LineEvent::rising(offset, timestamp_ns));
+    }
+
+    /// Queues an event and wakes whoever waits on the `event_notifier`.
+    pub fn push(&self, event: LineEvent) {
+        self.events.lock().unwrap().push_back(event);
+        if let Some(sender) = &*self.notifier.lock().unwrap() {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Events queued and not read yet.
+    pub fn pending(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+}
+
+impl LineEventSource for MockLineEvents {
+    fn read_events(&mut self) -> Result<Vec<LineEvent>> {
+        Ok(self.events.lock().unwrap().drain(..).collect())
+    }
+
+    fn event_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        let (sender, receiver) = mpsc::channel();
+        *self.notifier.lock().unwrap() = Some(sender);
+        Some(Box::new(receiver))
+    }
+}
+
+/// One `set_values` call seen by a `RecordingLineSink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineChange {
+    pub at: Instant,
+    pub values: u64,
+    pub mask: u64,
+}
+
+/// Output lines that record every change instead of driving pins. Clones
+/// share the record, so a test can keep one while a `SoftPwm` owns another.
+#[derive(Debug, Clone)]
+pub struct RecordingLineSink {
+    lines: usize,
+    changes: Arc<Mutex<Vec<LineChange>>>,
+}
+
+impl RecordingLineSink {
+    pub fn new(lines: usize) -> Self {
+        RecordingLineSink {
+            lines,
+            changes: Arc::default(),
+        }
+    }
+
+    /// Every change so far, oldest first.
+    pub fn changes(&self) -> Vec<LineChange> {
+        self.changes.lock().unwrap().clone()
+    }
+
+    /// Current level of every line, bit `i` being line `i`.
+    pub fn levels(&self) -> u64 {
+        levels_after(&self.changes.lock().unwrap())
+    }
+
+    /// How long `line` was high between `from` and `to`.
+    pub fn high_time(&self, line: usize, from: Instant, to: Instant) -> Duration {
+        let changes = self.changes.lock().unwrap();
+        let before = changes.partition_point(|change| change.at <= from);
+        let mut high = levels_after(&changes[..before]) >> line & 1 == 1;
+        let mut since = from;
+        let mut total = Duration::ZERO;
+        for change in changes[before..].iter().take_while(|change| change.at < to) {
+            if change.mask >> line & 1 == 1 {
+                if high {
+                    total += change.at - since;
+                }
+                high = change.values >> line & 1 == 1;
+                since = change.at;
+            }
+        }
+        if high {
+            total += to - since;
+        }
+        total
+    }
+
+    /// Times `line` went from low to high.
+    pub fn rising_edges(&self, line: usize) -> usize {
+        let mut high = false;
+        let mut edges = 0;
+        for change in self.changes.lock().unwrap().iter() {
+            if change.mask >> line & 1 == 1 {
+                let now_high = change.values >> line & 1 == 1;
+                edges += usize::from(now_high && !high);
+                high = now_high;
+            }
+        }
+        edges
+    }
+}
+
+fn levels_after(changes: &[LineChange]) -> u64 {
+    changes.iter().fold(0, |levels, change| {
+        levels & !change.mask | change.values & change.mask
+    })
+}
+
+impl LineSink for RecordingLineSink {
+    fn lines(&self) -> usize {
+        self.lines
+    }
+
+    fn set_values(&mut self, values: u64, mask: u64) -> Result<()> {
+        self.changes.lock().unwrap().push(LineChange {
+            at: Instant::now(),
+            values,
+            mask,
+        });
+        Ok(())
+    }
+}
+
+/// In-process stand-in for an MQTT broker such as Mosquitto, listening on
+/// a free localhost port until dropped. Handles what `MqttBridge` uses:
+/// QoS 0 publishes, retained messages, wills and `+`/`#` subscriptions.
+#[derive(Debug)]
+pub struct FakeBroker {
+    addr: SocketAddr,
+    state: Arc<Mutex<BrokerState>>,
+}
+
+#[derive(Debug, Default)]
+struct BrokerState {
+    clients: Vec<BrokerClient>,
+    retained: BTreeMap<String, Vec<u8>>,
+    log: Vec<(String, Vec<u8>)>,
+    connects: usize,
+    next_id: usize,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct BrokerClient {
+    id: usize,
+    stream: TcpStream,
+    filters: Vec<String>,
+}
+
+impl FakeBroker {
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if shared.lock().unwrap().closed {
+                    return;
+                }
+                if let Ok(stream) = stream {
+                    let shared = Arc::clone(&shared);
+                    thread::spawn(move || serve_mqtt_client(stream, &shared));
+                }
+            }
+        });
+        Ok(FakeBroker { addr, state })
+    }
+
+    /// Address to give the client as `broker`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of CONNECTs accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connects
+    }
+
+    /// The retained message on `topic`, if any.
+    pub fn retained(&self, topic: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let payload = state.retained.get(topic)?;
+        Some(String::from_utf8_lossy(payload).into_owned())
+    }
+
+    /// Every payload published to `topic` so far, oldest first.
+    pub fn messages(&self, topic: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .log
+            .iter()
+            .filter(|(logged, _)| logged == topic)
+            .map(|(_, payload)| String::from_utf8_lossy(payload).into_owned())
+            .collect()
+    }
+
+    /// Publishes as another client would, e.g. a hub sending a command.
+    pub fn publish(&self, topic: &str, payload: &str) {
+        let mut state = self.state.lock().unwrap();
+        route_publish(&mut state, topic, payload.as_bytes(), false);
+    }
+
+    /// Drops every client connection, as a broker restart would.
+    pub fn disconnect_all(&self) {
+        let state = self.state.lock().unwrap();
+        for client in &state.clients {
+            let _ = client.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for FakeBroker {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().closed = true;
+        self.disconnect_all();
+        // Wakes the accept loop so it sees `closed`
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+// Runs one client connection until it disconnects, then publishes its
+// will unless it said goodbye.
+fn serve_mqtt_client(stream: TcpStream, shared: &Mutex<BrokerState>) {
+    let Ok(mut reader) = stream.try_clone() else {
+        return;
+    };
+    let id = {
+        let mut state = shared.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.clients.push(BrokerClient {
+            id,
+            stream,
+            filters: Vec::new(),
+        });
+        id
+    };
+
+    let mut will: Option<Will> = None;
+    while let Ok(packet) = Packet::read(&mut reader) {
+        let mut state = shared.lock().unwrap();
+        match packet {
+            Packet::Connect {
+                will: last_will, ..
+            } => {
+                will = last_will;
+                state.connects += 1;
+                send_to(&mut state, id, &Packet::ConnAck { code: 0 });
+            }
+            Packet::Subscribe {
+                id: packet_id,
+                topics,
+            } => {
+                let count = topics.len();
+                send_to(
+                    &mut state,
+                    id,
+                    &Packet::SubAck {
+                        id: packet_id,
+                        count,
+                    },
+                );
+                // Retained messages go to new subscribers straight away
+                let retained: Vec<_> = state
+                    .retained
+                    .iter()
+                    .filter(|(topic, _)| topics.iter().any(|filter| topic_matches(filter, topic)))
+                    .map(|(topic, payload)| Packet::Publish {
+                        topic: topic.clone(),
+                        payload: payload.clone(),
+                        retain: true,
+                    })
+                    .collect();
+                for packet in &retained {
+                    send_to(&mut state, id, packet);
+                }
+                if let Some(client) = state.clients.iter_mut().find(|client| client.id == id) {
+                    client.filters.extend(topics);
+                }
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+            } => route_publish(&mut state, &topic, &payload, retain),
+            Packet::PingReq => send_to(&mut state, id, &Packet::PingResp),
+            Packet::Disconnect => {
+                will = None;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let mut state = shared.lock().unwrap();
+    state.clients.retain(|client| client.id != id);
+    if let Some(will) = will {
+        route_publish(&mut state, &will.topic, &will.payload, will.retain);
+    }
+}
+
+fn send_to(state: &mut BrokerState, id: usize, packet: &Packet) {
+    if let Some(client) = state.clients.iter_mut().find(|client| client.id == id) {
+        let _ = packet.write(&mut client.stream);
+    }
+}
+
+// Logs, retains and forwards a message to the matching subscribers.
+fn route_publish(state: &mut BrokerState, topic: &str, payload: &[u8], retain: bool) {
+    state.log.push((topic.to_string(), payload.to_vec()));
+    if retain {
+        if payload.is_empty() {
+            state.retained.remove(topic);
+        } else {
+            state.retained.insert(topic.to_string(), payload.to_vec());
+        }
+    }
+    let packet = Packet::Publish {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+        retain: false,
+    };
+    for client in &mut state.clients {
+        if client
+            .filters
+            .iter()
+            .any(|filter| topic_matches(filter, topic))
+        {
+            let _ = packet.write(&mut client.stream);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Input smoothing and threshold hysteresis between `read_speed` and the
+//! mapping.
+//!
+//! The kernel reports an integer speed that flickers between neighbouring
+//! values, which makes LEDs near their activation threshold blink on and
+//! off. Smoothing filters steady the speed itself; hysteresis makes each LED
+//! need a little more speed to turn on than it takes to stay on.
+
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+
+use crate::frame::DutyFrame;
+use crate::mapping::Mapping;
+
+/// A stateful filter over successive speed readings.
+pub trait SpeedFilter {
+    /// Feeds one reading and returns the filtered speed.
+    fn update(&mut self, speed: f64) -> f64;
+
+    /// Forgets all previous readings.
+    fn reset(&mut self);
+}
+
+/// Smoothing filter selected in the config file with e.g.
+/// `smoothing = { type = "ema", alpha = 0.3 }`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Smoothing {
+    /// Readings pass through unchanged.
+    #[default]
+    None,
+    /// Mean of the last `window` readings.
+    MovingAverage { window: usize },
+    /// Exponential moving average; higher `alpha` (0-1) follows faster.
+    Ema { alpha: f64 },
+    /// Median of the last `window` readings, which drops single spikes.
+    Median { window: usize },
+}
+
+impl Smoothing {
+    /// Checks the parameters, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Smoothing::None => Ok(()),
+            Smoothing::MovingAverage { window } | Smoothing::Median { window } => {
+                if *window == 0 {
+                    Err("window must be at least 1".into())
+                } else {
+                    Ok(())
+                }
+            }
+            Smoothing::Ema { alpha } => {
+                if *alpha > 0.0 && *alpha <= 1.0 {
+                    Ok(())
+                } else {
+                    Err(format!("ema alpha {} is outside (0, 1]", alpha))
+                }
+            }
+        }
+    }
+
+    /// Creates a fresh filter with these settings.
+    pub fn build(&self) -> Box<dyn SpeedFilter + Send> {
+        match *self {
+            Smoothing::None => Box::new(Passthrough),
+            Smoothing::MovingAverage { window } => Box::new(MovingAverage::new(window)),
+            Smoothing::Ema { alpha } => Box::new(Ema::new(alpha)),
+            Smoothing::Median { window } => Box::new(Median::new(window)),
+        }
+    }
+}
+
+/// `[filter]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub smoothing: Smoothing,
+    /// Width of the band around each LED's `start`, in the same 0.0-1.0
+    /// position units. An LED turns on at `start + hysteresis / 2` and off
+    /// again below `start - hysteresis / 2`.
+    pub hysteresis: f64,
+}
+
+impl FilterConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        self.smoothing.validate()?;
+        if !(0.0..=1.0).contains(&self.hysteresis) {
+            return Err(format!("hysteresis {} is outside 0.0-1.0", self.hysteresis));
+        }
+        Ok(())
+    }
+}
+
+/// Identity filter.
+#[derive(Debug, Default)]
+pub struct Passthrough;
+
+impl SpeedFilter for Passthrough {
+    fn update(&mut self, speed: f64) -> f64 {
+        speed
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Mean of the last `window` readings.
+#[derive(Debug)]
+pub struct MovingAverage {
+    window: usize,
+    samples: VecDeque<f64>,
+}
+
+impl MovingAverage {
+    pub fn new(window: usize) -> Self {
+        MovingAverage {
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl SpeedFilter for MovingAverage {
+    fn update(&mut self, speed: f64) -> f64 {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(speed);
+        self.samples.iter().sum::<f64>() / self.samples.len() as f64
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Exponential moving average, seeded with the first reading.
+#[derive(Debug)]
+pub struct Ema {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(alpha: f64) -> Self {
+        Ema { alpha, value: None }
+    }
+}
+
+impl SpeedFilter for Ema {
+    fn update(&mut self, speed: f64) -> f64 {
+        let value = match self.value {
+            Some(previous) => previous + self.alpha * (speed - previous),
+            None => speed,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Median of the last `window` readings; with an even count the two middle
+/// readings are averaged.
+#[derive(Debug)]
+pub struct Median {
+    window: usize,
+    samples: VecDeque<f64>,
+}
+
+impl Median {
+    pub fn new(window: usize) -> Self {
+        Median {
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl SpeedFilter for Median {
+    fn update(&mut self, speed: f64) -> f64 {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(speed);
+
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        if sorted.len() % 2 == 1 {
+            sorted[middle]
+        } else {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        }
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Per-LED on/off state with a hysteresis band around each `start`.
+#[derive(Debug, Clone)]
+pub struct Hysteresis {
+    band: f64,
+    active: Vec<bool>,
+}
+
+impl Hysteresis {
+    pub fn new(band: f64) -> Self {
+        Hysteresis {
+            band,
+            active: Vec::new(),
+        }
+    }
+
+    /// Maps a normalised speed position to duty cycles, updating which LEDs
+    /// are on.
+    pub fn map(&mut self, mapping: &Mapping, position: f64) -> DutyFrame {
+        self.active.resize(mapping.channels(), false);
+        let half = self.band / 2.0;
+        mapping
+            .leds
+            .iter()
+            .zip(self.active.iter_mut())
+            .map(|(led, active)| {
+                // An LED starting at the bottom of the range is always on
+                *active = if led.start <= 0.0 {
+                    true
+                } else if *active {
+                    position >= led.start - half
+                } else {
+                    position >= led.start + half
+                };
+                if *active {
+                    led.curve.apply(led.level(position))
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+}
+
+/// Smoothing followed by hysteresis, holding the state of both.
+pub struct FilterChain {
+    smoothing: Box<dyn SpeedFilter + Send>,
+    hysteresis: Hysteresis,
+}
+
+impl FilterChain {
+    pub fn new(config: &FilterConfig) -> Self {
+        FilterChain {
+            smoothing: config.smoothing.build(),
+            hysteresis: Hysteresis::new(config.hysteresis),
+        }
+    }
+
+    /// Feeds a raw reading through the smoothing filter.
+    pub fn smooth(&mut self, speed: f64) -> f64 {
+        self.smoothing.update(speed)
+    }
+
+    /// Maps a smoothed speed to duty cycles through the hysteresis stage.
+    pub fn map(&mut self, mapping: &Mapping, speed: f64) -> DutyFrame {
+        self.hysteresis.map(mapping, mapping.position(speed))
+    }
+
+    /// Forgets all previous readings and LED states.
+    pub fn reset(&mut self) {
+        self.smoothing.reset();
+        self.hysteresis = Hysteresis::new(self.hysteresis.band);
+    }
+}
@@ -0,0 +1,79 @@
+//! Duty frames: one duty cycle per LED channel.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use serde::{Deserialize, Serialize};
+
+/// One duty cycle (0-100%) per LED, LED1 first. The length is the number of
+/// channels, which depends on the board. Serializes as a plain list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DutyFrame(Vec<u32>);
+
+impl DutyFrame {
+    /// A frame of `channels` LEDs, all off.
+    pub fn off(channels: usize) -> Self {
+        DutyFrame(vec![0; channels])
+    }
+
+    /// Number of channels in the frame.
+    pub fn channels(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The duties as a plain vector.
+    pub fn into_vec(self) -> Vec<u32> {
+        self.0
+    }
+}
+
+impl Deref for DutyFrame {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl DerefMut for DutyFrame {
+    fn deref_mut(&mut self) -> &mut [u32] {
+        &mut self.0
+    }
+}
+
+impl From<Vec<u32>> for DutyFrame {
+    fn from(duties: Vec<u32>) -> Self {
+        DutyFrame(duties)
+    }
+}
+
+impl<const N: usize> From<[u32; N]> for DutyFrame {
+    fn from(duties: [u32; N]) -> Self {
+        DutyFrame(duties.to_vec())
+    }
+}
+
+impl FromIterator<u32> for DutyFrame {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        DutyFrame(iter.into_iter().collect())
+    }
+}
+
+impl<const N: usize> PartialEq<[u32; N]> for DutyFrame {
+    fn eq(&self, other: &[u32; N]) -> bool {
+        self.0 == other
+    }
+}
+
+// Formats as "L1=10%, L2=0%, L3=0%".
+impl fmt::Display for DutyFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, duty) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "L{}={}%", index + 1, duty)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+//! GPIO lines through the character device (`/dev/gpiochipN`) and the
+//! kernel's v2 uAPI, so buttons can be read without the custom module.
+//!
+//! Requested lines report their edges as events stamped by the kernel when
+//! the interrupt fired (`CLOCK_MONOTONIC`, like the module's `ktime_get`),
+//! so the time between presses does not depend on when they are read.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{ControllerError, Result};
+use crate::watch::SpeedNotifier;
+
+/// Default GPIO chip, the one holding the header pins on a Raspberry Pi.
+pub const GPIO_CHIP_PATH: &str = "/dev/gpiochip0";
+
+/// Name the requested lines show up under in `gpioinfo`.
+pub const CONSUMER: &str = "pwm_led";
+
+// From <linux/gpio.h>
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const GPIO_V2_LINE_ATTR_ID_DEBOUNCE: u32 = 3;
+const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+const LINE_EVENT_SIZE: usize = 48;
+const GPIO_V2_GET_LINE_IOCTL: u64 = iowr(0x07, mem::size_of::<LineRequest>());
+const GPIO_V2_LINE_SET_VALUES_IOCTL: u64 = iowr(0x0F, mem::size_of::<LineValues>());
+
+// _IOWR(0xB4, nr, size)
+const fn iowr(nr: u64, size: usize) -> u64 {
+    (3 << 30) | ((size as u64) << 16) | (0xB4 << 8) | nr
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union AttributeValue {
+    flags: u64,
+    debounce_period_us: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: AttributeValue,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+const _: () = assert!(mem::size_of::<LineRequest>() == 592);
+
+/// Now on `CLOCK_MONOTONIC`, the clock of line event timestamps.
+pub fn monotonic_ns() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid timespec for the call to fill in
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// Which edges of an input line produce events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    /// Low to high, when a button wired like the module expects is pressed.
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Edge {
+    fn flags(self) -> u64 {
+        match self {
+            Edge::Rising => GPIO_V2_LINE_FLAG_EDGE_RISING,
+            Edge::Falling => GPIO_V2_LINE_FLAG_EDGE_FALLING,
+            Edge::Both => GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING,
+        }
+    }
+}
+
+/// One edge on a requested line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEvent {
+    /// Offset of the line on its chip.
+    pub offset: u32,
+    /// Whether the line went high; otherwise it went low.
+    pub rising: bool,
+    /// When the edge happened, in nanoseconds of `CLOCK_MONOTONIC`.
+    pub timestamp_ns: u64,
+}
+
+impl LineEvent {
+    /// A rising edge, the kind a button press produces.
+    pub fn rising(offset: u32, timestamp_ns: u64) -> Self {
+        LineEvent {
+            offset,
+            rising: true,
+            timestamp_ns,
+        }
+    }
+
+    // Decodes a `struct gpio_v2_line_event`.
+    fn parse(bytes: &[u8]) -> Self {
+        let u32_at = |at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+        LineEvent {
+            offset: u32_at(12),
+            rising: u32_at(8) == GPIO_V2_LINE_EVENT_RISING_EDGE,
+            timestamp_ns: u64::from_ne_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+}
+
+/// Where line events come from: lines requested from a GPIO chip, or a
+/// mock in tests.
+pub trait LineEventSource: Send {
+    /// Returns the events that arrived since the last call, oldest first,
+    /// without waiting for more.
+    fn read_events(&mut self) -> Result<Vec<LineEvent>>;
+
+    /// Opens a way to wait for the next event.
+    fn event_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        None
+    }
+}
+
+/// Input lines of a GPIO chip, requested with edge detection.
+///
+/// The lines are released when this is dropped.
+#[derive(Debug)]
+pub struct InputLines {
+    request: File,
+    chip: PathBuf,
+}
+
+impl InputLines {
+    /// Requests `offsets` of `chip` as inputs reporting `edge`. A non-zero
+    /// `debounce_us` has the kernel ignore bounces shorter than that.
+    pub fn request(
+        chip: impl Into<PathBuf>,
+        offsets: &[u32],
+        edge: Edge,
+        debounce_us: u32,
+    ) -> Result<Self> {
+        let chip = chip.into();
+        let mut attrs = Vec::new();
+        if debounce_us > 0 {
+            attrs.push(LineAttribute {
+                id: GPIO_V2_LINE_ATTR_ID_DEBOUNCE,
+                padding: 0,
+                value: AttributeValue {
+                    debounce_period_us: debounce_us,
+                },
+            });
+        }
+        let request = request_lines(
+            &chip,
+            offsets,
+            GPIO_V2_LINE_FLAG_INPUT | edge.flags(),
+            &attrs,
+        )?;
+        set_nonblocking(&request).map_err(|e| ControllerError::from_io(&chip, e))?;
+        Ok(InputLines { request, chip })
+    }
+
+    /// The chip the lines belong to.
+    pub fn chip(&self) -> &Path {
+        &self.chip
+    }
+}
+
+impl LineEventSource for InputLines {
+    fn read_events(&mut self) -> Result<Vec<LineEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = [0; LINE_EVENT_SIZE * 16];
+        loop {
+            match self.request.read(&mut buffer) {
+                Ok(0) => return Ok(events),
+                Ok(len) => events.extend(
+                    buffer[..len]
+                        .chunks_exact(LINE_EVENT_SIZE)
+                        .map(LineEvent::parse),
+                ),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(events),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(ControllerError::from_io(&self.chip, err)),
+            }
+        }
+    }
+
+    fn event_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        let notifier = EventNotifier::watch(&self.request, &self.chip).ok()?;
+        Some(Box::new(notifier))
+    }
+}
+
+/// Where output line levels go: lines requested from a GPIO chip, or a
+/// recording fake in tests.
+pub trait LineSink: Send {
+    /// Number of lines; bit `i` of a value mask is line `i` of the request.
+    fn lines(&self) -> usize;
+
+    /// Drives the lines selected by `mask` high where `values` has a 1 and
+    /// low where it has a 0, leaving the others as they are.
+    fn set_values(&mut self, values: u64, mask: u64) -> Result<()>;
+}
+
+/// Output lines of a GPIO chip, starting low.
+///
+/// The lines are released when this is dropped.
+#[derive(Debug)]
+pub struct OutputLines {
+    request: File,
+    chip: PathBuf,
+    lines: usize,
+}
+
+impl OutputLines {
+    /// Requests `offsets` of `chip` as outputs, all driven low.
+    pub fn request(chip: impl Into<PathBuf>, offsets: &[u32]) -> Result<Self> {
+        let chip = chip.into();
+        let request = request_lines(&chip, offsets, GPIO_V2_LINE_FLAG_OUTPUT, &[])?;
+        Ok(OutputLines {
+            request,
+            chip,
+            lines: offsets.len(),
+        })
+    }
+
+    /// The chip the lines belong to.
+    pub fn chip(&self) -> &Path {
+        &self.chip
+    }
+}
+
+impl LineSink for OutputLines {
+    fn lines(&self) -> usize {
+        self.lines
+    }
+
+    fn set_values(&mut self, values: u64, mask: u64) -> Result<()> {
+        let mut request = LineValues { bits: values, mask };
+        // SAFETY: `request` is a valid `struct gpio_v2_line_values` that
+        // outlives the call
+        let ret = unsafe {
+            libc::ioctl(
+                self.request.as_raw_fd(),
+                GPIO_V2_LINE_SET_VALUES_IOCTL as _,
+                &mut request,
+            )
+        };
+        if ret < 0 {
+            return Err(ControllerError::from_io(
+                &self.chip,
+                io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Asks the chip for `offsets` with `flags`, each of `attrs` applying to
+// every line, and returns the file of the request.
+fn request_lines(
+    chip: &Path,
+    offsets: &[u32],
+    flags: u64,
+    attrs: &[LineAttribute],
+) -> Result<File> {
+    if offsets.is_empty() || offsets.len() > GPIO_V2_LINES_MAX {
+        return Err(ControllerError::Unsupported(
+            "a GPIO request takes between 1 and 64 lines",
+        ));
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open(chip)
+        .map_err(|e| ControllerError::from_io(chip, e))?;
+
+    // SAFETY: every field is an integer or an array or union of them, for
+    // which all zeroes is valid
+    let mut request: LineRequest = unsafe { mem::zeroed() };
+    request.offsets[..offsets.len()].copy_from_slice(offsets);
+    request.consumer[..CONSUMER.len()].copy_from_slice(CONSUMER.as_bytes());
+    request.num_lines = offsets.len() as u32;
+    request.config.flags = flags;
+    let mask = u64::MAX >> (GPIO_V2_LINES_MAX - offsets.len());
+    for (slot, &attr) in request.config.attrs.iter_mut().zip(attrs) {
+        *slot = LineConfigAttribute { attr, mask };
+    }
+    request.config.num_attrs = attrs.len() as u32;
+
+    // SAFETY: `request` is a valid `struct gpio_v2_line_request` that
+    // outlives the call
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL as _, &mut request) };
+    if ret < 0 {
+        return Err(ControllerError::from_io(chip, io::Error::last_os_error()));
+    }
+    // SAFETY: on success the kernel hands over a new descriptor we own
+    Ok(unsafe { File::from_raw_fd(request.fd) })
+}
+
+fn set_nonblocking(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    // SAFETY: plain fcntl calls on a descriptor we own
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Waits for new data on a descriptor, such as the events of requested
+/// lines.
+///
+/// Edge-triggered: only data arriving after the last `wait` wakes it, so
+/// events nobody has read yet do not keep it spinning.
+#[derive(Debug)]
+pub struct EventNotifier {
+    epoll: OwnedFd,
+    path: PathBuf,
+}
+
+impl EventNotifier {
+    /// Watches `file`; `path` is only used in error messages.
+    pub fn watch(file: &impl AsFd, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        // SAFETY: plain syscall, the descriptor is checked below
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(ControllerError::from_io(&path, io::Error::last_os_error()));
+        }
+        // SAFETY: a new descriptor nobody else owns
+        let epoll = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLET) as u32,
+            u64: 0,
+        };
+        let watched = file.as_fd().as_raw_fd();
+        // SAFETY: both descriptors are open and `event` outlives the call
+        if unsafe { libc::epoll_ctl(fd, libc::EPOLL_CTL_ADD, watched, &mut event) } < 0 {
+            return Err(ControllerError::from_io(&path, io::Error::last_os_error()));
+        }
+        Ok(EventNotifier { epoll, path })
+    }
+}
+
+impl SpeedNotifier for EventNotifier {
+    fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: room for exactly the one event asked for
+        let ready = unsafe { libc::epoll_wait(self.epoll.as_raw_fd(), &mut event, 1, timeout_ms) };
+        match ready {
+            0 => Ok(false),
+            n if n > 0 => Ok(true),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(ControllerError::from_io(&self.path, err))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! JSON HTTP API over the control service (`http` feature), described in
+//! `openapi.yaml`. Minimal HTTP/1.1: one request per connection.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::daemon::next_client;
+use crate::error::ControllerError;
+use crate::frame::DutyFrame;
+use crate::service::{Mode, Service, Status};
+
+/// The OpenAPI description of the endpoints, served at `/openapi.yaml`.
+pub const OPENAPI: &str = include_str!("../openapi.yaml");
+
+// Requests larger than this are refused rather than buffered.
+const MAX_HEADER: usize = 8 * 1024;
+const MAX_BODY: usize = 64 * 1024;
+
+/// Accepts HTTP clients, one thread per connection.
+#[derive(Debug)]
+pub struct HttpServer {
+    listener: TcpListener,
+    service: Service,
+}
+
+impl HttpServer {
+    pub fn bind(addr: impl ToSocketAddrs, service: Service) -> io::Result<HttpServer> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(HttpServer { listener, service })
+    }
+
+    /// The address actually bound, useful after binding port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients until the socket itself fails. A client that cannot
+    /// be accepted is logged and skipped.
+    pub fn serve(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let Some(stream) = next_client(stream)? else {
+                continue;
+            };
+            let service = self.service.clone();
+            thread::spawn(move || {
+                if let Err(err) = serve_client(stream, &service) {
+                    eprintln!("HTTP client error: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One parsed HTTP request.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of the header called `name`, matched case-insensitively.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Whether this asks to switch the connection to WebSocket.
+    #[cfg(feature = "websocket")]
+    fn wants_websocket(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+/// What to send back.
+#[derive(Debug)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Reply::json(status, &json!({ "error": message.to_string() }))
+    }
+}
+
+fn serve_client(stream: TcpStream, service: &Service) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let reply = match read_request(&mut BufReader::new(stream)) {
+        #[cfg(feature = "websocket")]
+        Ok(request) if request.method == "GET" && request.path == "/events" => {
+            match request.header("sec-websocket-key") {
+                Some(key) if request.wants_websocket() => {
+                    return crate::websocket::stream_events(writer, key, service);
+                }
+                _ => Reply::error(400, "/events needs a WebSocket upgrade"),
+            }
+        }
+        Ok(request) => route(service, &request),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => Reply::error(400, err),
+        Err(err) => return Err(err),
+    };
+    write_reply(&mut writer, &reply)
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), path.to_string())
+        }
+        _ => return Err(bad("malformed request line")),
+    };
+
+    let mut headers = Vec::new();
+    let mut header_size = line.len();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(bad("connection closed in the headers"));
+        }
+        header_size += line.len();
+        if header_size > MAX_HEADER {
+            return Err(bad("headers too large"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let length = match request.header("content-length") {
+        Some(value) => value.parse().map_err(|_| bad("bad Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(bad("body too large"));
+    }
+
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+fn write_reply<W: Write>(writer: &mut W, reply: &Reply) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reason(reply.status),
+        reply.content_type,
+        reply.body.len(),
+        reply.body
+    )?;
+    writer.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Body of `PUT /leds/{n}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LedBody {
+    duty: u32,
+}
+
+/// Body of `POST /mode`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModeBody {
+    mode: Mode,
+    /// Override for manual mode; without it the current duties are kept.
+    duties: Option<DutyFrame>,
+    profile: Option<String>,
+}
+
+fn route(service: &Service, request: &Request) -> Reply {
+    let path = request.path.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["speed"]) => get_speed(service),
+        ("GET", ["leds"]) => get_leds(service),
+        ("PUT", ["leds", led]) => put_led(service, led, &request.body),
+        ("POST", ["mode"]) => post_mode(service, &request.body),
+        ("GET", ["metrics"]) => Reply {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: service.metrics().render(&service.status()),
+        },
+        ("GET", ["openapi.yaml"]) => Reply {
+            status: 200,
+            content_type: "application/yaml",
+            body: OPENAPI.to_string(),
+        },
+        (_, ["speed"]) | (_, ["leds"]) | (_, ["leds", _]) | (_, ["mode"]) | (_, ["metrics"]) => {
+            Reply::error(405, format!("{} not allowed on {}", request.method, path))
+        }
+        _ => Reply::error(404, format!("no such endpoint {}", path)),
+    }
+}
+
+fn get_speed(service: &Service) -> Reply {
+    let status = service.status();
+    // The last speed is kept after a failure, but it is stale by then
+    match (status.speed, status.error) {
+        (_, Some(error)) => Reply::error(503, error),
+        (Some(speed), None) => Reply::json(
+            200,
+            &json!({ "speed": speed, "filtered_speed": status.filtered_speed }),
+        ),
+        (None, None) => Reply::error(503, "no speed reading yet"),
+    }
+}
+
+// Lists `frame` with LEDs numbered from 1, like the ledN_duty files.
+fn leds_json(status: &Status, frame: Option<&DutyFrame>) -> serde_json::Value {
+    let duties = frame.map(|frame| &frame[..]).unwrap_or(&[]);
+    let leds: Vec<_> = duties
+        .iter()
+        .enumerate()
+        .map(|(index, duty)| json!({ "led": index + 1, "duty": duty }))
+        .collect();
+    json!({ "mode": status.mode, "profile": status.profile, "leds": leds })
+}
+
+fn get_leds(service: &Service) -> Reply {
+    let status = service.status();
+    Reply::json(200, &leds_json(&status, status.duties.as_ref()))
+}
+
+fn put_led(service: &Service, led: &str, body: &[u8]) -> Reply {
+    let led = match led.parse::<usize>() {
+        Ok(led) if led >= 1 => led - 1,
+        _ => return Reply::error(404, format!("no LED '{}'", led)),
+    };
+    let body: LedBody = match serde_json::from_slice(body) {
+        Ok(body) => body,
+        Err(err) => return Reply::error(400, format!("bad body: {}", err)),
+    };
+    match service.set_led(led, body.duty) {
+        // The new override, which the loop writes right after
+        Ok(status) => Reply::json(200, &leds_json(&status, status.manual.as_ref())),
+        Err(err @ ControllerError::LedOutOfRange { .. }) => Reply::error(404, err),
+        Err(err) => Reply::error(400, err),
+    }
+}
+
+fn post_mode(service: &Service, body: &[u8]) -> Reply {
+    let body: ModeBody = match serde_json::from_slice(body) {
+        Ok(body) => body,
+        Err(err) => return Reply::error(400, format!("bad body: {}", err)),
+    };
+    // Checked as a whole, so a bad part leaves everything as it was
+    match service.switch_mode(body.mode, body.duties, body.profile.as_deref()) {
+        Ok(status) => Reply::json(200, &status),
+        Err(err) => Reply::error(400, err),
+    }
+}
@@ -0,0 +1,264 @@
+//! LEDs of the kernel's LED class (`/sys/class/leds/<name>`), as set up by
+//! drivers such as `leds-pwm` or `leds-gpio` instead of this project's
+//! module.
+//!
+//! Duties are the same 0-100 values the module takes, scaled against each
+//! LED's `max_brightness`; `leds-gpio` LEDs have a `max_brightness` of 1 and
+//! simply turn on from 50 up. Like the PWM subsystem, the LED class knows
+//! nothing about buttons, so the speed comes from a separate `SpeedSource`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::backend::{check_duty, check_frame, check_led, LedBackend};
+use crate::error::{ControllerError, Result, MAX_DUTY};
+use crate::frame::DutyFrame;
+use crate::pwmchip::write_attribute;
+use crate::speed::{SpeedFile, SpeedSource};
+use crate::sysfs::{read_attribute, read_text, SYSFS_PATH};
+use crate::watch::SpeedNotifier;
+
+/// Where the kernel lists the LED class devices.
+pub const LED_CLASS_PATH: &str = "/sys/class/leds";
+
+/// `[leds]` section of the config file, used by `--backend ledclass`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedClassConfig {
+    /// Directory holding the LEDs.
+    pub path: PathBuf,
+    /// Name of each LED, in LED order, e.g. `"pwm-led1"`.
+    pub names: Vec<String>,
+    /// Switch every LED's `trigger` to `none` while the backend is open,
+    /// so heartbeat or disk activity triggers do not fight the duties.
+    pub disable_triggers: bool,
+    /// File the speed is read from, as `SpeedFile` reads it.
+    pub speed: PathBuf,
+}
+
+impl Default for LedClassConfig {
+    fn default() -> Self {
+        LedClassConfig {
+            path: PathBuf::from(LED_CLASS_PATH),
+            names: vec!["led1".into(), "led2".into(), "led3".into()],
+            disable_triggers: true,
+            speed: Path::new(SYSFS_PATH).join("button_speed"),
+        }
+    }
+}
+
+impl LedClassConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.names.is_empty() {
+            return Err("names must list at least one LED".into());
+        }
+        for (i, name) in self.names.iter().enumerate() {
+            if name.is_empty() || name.contains('/') {
+                return Err(format!("'{}' is not an LED name", name));
+            }
+            if self.names[..i].contains(name) {
+                return Err(format!("LED '{}' is listed twice", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts a 0-100 duty to a brightness out of `max_brightness`, rounded
+/// to the nearest step.
+pub fn duty_to_brightness(duty: u32, max_brightness: u32) -> u32 {
+    ((u64::from(duty) * u64::from(max_brightness) + u64::from(MAX_DUTY) / 2) / u64::from(MAX_DUTY))
+        as u32
+}
+
+/// Converts a brightness back to the nearest 0-100 duty.
+pub fn brightness_to_duty(brightness: u32, max_brightness: u32) -> u32 {
+    if max_brightness == 0 {
+        return 0;
+    }
+    let duty = (u64::from(brightness) * u64::from(MAX_DUTY) + u64::from(max_brightness) / 2)
+        / u64::from(max_brightness);
+    duty.min(u64::from(MAX_DUTY)) as u32
+}
+
+/// The active trigger in a `trigger` attribute, which lists every trigger
+/// with the active one in brackets: `none [timer] heartbeat`.
+pub fn active_trigger(text: &str) -> Option<&str> {
+    text.split_whitespace()
+        .find_map(|word| word.strip_prefix('[')?.strip_suffix(']'))
+}
+
+// One LED and what it was like before we took it over.
+#[derive(Debug)]
+struct Led {
+    dir: PathBuf,
+    max_brightness: u32,
+    // Trigger to put back on release, if one was switched off
+    trigger: Option<String>,
+}
+
+/// LEDs of the LED class, with the speed from `speed`.
+///
+/// With `disable_triggers`, the triggers are switched off before the first
+/// write, so commands that only read leave them alone. `release` puts them
+/// back when a long-running loop exits; dropping the backend leaves them at
+/// `none`, so the brightness a one-shot command wrote is not overridden.
+pub struct LedClassBackend {
+    leds: Vec<Led>,
+    speed: Box<dyn SpeedSource + Send>,
+    disable_triggers: bool,
+    triggers_taken: bool,
+}
+
+impl LedClassBackend {
+    /// Opens the LEDs called `names` under `path`, reading their
+    /// `max_brightness`. With `disable_triggers`, every LED with an active
+    /// trigger is switched to `none` on the first write.
+    pub fn open(
+        path: impl AsRef<Path>,
+        names: &[String],
+        disable_triggers: bool,
+        speed: Box<dyn SpeedSource + Send>,
+    ) -> Result<Self> {
+        let leds = names
+            .iter()
+            .map(|name| open_led(path.as_ref().join(name)))
+            .collect::<Result<_>>()?;
+        Ok(LedClassBackend {
+            leds,
+            speed,
+            disable_triggers,
+            triggers_taken: false,
+        })
+    }
+
+    // Switches every trigger to `none` the first time the LEDs are written.
+    // A failure puts back the ones already switched.
+    fn take_triggers(&mut self) -> Result<()> {
+        if !self.disable_triggers || self.triggers_taken {
+            return Ok(());
+        }
+        for i in 0..self.leds.len() {
+            match take_trigger(&self.leds[i].dir) {
+                Ok(trigger) => self.leds[i].trigger = trigger,
+                Err(err) => {
+                    let _ = self.release();
+                    return Err(err);
+                }
+            }
+        }
+        self.triggers_taken = true;
+        Ok(())
+    }
+
+    /// Opens the LEDs of a `[leds]` section, reading the speed from its
+    /// `speed` file.
+    pub fn from_config(config: &LedClassConfig) -> Result<Self> {
+        Self::open(
+            &config.path,
+            &config.names,
+            config.disable_triggers,
+            Box::new(SpeedFile::new(&config.speed)),
+        )
+    }
+
+    /// `max_brightness` of each LED.
+    pub fn max_brightness(&self) -> Vec<u32> {
+        self.leds.iter().map(|led| led.max_brightness).collect()
+    }
+}
+
+fn open_led(dir: PathBuf) -> Result<Led> {
+    if !dir.is_dir() {
+        return Err(ControllerError::DeviceMissing { path: dir });
+    }
+    let max_path = dir.join("max_brightness");
+    let max_brightness: u32 = read_attribute(&max_path)?;
+    if max_brightness == 0 {
+        return Err(ControllerError::malformed(&max_path, "0"));
+    }
+    Ok(Led {
+        dir,
+        max_brightness,
+        trigger: None,
+    })
+}
+
+// Switches the LED's trigger to `none`, returning the one that was active.
+fn take_trigger(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join("trigger");
+    let text = read_text(&path)?;
+    match active_trigger(&text) {
+        Some("none") | None => Ok(None),
+        Some(trigger) => {
+            let trigger = trigger.to_string();
+            write_attribute(&path, "none")?;
+            Ok(Some(trigger))
+        }
+    }
+}
+
+impl fmt::Debug for LedClassBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LedClassBackend")
+            .field("leds", &self.leds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LedBackend for LedClassBackend {
+    fn led_count(&self) -> usize {
+        self.leds.len()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.speed.read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.leds.len())?;
+        let led = &self.leds[led];
+        let brightness = read_attribute(&led.dir.join("brightness"))?;
+        Ok(brightness_to_duty(brightness, led.max_brightness))
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.leds.len())?;
+        check_duty(led, duty)?;
+        self.take_triggers()?;
+        let led = &self.leds[led];
+        write_attribute(
+            &led.dir.join("brightness"),
+            duty_to_brightness(duty, led.max_brightness),
+        )
+    }
+
+    // One write per LED, like sysfs; validated first so a bad value cannot
+    // leave the frame half applied.
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.leds.len())?;
+        for (led, &duty) in frame.iter().enumerate() {
+            self.write_duty(led, duty)?;
+        }
+        Ok(())
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        self.speed.speed_notifier()
+    }
+
+    // Every trigger is tried even if one fails; the first error is returned.
+    fn release(&mut self) -> Result<()> {
+        self.triggers_taken = false;
+        let mut result = Ok(());
+        for led in &mut self.leds {
+            if let Some(trigger) = led.trigger.take() {
+                result = result.and(write_attribute(&led.dir.join("trigger"), trigger));
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,77 @@
+//! Shared logic for the PWM LED controller clients.
+//!
+//! Reads the button press speed from the kernel module, maps it to LED duty
+//! cycles and writes them back, over either the character device or sysfs.
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod backend;
+pub mod buttons;
+pub mod chardev;
+pub mod config;
+pub mod control;
+pub mod curve;
+pub mod daemon;
+pub mod error;
+pub mod estimator;
+pub mod fade;
+pub mod fake;
+pub mod filter;
+pub mod frame;
+pub mod gpio;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod ledclass;
+pub mod mapping;
+pub mod metrics;
+pub mod mqtt;
+pub mod pwmchip;
+pub mod service;
+pub mod shutdown;
+pub mod softpwm;
+pub mod speed;
+pub mod sysfs;
+pub mod watch;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::{
+    run_until, speed_samples, AsyncCharDevBackend, AsyncLedBackend, AsyncSysfsBackend,
+};
+pub use backend::{BackendKind, BackendPaths, LedBackend, DEFAULT_LED_COUNT};
+pub use buttons::{ButtonConfig, ButtonSpeed};
+pub use chardev::CharDevBackend;
+pub use config::{Config, ConfigError, DEFAULT_PROFILE};
+pub use control::{
+    check_channels, run, run_every, run_reporting, run_until_stopped, step, Reading,
+};
+pub use curve::ResponseCurve;
+pub use daemon::{Client, ClientError, DaemonConfig, Request, Response, Server};
+pub use error::{ControllerError, Result, MAX_DUTY};
+pub use estimator::SpeedEstimator;
+pub use fade::{Easing, FadeConfig, Fader};
+pub use fake::{
+    FakeBroker, FakeLedClass, FakePwmChip, FakeSysfsBackend, LineChange, MockBackend,
+    MockLineEvents, RecordingLineSink,
+};
+pub use filter::{FilterChain, FilterConfig, Hysteresis, Smoothing, SpeedFilter};
+pub use frame::DutyFrame;
+pub use gpio::{
+    Edge, EventNotifier, InputLines, LineEvent, LineEventSource, LineSink, OutputLines,
+};
+#[cfg(feature = "http")]
+pub use http::HttpServer;
+pub use ledclass::{LedClassBackend, LedClassConfig};
+pub use mapping::{map_speed_to_duty_cycles, LedRamp, Mapping, MAX_SPEED, MIN_SPEED};
+pub use metrics::{Histogram, Metrics, LATENCY_BUCKETS};
+pub use mqtt::{MqttBridge, MqttConfig};
+pub use pwmchip::{PwmChipBackend, PwmConfig};
+pub use service::{Event, EventKind, Mode, Service, Status};
+pub use shutdown::{
+    run_until_shutdown, signal_name, ShutdownConfig, ShutdownPolicy, ShutdownSignals,
+};
+pub use softpwm::{JitterStats, SoftPwm, SoftPwmBackend, SoftPwmConfig};
+pub use speed::{SpeedFile, SpeedSource};
+pub use sysfs::SysfsBackend;
+pub use watch::{speed_changes, AdaptiveInterval, PollNotifier, SpeedChanges, SpeedNotifier};
@@ -0,0 +1,182 @@
+//! Mapping from button press speed to LED duty cycles.
+
+use serde::Deserialize;
+
+use crate::curve::ResponseCurve;
+use crate::error::MAX_DUTY;
+use crate::frame::DutyFrame;
+
+/// Max button press speed of the default profile.
+pub const MAX_SPEED: f64 = 10.0;
+/// Min button press speed of the default profile.
+pub const MIN_SPEED: f64 = 1.0;
+
+/// Linear ramp of one LED over the normalised speed range.
+///
+/// `start` is the position (0.0 at `min_speed`, 1.0 at `max_speed`) where the
+/// LED turns on. From there the duty rises from `floor` by `slope` percent
+/// per full range, capped at `ceiling`. Below `start` the LED is off.
+/// `curve` is then applied to the result.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LedRamp {
+    pub start: f64,
+    #[serde(default)]
+    pub floor: u32,
+    #[serde(default = "default_ceiling")]
+    pub ceiling: u32,
+    pub slope: f64,
+    #[serde(default)]
+    pub curve: ResponseCurve,
+}
+
+fn default_ceiling() -> u32 {
+    MAX_DUTY
+}
+
+impl LedRamp {
+    /// Duty cycle at normalised speed position `position`, before the
+    /// response curve.
+    pub fn ramp(&self, position: f64) -> u32 {
+        if position < self.start {
+            return 0;
+        }
+        self.level(position)
+    }
+
+    /// Duty cycle of the LED while it is on, which is `floor` at or below
+    /// `start`.
+    pub fn level(&self, position: f64) -> u32 {
+        // The cast saturates on steep slopes, and so must the sum
+        let rise = ((position - self.start).max(0.0) * self.slope) as u32;
+        self.floor.saturating_add(rise).min(self.ceiling)
+    }
+
+    /// Duty cycle at normalised speed position `position`.
+    pub fn duty(&self, position: f64) -> u32 {
+        self.curve.apply(self.ramp(position))
+    }
+}
+
+/// A complete speed-to-duty profile: the speed range and one ramp per LED.
+/// Fields missing from a config file keep their default value.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mapping {
+    pub min_speed: f64,
+    pub max_speed: f64,
+    #[serde(rename = "led")]
+    pub leds: Vec<LedRamp>,
+}
+
+impl Default for Mapping {
+    // LED1 10% -> 100% across the range, LED2 from 33%, LED3 from 66%.
+    fn default() -> Self {
+        Mapping {
+            min_speed: MIN_SPEED,
+            max_speed: MAX_SPEED,
+            leds: vec![
+                LedRamp {
+                    start: 0.0,
+                    floor: 10,
+                    ceiling: 100,
+                    slope: 90.0,
+                    curve: ResponseCurve::Linear,
+                },
+                LedRamp {
+                    start: 0.33,
+                    floor: 0,
+                    ceiling: 100,
+                    slope: 150.0,
+                    curve: ResponseCurve::Linear,
+                },
+                LedRamp {
+                    start: 0.66,
+                    floor: 0,
+                    ceiling: 100,
+                    slope: 300.0,
+                    curve: ResponseCurve::Linear,
+                },
+            ],
+        }
+    }
+}
+
+impl Mapping {
+    /// Checks that the profile is usable, returning a description of the
+    /// first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.min_speed >= 0.0 && self.max_speed.is_finite()) {
+            return Err(format!(
+                "speed range {}-{} must be finite and not negative",
+                self.min_speed, self.max_speed
+            ));
+        }
+        if self.min_speed >= self.max_speed {
+            return Err(format!(
+                "min_speed ({}) must be below max_speed ({})",
+                self.min_speed, self.max_speed
+            ));
+        }
+        if self.leds.is_empty() {
+            return Err("at least one [[mapping.led]] entry is needed".into());
+        }
+        for (index, led) in self.leds.iter().enumerate() {
+            let name = format!("LED{}", index + 1);
+            if !(0.0..=1.0).contains(&led.start) {
+                return Err(format!("{}: start {} is outside 0.0-1.0", name, led.start));
+            }
+            if !led.slope.is_finite() || led.slope < 0.0 {
+                return Err(format!(
+                    "{}: slope {} must be a positive number",
+                    name, led.slope
+                ));
+            }
+            if led.ceiling > MAX_DUTY {
+                return Err(format!(
+                    "{}: ceiling {} is above {}%",
+                    name, led.ceiling, MAX_DUTY
+                ));
+            }
+            if led.floor > led.ceiling {
+                return Err(format!(
+                    "{}: floor {} is above ceiling {}",
+                    name, led.floor, led.ceiling
+                ));
+            }
+            led.curve
+                .validate()
+                .map_err(|e| format!("{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    /// Position of `speed` within the range, 0.0 at or below `min_speed`
+    /// and 1.0 at or above `max_speed`.
+    pub fn position(&self, speed: f64) -> f64 {
+        if speed <= self.min_speed {
+            0.0
+        } else if speed >= self.max_speed {
+            1.0
+        } else {
+            (speed - self.min_speed) / (self.max_speed - self.min_speed)
+        }
+    }
+
+    /// Number of LEDs the profile drives.
+    pub fn channels(&self) -> usize {
+        self.leds.len()
+    }
+
+    /// Maps button press speed to one duty cycle per configured LED.
+    pub fn map(&self, speed: f64) -> DutyFrame {
+        let position = self.position(speed);
+        self.leds.iter().map(|led| led.duty(position)).collect()
+    }
+}
+
+/// Maps button press speed to LED duty cycles with the default profile.
+pub fn map_speed_to_duty_cycles(speed: f64) -> (u32, u32, u32) {
+    let frame = Mapping::default().map(speed);
+    (frame[0], frame[1], frame[2])
+}
@@ -0,0 +1,136 @@
+//! Counters kept by the control loop and their rendering in the Prometheus
+//! text exposition format, served at `/metrics`.
+
+use std::fmt::Write;
+
+use crate::service::Status;
+
+/// Upper bounds, in seconds, of the loop latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// Distribution of observed durations over `LATENCY_BUCKETS`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    pub count: u64,
+    /// Sum of all observations, in seconds.
+    pub sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// What the loop has done since the service started.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Failed speed readings.
+    pub read_errors: u64,
+    /// Failed duty writes.
+    pub write_errors: u64,
+    /// Passes through the loop, including fade steps and command wake-ups.
+    pub iterations: u64,
+    /// Time each pass spent reading, mapping and writing, without sleeping.
+    pub loop_latency: Histogram,
+}
+
+impl Metrics {
+    /// The metrics plus the speed and duty gauges taken from `status`, in
+    /// the Prometheus text format.
+    pub fn render(&self, status: &Status) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "pwm_led_speed",
+            "gauge",
+            "Last button press speed in presses/second.",
+        );
+        if let Some(speed) = status.speed {
+            let _ = writeln!(out, "pwm_led_speed {}", speed);
+        }
+        header(
+            &mut out,
+            "pwm_led_filtered_speed",
+            "gauge",
+            "Last button press speed after smoothing.",
+        );
+        if let Some(speed) = status.filtered_speed {
+            let _ = writeln!(out, "pwm_led_filtered_speed {}", speed);
+        }
+        header(
+            &mut out,
+            "pwm_led_duty",
+            "gauge",
+            "Duty cycle last written to each LED, in percent.",
+        );
+        if let Some(duties) = &status.duties {
+            // LEDs are numbered from 1, like the ledN_duty files
+            for (index, duty) in duties.iter().enumerate() {
+                let _ = writeln!(out, "pwm_led_duty{{led=\"{}\"}} {}", index + 1, duty);
+            }
+        }
+
+        header(
+            &mut out,
+            "pwm_led_read_errors_total",
+            "counter",
+            "Failed speed readings.",
+        );
+        let _ = writeln!(out, "pwm_led_read_errors_total {}", self.read_errors);
+        header(
+            &mut out,
+            "pwm_led_write_errors_total",
+            "counter",
+            "Failed duty cycle writes.",
+        );
+        let _ = writeln!(out, "pwm_led_write_errors_total {}", self.write_errors);
+        header(
+            &mut out,
+            "pwm_led_loop_iterations_total",
+            "counter",
+            "Passes through the control loop.",
+        );
+        let _ = writeln!(out, "pwm_led_loop_iterations_total {}", self.iterations);
+
+        let latency = &self.loop_latency;
+        header(
+            &mut out,
+            "pwm_led_loop_duration_seconds",
+            "histogram",
+            "Time spent reading, mapping and writing per loop pass.",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&latency.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "pwm_led_loop_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "pwm_led_loop_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            latency.count
+        );
+        let _ = writeln!(out, "pwm_led_loop_duration_seconds_sum {}", latency.sum);
+        let _ = writeln!(out, "pwm_led_loop_duration_seconds_count {}", latency.count);
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
@@ -0,0 +1,808 @@
+//! MQTT bridge: publishes the speed and every LED's duty, takes per-LED
+//! `set` commands as Home Assistant JSON lights and announces itself with
+//! retained discovery configs. Speaks MQTT 3.1.1 at QoS 0 and reconnects
+//! with a growing delay when the broker goes away.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::MAX_DUTY;
+use crate::frame::DutyFrame;
+use crate::service::{Event, EventKind, Mode, Service};
+
+// Largest packet we accept, far above anything this protocol needs.
+const MAX_PACKET: usize = 256 * 1024;
+
+// How often the bridge checks for a stop request while idle.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `[mqtt]` section of the config file. The bridge only runs when the
+/// section is present.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Broker address as `host:port`.
+    pub broker: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of the state and command topics.
+    pub topic_prefix: String,
+    /// Prefix the hub watches for discovery configs, empty to skip them.
+    pub discovery_prefix: String,
+    /// Seconds between two pings while idle, 0 to never ping.
+    pub keep_alive_secs: u16,
+    /// First delay before reconnecting, doubled after every failure.
+    pub reconnect_min_ms: u64,
+    pub reconnect_max_ms: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            broker: "localhost:1883".into(),
+            client_id: "pwmledd".into(),
+            username: None,
+            password: None,
+            topic_prefix: "pwm_led".into(),
+            discovery_prefix: "homeassistant".into(),
+            keep_alive_secs: 30,
+            reconnect_min_ms: 500,
+            reconnect_max_ms: 30_000,
+        }
+    }
+}
+
+impl MqttConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.broker.is_empty() || self.client_id.is_empty() {
+            return Err("broker and client_id must not be empty".into());
+        }
+        for (name, topic) in [
+            ("topic_prefix", &self.topic_prefix),
+            ("discovery_prefix", &self.discovery_prefix),
+        ] {
+            if topic.contains(['+', '#']) || topic.ends_with('/') {
+                return Err(format!("{} '{}' is not a plain topic", name, topic));
+            }
+        }
+        if self.topic_prefix.is_empty() {
+            return Err("topic_prefix must not be empty".into());
+        }
+        if self.reconnect_min_ms == 0 || self.reconnect_min_ms > self.reconnect_max_ms {
+            return Err(format!(
+                "reconnect_min_ms ({}) must be above 0 and at most reconnect_max_ms ({})",
+                self.reconnect_min_ms, self.reconnect_max_ms
+            ));
+        }
+        Ok(())
+    }
+
+    /// Topic `name` under the prefix, e.g. `pwm_led/led1/state`.
+    pub fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    // Discovery object id: the client id with anything unusual replaced.
+    fn node_id(&self) -> String {
+        self.client_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+}
+
+/// Message published on connect and, by the broker, when we drop off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+/// The MQTT 3.1.1 control packets the bridge and `FakeBroker` use. Publish
+/// is QoS 0 only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        keep_alive: u16,
+        will: Option<Will>,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    Subscribe {
+        id: u16,
+        topics: Vec<String>,
+    },
+    SubAck {
+        id: u16,
+        count: usize,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Packet {
+    /// Reads one packet, blocking until it is complete.
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Packet> {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+        let header = byte[0];
+
+        let mut length = 0;
+        for shift in (0..28).step_by(7) {
+            reader.read_exact(&mut byte)?;
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if length > MAX_PACKET {
+            return Err(invalid("packet too large"));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        Packet::decode(header, &body)
+    }
+
+    /// Writes the packet in one piece.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (header, body) = self.encode();
+        let mut packet = vec![header];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        packet.extend(body);
+        writer.write_all(&packet)?;
+        writer.flush()
+    }
+
+    fn encode(&self) -> (u8, Vec<u8>) {
+        let mut body = Vec::new();
+        match self {
+            Packet::Connect {
+                client_id,
+                keep_alive,
+                will,
+                username,
+                password,
+            } => {
+                put_str(&mut body, "MQTT");
+                body.push(4);
+                // Always a clean session
+                let mut flags = 0x02;
+                if let Some(will) = will {
+                    flags |= 0x04;
+                    if will.retain {
+                        flags |= 0x20;
+                    }
+                }
+                if password.is_some() {
+                    flags |= 0x40;
+                }
+                if username.is_some() {
+                    flags |= 0x80;
+                }
+                body.push(flags);
+                body.extend(keep_alive.to_be_bytes());
+                put_str(&mut body, client_id);
+                if let Some(will) = will {
+                    put_str(&mut body, &will.topic);
+                    put_bytes(&mut body, &will.payload);
+                }
+                if let Some(username) = username {
+                    put_str(&mut body, username);
+                }
+                if let Some(password) = password {
+                    put_str(&mut body, password);
+                }
+                (0x10, body)
+            }
+            Packet::ConnAck { code } => (0x20, vec![0, *code]),
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+            } => {
+                put_str(&mut body, topic);
+                body.extend(payload);
+                (0x30 | *retain as u8, body)
+            }
+            Packet::Subscribe { id, topics } => {
+                body.extend(id.to_be_bytes());
+                for topic in topics {
+                    put_str(&mut body, topic);
+                    body.push(0);
+                }
+                (0x82, body)
+            }
+            Packet::SubAck { id, count } => {
+                body.extend(id.to_be_bytes());
+                body.extend(std::iter::repeat_n(0, *count));
+                (0x90, body)
+            }
+            Packet::PingReq => (0xc0, body),
+            Packet::PingResp => (0xd0, body),
+            Packet::Disconnect => (0xe0, body),
+        }
+    }
+
+    fn decode(header: u8, body: &[u8]) -> io::Result<Packet> {
+        let mut cursor = Cursor { data: body };
+        let packet = match header >> 4 {
+            1 => {
+                if cursor.string()? != "MQTT" || cursor.byte()? != 4 {
+                    return Err(invalid("only MQTT 3.1.1 is supported"));
+                }
+                let flags = cursor.byte()?;
+                let keep_alive = cursor.u16()?;
+                let client_id = cursor.string()?;
+                let will = if flags & 0x04 != 0 {
+                    Some(Will {
+                        topic: cursor.string()?,
+                        payload: cursor.bytes()?.to_vec(),
+                        retain: flags & 0x20 != 0,
+                    })
+                } else {
+                    None
+                };
+                let username = if flags & 0x80 != 0 {
+                    Some(cursor.string()?)
+                } else {
+                    None
+                };
+                let password = if flags & 0x40 != 0 {
+                    Some(cursor.string()?)
+                } else {
+                    None
+                };
+                Packet::Connect {
+                    client_id,
+                    keep_alive,
+                    will,
+                    username,
+                    password,
+                }
+            }
+            2 => {
+                cursor.byte()?;
+                Packet::ConnAck {
+                    code: cursor.byte()?,
+                }
+            }
+            3 => {
+                let topic = cursor.string()?;
+                // QoS 1 and 2 carry a packet id we have no use for
+                if header & 0x06 != 0 {
+                    cursor.u16()?;
+                }
+                Packet::Publish {
+                    topic,
+                    payload: cursor.data.to_vec(),
+                    retain: header & 0x01 != 0,
+                }
+            }
+            8 => {
+                let id = cursor.u16()?;
+                let mut topics = Vec::new();
+                while !cursor.data.is_empty() {
+                    topics.push(cursor.string()?);
+                    cursor.byte()?;
+                }
+                Packet::Subscribe { id, topics }
+            }
+            9 => Packet::SubAck {
+                id: cursor.u16()?,
+                count: cursor.data.len(),
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            kind => return Err(invalid(&format!("unsupported packet type {}", kind))),
+        };
+        Ok(packet)
+    }
+}
+
+/// Whether `topic` matches the subscription `filter`, with `+` and `#`
+/// wildcards.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn put_str(body: &mut Vec<u8>, text: &str) {
+    put_bytes(body, text.as_bytes());
+}
+
+fn put_bytes(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend((bytes.len() as u16).to_be_bytes());
+    body.extend(bytes);
+}
+
+// Reads the fields of one packet body.
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(invalid("packet too short"));
+        }
+        let (head, tail) = self.data.split_at(count);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let length = self.u16()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid("topic is not UTF-8"))
+    }
+}
+
+/// Home Assistant JSON light command, e.g.
+/// `{"state": "ON", "brightness": 55}`.
+#[derive(Debug, Deserialize)]
+struct LightCommand {
+    state: Option<String>,
+    brightness: Option<u32>,
+}
+
+/// Runs the bridge on its own thread until `stop`.
+#[derive(Debug)]
+pub struct MqttBridge {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MqttBridge {
+    /// Starts publishing the state of `service` to the broker in `config`
+    /// and applying the commands received from it.
+    pub fn start(service: Service, config: MqttConfig) -> MqttBridge {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run(&service, &config, &stop))
+        };
+        MqttBridge {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Disconnects cleanly and waits for the bridge thread.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Keeps a session up, waiting longer after each failed attempt.
+fn run(service: &Service, config: &MqttConfig, stop: &AtomicBool) {
+    let min = Duration::from_millis(config.reconnect_min_ms);
+    let max = Duration::from_millis(config.reconnect_max_ms);
+    let mut delay = min;
+    while !stop.load(Ordering::Relaxed) {
+        let mut connected = false;
+        match session(service, config, stop, &mut connected) {
+            Ok(()) => return,
+            Err(err) => {
+                if connected {
+                    delay = min;
+                }
+                eprintln!(
+                    "MQTT: {}: {} (reconnecting in {:?})",
+                    config.broker, err, delay
+                );
+            }
+        }
+
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline && !stop.load(Ordering::Relaxed) {
+            thread::sleep(STOP_POLL.min(deadline - Instant::now()));
+        }
+        delay = (delay * 2).min(max);
+    }
+}
+
+// What was last published, so only changes go out.
+#[derive(Default)]
+struct Published {
+    speed: Option<f64>,
+    duties: Vec<Option<u32>>,
+    mode: Option<Mode>,
+}
+
+// One connection, from CONNECT until it breaks (`Err`) or the bridge or
+// the service stops (`Ok`).
+fn session(
+    service: &Service,
+    config: &MqttConfig,
+    stop: &AtomicBool,
+    connected: &mut bool,
+) -> io::Result<()> {
+    let addr = config
+        .broker
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "broker address not found"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let availability = config.topic("status");
+    Packet::Connect {
+        client_id: config.client_id.clone(),
+        keep_alive: config.keep_alive_secs,
+        will: Some(Will {
+            topic: availability.clone(),
+            payload: b"offline".to_vec(),
+            retain: true,
+        }),
+        username: config.username.clone(),
+        password: config.password.clone(),
+    }
+    .write(&mut stream)?;
+    match Packet::read(&mut stream)? {
+        Packet::ConnAck { code: 0 } => {}
+        Packet::ConnAck { code } => {
+            return Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("broker refused the connection (code {})", code),
+            ))
+        }
+        other => return Err(invalid(&format!("expected CONNACK, got {:?}", other))),
+    }
+    *connected = true;
+
+    // Subscribe to events before reading the status so no change is lost
+    let events = service.subscribe();
+    let status = service.status();
+    if !config.discovery_prefix.is_empty() {
+        for (topic, payload) in discovery(config, status.leds) {
+            publish(&mut stream, &topic, payload.as_bytes(), true)?;
+        }
+    }
+    publish(&mut stream, &availability, b"online", true)?;
+    Packet::Subscribe {
+        id: 1,
+        topics: vec![config.topic("+/set")],
+    }
+    .write(&mut stream)?;
+
+    let mut published = Published {
+        duties: vec![None; status.leds],
+        ..Published::default()
+    };
+    publish_changes(
+        &mut stream,
+        config,
+        &mut published,
+        status.speed,
+        status.duties.as_ref(),
+        status.mode,
+    )?;
+
+    let reader = {
+        let stream = stream.try_clone()?;
+        let service = service.clone();
+        let config = config.clone();
+        thread::spawn(move || read_commands(stream, &service, &config))
+    };
+    let result = publish_loop(
+        &mut stream,
+        service,
+        config,
+        stop,
+        &events,
+        &reader,
+        &mut published,
+    );
+
+    // Also wakes the reader if it is still waiting for packets
+    if result.is_ok() {
+        let _ = publish(&mut stream, &availability, b"offline", true);
+        let _ = Packet::Disconnect.write(&mut stream);
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = reader.join();
+    result
+}
+
+// Publishes state changes as the service reports them, pinging while idle.
+fn publish_loop(
+    stream: &mut TcpStream,
+    service: &Service,
+    config: &MqttConfig,
+    stop: &AtomicBool,
+    events: &Receiver<Event>,
+    reader: &JoinHandle<()>,
+    published: &mut Published,
+) -> io::Result<()> {
+    let keep_alive = Duration::from_secs(config.keep_alive_secs.into());
+    let mut last_sent = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        // Nothing may be written for a while, so the reader is what notices
+        // a dropped connection first
+        if reader.is_finished() {
+            return Err(io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "connection closed by the broker",
+            ));
+        }
+        match events.recv_timeout(STOP_POLL) {
+            Ok(event) => {
+                let (speed, duties) = match event.kind {
+                    EventKind::Speed => (event.speed, None),
+                    EventKind::Duties => (None, event.duties.as_ref()),
+                };
+                if publish_changes(
+                    stream,
+                    config,
+                    published,
+                    speed,
+                    duties,
+                    service.status().mode,
+                )? {
+                    last_sent = Instant::now();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if !keep_alive.is_zero() && last_sent.elapsed() >= keep_alive / 2 {
+            Packet::PingReq.write(stream)?;
+            last_sent = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+// Publishes whatever differs from what the broker already has. Returns
+// whether anything was sent.
+fn publish_changes(
+    stream: &mut TcpStream,
+    config: &MqttConfig,
+    published: &mut Published,
+    speed: Option<f64>,
+    duties: Option<&DutyFrame>,
+    mode: Mode,
+) -> io::Result<bool> {
+    let mut sent = false;
+    if let Some(speed) = speed.filter(|&speed| published.speed != Some(speed)) {
+        publish(
+            stream,
+            &config.topic("speed"),
+            speed.to_string().as_bytes(),
+            true,
+        )?;
+        published.speed = Some(speed);
+        sent = true;
+    }
+    for (led, &duty) in duties
+        .map(|frame| &frame[..])
+        .unwrap_or(&[])
+        .iter()
+        .enumerate()
+    {
+        if published.duties.get(led) == Some(&Some(duty)) {
+            continue;
+        }
+        let state = json!({
+            "state": if duty > 0 { "ON" } else { "OFF" },
+            "brightness": duty,
+        });
+        let topic = config.topic(&format!("led{}/state", led + 1));
+        publish(stream, &topic, state.to_string().as_bytes(), true)?;
+        if let Some(slot) = published.duties.get_mut(led) {
+            *slot = Some(duty);
+        }
+        sent = true;
+    }
+    if published.mode != Some(mode) {
+        let name = serde_json::to_value(mode)?;
+        let name = name.as_str().unwrap_or_default();
+        publish(stream, &config.topic("mode"), name.as_bytes(), true)?;
+        published.mode = Some(mode);
+        sent = true;
+    }
+    Ok(sent)
+}
+
+fn publish(stream: &mut TcpStream, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+    Packet::Publish {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+        retain,
+    }
+    .write(stream)
+}
+
+// Applies `<prefix>/ledN/set` and `<prefix>/mode/set` commands until the
+// connection breaks. A silent broker counts as broken after one and a half
+// keep-alive periods.
+fn read_commands(mut stream: TcpStream, service: &Service, config: &MqttConfig) {
+    let timeout = match config.keep_alive_secs {
+        0 => None,
+        secs => Some(Duration::from_millis(u64::from(secs) * 1500)),
+    };
+    if stream.set_read_timeout(timeout).is_err() {
+        return;
+    }
+    while let Ok(packet) = Packet::read(&mut stream) {
+        if let Packet::Publish { topic, payload, .. } = packet {
+            if let Err(err) = apply_command(service, config, &topic, &payload) {
+                eprintln!("MQTT: ignoring {}: {}", topic, err);
+            }
+        }
+    }
+    // Make the publishing side notice too
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn apply_command(
+    service: &Service,
+    config: &MqttConfig,
+    topic: &str,
+    payload: &[u8],
+) -> Result<(), String> {
+    let name = topic
+        .strip_prefix(&config.topic_prefix)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|rest| rest.strip_suffix("/set"))
+        .ok_or("not a command topic")?;
+    let text = std::str::from_utf8(payload).map_err(|_| "payload is not UTF-8")?;
+
+    if name == "mode" {
+        match text.trim() {
+            "auto" => service.resume(),
+            "manual" => service.hold(),
+            "paused" => service.pause(),
+            other => return Err(format!("unknown mode '{}'", other)),
+        };
+        return Ok(());
+    }
+
+    // LEDs are numbered from 1 in topics, like the ledN_duty files
+    let led = name
+        .strip_prefix("led")
+        .and_then(|number| number.parse::<usize>().ok())
+        .filter(|&led| led >= 1)
+        .ok_or_else(|| format!("unknown command topic '{}'", name))?;
+    let duty = parse_light_command(text)?;
+    service
+        .set_led(led - 1, duty)
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+// Accepts a JSON light command or a bare duty cycle.
+fn parse_light_command(text: &str) -> Result<u32, String> {
+    if let Ok(duty) = text.trim().parse::<u32>() {
+        return Ok(duty);
+    }
+    let command: LightCommand =
+        serde_json::from_str(text).map_err(|err| format!("bad command: {}", err))?;
+    match (command.state.as_deref(), command.brightness) {
+        (Some("OFF"), _) => Ok(0),
+        (_, Some(brightness)) => Ok(brightness),
+        (Some("ON"), None) => Ok(MAX_DUTY),
+        _ => Err("command needs a state or a brightness".into()),
+    }
+}
+
+// Retained discovery configs: a sensor for the speed, a JSON light per LED
+// and a select for the mode.
+fn discovery(config: &MqttConfig, leds: usize) -> Vec<(String, String)> {
+    let node = config.node_id();
+    let device = json!({
+        "identifiers": [node],
+        "name": "PWM LED controller",
+        "model": "pwm_led_controller",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let availability = config.topic("status");
+    let prefix = &config.discovery_prefix;
+
+    let mut configs = vec![(
+        format!("{}/sensor/{}/speed/config", prefix, node),
+        json!({
+            "name": "Button press speed",
+            "unique_id": format!("{}_speed", node),
+            "state_topic": config.topic("speed"),
+            "unit_of_measurement": "presses/s",
+            "state_class": "measurement",
+            "availability_topic": availability,
+            "device": device,
+        }),
+    )];
+    for led in 1..=leds {
+        configs.push((
+            format!("{}/light/{}/led{}/config", prefix, node, led),
+            json!({
+                "name": format!("LED {}", led),
+                "unique_id": format!("{}_led{}", node, led),
+                "schema": "json",
+                "state_topic": config.topic(&format!("led{}/state", led)),
+                "command_topic": config.topic(&format!("led{}/set", led)),
+                "brightness": true,
+                "brightness_scale": MAX_DUTY,
+                "availability_topic": availability,
+                "device": device,
+            }),
+        ));
+    }
+    configs.push((
+        format!("{}/select/{}/mode/config", prefix, node),
+        json!({
+            "name": "Mode",
+            "unique_id": format!("{}_mode", node),
+            "state_topic": config.topic("mode"),
+            "command_topic": config.topic("mode/set"),
+            "options": ["auto", "manual", "paused"],
+            "availability_topic": availability,
+            "device": device,
+        }),
+    ));
+    configs
+        .into_iter()
+        .map(|(topic, payload)| (topic, payload.to_string()))
+        .collect()
+}
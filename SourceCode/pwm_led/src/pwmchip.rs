@@ -0,0 +1,242 @@
+//! LEDs on hardware PWM channels of the kernel's PWM subsystem
+//! (`/sys/class/pwm/pwmchipN`), without the custom module's software PWM.
+//!
+//! Each LED is one channel of a chip. Duties are the same 0-100 values the
+//! module takes, written as a `duty_cycle` in nanoseconds of the channel's
+//! `period`. The subsystem knows nothing about buttons, so the speed comes
+//! from a separate `SpeedSource`.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::backend::{check_duty, check_frame, check_led, LedBackend};
+use crate::error::{ControllerError, Result, MAX_DUTY};
+use crate::frame::DutyFrame;
+use crate::speed::{SpeedFile, SpeedSource};
+use crate::sysfs::{read_attribute, SYSFS_PATH};
+use crate::watch::SpeedNotifier;
+
+/// Where the kernel lists the PWM chips.
+pub const PWM_CLASS_PATH: &str = "/sys/class/pwm";
+
+/// Default PWM period: 1 kHz, well above visible flicker.
+pub const DEFAULT_PERIOD_NS: u64 = 1_000_000;
+
+// How long a freshly exported channel may take to become writable, while
+// udev adjusts the permissions of its attributes.
+const EXPORT_SETTLE: Duration = Duration::from_millis(500);
+
+/// `[pwm]` section of the config file, used by `--backend pwmchip`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PwmConfig {
+    /// The chip's directory, e.g. `/sys/class/pwm/pwmchip0`.
+    pub chip: PathBuf,
+    /// Channel of the chip driving each LED, in LED order.
+    pub channels: Vec<u32>,
+    pub period_ns: u64,
+    /// File the speed is read from, as `SpeedFile` reads it.
+    pub speed: PathBuf,
+}
+
+impl Default for PwmConfig {
+    fn default() -> Self {
+        PwmConfig {
+            chip: Path::new(PWM_CLASS_PATH).join("pwmchip0"),
+            channels: vec![0, 1, 2],
+            period_ns: DEFAULT_PERIOD_NS,
+            speed: Path::new(SYSFS_PATH).join("button_speed"),
+        }
+    }
+}
+
+impl PwmConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.channels.is_empty() {
+            return Err("channels must list at least one channel".into());
+        }
+        for (i, channel) in self.channels.iter().enumerate() {
+            if self.channels[..i].contains(channel) {
+                return Err(format!("channel {} is listed twice", channel));
+            }
+        }
+        if self.period_ns == 0 {
+            return Err("period_ns must be above 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// Converts a 0-100 duty to nanoseconds of `period_ns`.
+pub fn duty_to_ns(duty: u32, period_ns: u64) -> u64 {
+    (u128::from(period_ns) * u128::from(duty) / u128::from(MAX_DUTY)) as u64
+}
+
+/// Converts a `duty_cycle` back to the nearest 0-100 duty.
+pub fn ns_to_duty(duty_ns: u64, period_ns: u64) -> u32 {
+    if period_ns == 0 {
+        return 0;
+    }
+    let duty = (u128::from(duty_ns) * u128::from(MAX_DUTY) + u128::from(period_ns) / 2)
+        / u128::from(period_ns);
+    duty.min(u128::from(MAX_DUTY)) as u32
+}
+
+/// LEDs on channels of one PWM chip, with the speed from `speed`.
+pub struct PwmChipBackend {
+    chip: PathBuf,
+    channels: Vec<u32>,
+    period_ns: u64,
+    speed: Box<dyn SpeedSource + Send>,
+}
+
+impl PwmChipBackend {
+    /// Exports each of `channels` that is not exported yet, sets its period
+    /// to `period_ns` and enables it. A freshly exported channel starts with
+    /// the LED off; one that is already set up keeps its duty.
+    ///
+    /// Channels stay exported and enabled when the backend is dropped, so
+    /// the LEDs keep their duties like with the kernel module.
+    pub fn open(
+        chip: impl Into<PathBuf>,
+        channels: &[u32],
+        period_ns: u64,
+        speed: Box<dyn SpeedSource + Send>,
+    ) -> Result<Self> {
+        let chip = chip.into();
+        if !chip.is_dir() {
+            return Err(ControllerError::DeviceMissing { path: chip });
+        }
+        let backend = PwmChipBackend {
+            chip,
+            channels: channels.to_vec(),
+            period_ns,
+            speed,
+        };
+        for &channel in channels {
+            backend.set_up(channel)?;
+        }
+        Ok(backend)
+    }
+
+    /// Opens the chip and channels of a `[pwm]` section, reading the speed
+    /// from its `speed` file.
+    pub fn from_config(config: &PwmConfig) -> Result<Self> {
+        Self::open(
+            &config.chip,
+            &config.channels,
+            config.period_ns,
+            Box::new(SpeedFile::new(&config.speed)),
+        )
+    }
+
+    /// The chip's directory.
+    pub fn path(&self) -> &Path {
+        &self.chip
+    }
+
+    pub fn period_ns(&self) -> u64 {
+        self.period_ns
+    }
+
+    fn channel_path(&self, channel: u32) -> PathBuf {
+        self.chip.join(format!("pwm{}", channel))
+    }
+
+    fn set_up(&self, channel: u32) -> Result<()> {
+        let dir = self.channel_path(channel);
+        if dir.is_dir() {
+            return self.configure(channel);
+        }
+        write_attribute(&self.chip.join("export"), channel)?;
+
+        // The attributes may not be writable for a moment after the export
+        let deadline = Instant::now() + EXPORT_SETTLE;
+        loop {
+            match self.configure(channel) {
+                Err(
+                    ControllerError::DeviceMissing { .. }
+                    | ControllerError::PermissionDenied { .. },
+                ) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                result => return result,
+            }
+        }
+    }
+
+    // Only what differs is written, so a lit LED does not flicker. The duty
+    // goes to 0 only if it is above the new period, which cannot drop below
+    // it.
+    fn configure(&self, channel: u32) -> Result<()> {
+        let dir = self.channel_path(channel);
+        let period: u64 = read_attribute(&dir.join("period"))?;
+        let enabled: u64 = read_attribute(&dir.join("enable"))?;
+        if period == self.period_ns && enabled == 1 {
+            return Ok(());
+        }
+        if period != self.period_ns {
+            let duty: u64 = read_attribute(&dir.join("duty_cycle"))?;
+            if duty > self.period_ns {
+                write_attribute(&dir.join("duty_cycle"), 0)?;
+            }
+            write_attribute(&dir.join("period"), self.period_ns)?;
+        }
+        write_attribute(&dir.join("enable"), 1)
+    }
+}
+
+impl fmt::Debug for PwmChipBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PwmChipBackend")
+            .field("chip", &self.chip)
+            .field("channels", &self.channels)
+            .field("period_ns", &self.period_ns)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn write_attribute(path: &Path, value: impl fmt::Display) -> Result<()> {
+    fs::write(path, value.to_string()).map_err(|e| ControllerError::from_io(path, e))
+}
+
+impl LedBackend for PwmChipBackend {
+    fn led_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.speed.read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.channels.len())?;
+        let path = self.channel_path(self.channels[led]).join("duty_cycle");
+        Ok(ns_to_duty(read_attribute(&path)?, self.period_ns))
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.channels.len())?;
+        check_duty(led, duty)?;
+        let path = self.channel_path(self.channels[led]).join("duty_cycle");
+        write_attribute(&path, duty_to_ns(duty, self.period_ns))
+    }
+
+    // One write per channel, like sysfs; validated first so a bad value
+    // cannot leave the frame half applied.
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.channels.len())?;
+        for (led, &duty) in frame.iter().enumerate() {
+            self.write_duty(led, duty)?;
+        }
+        Ok(())
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        self.speed.speed_notifier()
+    }
+}
@@ -0,0 +1,522 @@
+//! The control loop as a background service that other threads can query
+//! and steer: manual overrides, profile switches and pausing.
+
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{check_frame, check_led, LedBackend};
+use crate::config::{Config, DEFAULT_PROFILE};
+use crate::control::{check_channels, ControlState};
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+use crate::metrics::Metrics;
+use crate::shutdown::ShutdownPolicy;
+use crate::watch::SpeedNotifier;
+
+/// Who decides the duty cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// The speed is mapped with the active profile.
+    Auto,
+    /// A fixed frame set by a client overrides the mapping.
+    Manual,
+    /// Nothing is written; the LEDs keep their last duties.
+    Paused,
+}
+
+/// What the service is doing, as reported to clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub mode: Mode,
+    /// Active mapping profile.
+    pub profile: String,
+    /// Every profile that can be switched to.
+    pub profiles: Vec<String>,
+    pub leds: usize,
+    /// Last raw speed reading, in presses/second.
+    pub speed: Option<f64>,
+    /// Last speed after smoothing.
+    pub filtered_speed: Option<f64>,
+    /// Duties last written, or read back at startup.
+    pub duties: Option<DutyFrame>,
+    /// Where the LEDs are heading.
+    pub target: Option<DutyFrame>,
+    /// The manual override, kept while paused.
+    #[serde(rename = "override")]
+    pub manual: Option<DutyFrame>,
+    /// Last backend error, cleared by the next good speed reading.
+    pub error: Option<String>,
+}
+
+/// Events a subscriber can fall behind by before new ones are dropped.
+pub const EVENT_BACKLOG: usize = 256;
+
+/// What made the loop publish an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A new speed reading.
+    Speed,
+    /// New duties written to the LEDs.
+    Duties,
+}
+
+/// Pushed to subscribers on every speed reading and duty write, carrying
+/// the latest values of both.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub speed: Option<f64>,
+    pub filtered_speed: Option<f64>,
+    pub duties: Option<DutyFrame>,
+}
+
+// State shared between the loop thread and the handles.
+#[derive(Debug)]
+struct State {
+    status: Status,
+    metrics: Metrics,
+    // Bumped by every command so the loop can tell it has to react
+    generation: u64,
+    // Set when the backend signals a speed change before the next reading
+    speed_changed: bool,
+    stopping: bool,
+    // Applied by the loop on its way out
+    on_stop: ShutdownPolicy,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    subscribers: Mutex<Vec<SyncSender<Event>>>,
+}
+
+/// Handle to a running control loop. Clones control the same loop.
+#[derive(Debug, Clone)]
+pub struct Service {
+    shared: Arc<Shared>,
+    config: Arc<Config>,
+}
+
+impl Service {
+    /// Starts the control loop on its own thread, driving `backend` with
+    /// `config`. Starts in auto mode with the `default` profile.
+    ///
+    /// If the backend can signal speed changes, a second thread waits for
+    /// them so the speed is read straight away.
+    pub fn start(mut backend: Box<dyn LedBackend + Send>, config: Config) -> Result<Service> {
+        check_channels(&*backend, &config.mapping)?;
+        let leds = backend.led_count();
+        let backend_notifier = backend.speed_notifier();
+
+        let status = Status {
+            mode: Mode::Auto,
+            profile: DEFAULT_PROFILE.to_string(),
+            profiles: config.profile_names(),
+            leds,
+            speed: None,
+            filtered_speed: None,
+            duties: None,
+            target: None,
+            manual: None,
+            error: None,
+        };
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                status,
+                metrics: Metrics::default(),
+                generation: 0,
+                speed_changed: false,
+                stopping: false,
+                on_stop: ShutdownPolicy::Leave,
+            }),
+            wake: Condvar::new(),
+            thread: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+        });
+        let config = Arc::new(config);
+
+        let handle = {
+            let shared = Arc::clone(&shared);
+            let config = Arc::clone(&config);
+            thread::Builder::new()
+                .name("pwm_led-control".into())
+                .spawn(move || control_loop(backend, &config, &shared))
+                .map_err(|err| ControllerError::from_io("<control thread>", err))?
+        };
+        *shared.thread.lock().unwrap() = Some(handle);
+
+        if let Some(notifier) = backend_notifier {
+            let shared = Arc::clone(&shared);
+            let timeout = config.daemon.poll_interval();
+            let spawned = thread::Builder::new()
+                .name("pwm_led-watch".into())
+                .spawn(move || watch_speed(notifier, &shared, timeout));
+            // The loop still polls without it
+            if let Err(err) = spawned {
+                eprintln!("Error: cannot watch the speed: {} (polling instead)", err);
+            }
+        }
+        Ok(Service { shared, config })
+    }
+
+    /// The config the service was started with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Current state of the loop.
+    pub fn status(&self) -> Status {
+        self.lock().status.clone()
+    }
+
+    /// Error counts and loop timings since the service started.
+    pub fn metrics(&self) -> Metrics {
+        self.lock().metrics.clone()
+    }
+
+    /// Overrides the mapping with `frame` until `clear_override` or
+    /// `resume`.
+    pub fn set_override(&self, frame: DutyFrame) -> Result<Status> {
+        let mut state = self.lock();
+        check_frame(&frame, state.status.leds)?;
+        state.status.mode = Mode::Manual;
+        state.status.manual = Some(frame);
+        Ok(self.notify(state))
+    }
+
+    /// Overrides one LED, keeping the others at the current override or,
+    /// without one, at the duties they show now.
+    pub fn set_led(&self, led: usize, duty: u32) -> Result<Status> {
+        let mut state = self.lock();
+        check_led(led, state.status.leds)?;
+        let mut frame = held_frame(&state.status);
+        frame[led] = duty;
+        check_frame(&frame, state.status.leds)?;
+        state.status.mode = Mode::Manual;
+        state.status.manual = Some(frame);
+        Ok(self.notify(state))
+    }
+
+    /// Switches to manual mode, holding the current override or, without
+    /// one, the duties the LEDs show now.
+    pub fn hold(&self) -> Status {
+        let mut state = self.lock();
+        state.status.manual = Some(held_frame(&state.status));
+        state.status.mode = Mode::Manual;
+        self.notify(state)
+    }
+
+    /// Drops the manual override and goes back to auto mode.
+    pub fn clear_override(&self) -> Status {
+        let mut state = self.lock();
+        state.status.mode = Mode::Auto;
+        state.status.manual = None;
+        self.notify(state)
+    }
+
+    /// Maps the speed with the profile called `name` from now on.
+    pub fn set_profile(&self, name: &str) -> Result<Status> {
+        if self.config.profile(name).is_none() {
+            return Err(ControllerError::UnknownProfile(name.to_string()));
+        }
+        let mut state = self.lock();
+        state.status.profile = name.to_string();
+        Ok(self.notify(state))
+    }
+
+    /// Switches to `mode` and, if given, to the mapping profile `profile`
+    /// as one change: nothing is applied unless all of it is valid.
+    /// `manual` is the override for manual mode, which otherwise holds the
+    /// current duties; the other modes take none.
+    pub fn switch_mode(
+        &self,
+        mode: Mode,
+        manual: Option<DutyFrame>,
+        profile: Option<&str>,
+    ) -> Result<Status> {
+        if let Some(name) = profile {
+            if self.config.profile(name).is_none() {
+                return Err(ControllerError::UnknownProfile(name.to_string()));
+            }
+        }
+        let mut state = self.lock();
+        state.status.manual = match (mode, manual) {
+            (Mode::Manual, Some(frame)) => {
+                check_frame(&frame, state.status.leds)?;
+                Some(frame)
+            }
+            (Mode::Manual, None) => Some(held_frame(&state.status)),
+            (_, Some(_)) => {
+                return Err(ControllerError::Unsupported(
+                    "duties are only taken in manual mode",
+                ))
+            }
+            (Mode::Auto, None) => None,
+            // The override is kept while paused
+            (Mode::Paused, None) => state.status.manual.take(),
+        };
+        if let Some(name) = profile {
+            state.status.profile = name.to_string();
+        }
+        state.status.mode = mode;
+        Ok(self.notify(state))
+    }
+
+    /// Stops writing duties; the LEDs keep what they show now.
+    pub fn pause(&self) -> Status {
+        let mut state = self.lock();
+        state.status.mode = Mode::Paused;
+        self.notify(state)
+    }
+
+    /// Resumes the automatic mapping, dropping any manual override.
+    pub fn resume(&self) -> Status {
+        self.clear_override()
+    }
+
+    /// Receives an `Event` for every speed reading and duty write from now
+    /// on. A receiver that falls `EVENT_BACKLOG` events behind misses the
+    /// newer ones until it catches up; dropping it unsubscribes.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BACKLOG);
+        self.shared.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Stops the loop, waits for its thread to finish and disconnects the
+    /// event subscribers. The LEDs keep their last duties.
+    pub fn stop(&self) {
+        let mut state = self.lock();
+        state.stopping = true;
+        self.notify(state);
+        if let Some(handle) = self.shared.thread.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+        // Ends every event stream
+        self.shared.subscribers.lock().unwrap().clear();
+    }
+
+    /// Stops the loop like `stop` once the current write is done, then
+    /// leaves the LEDs as the `[shutdown]` policy says.
+    ///
+    /// Gives up after the `[shutdown]` timeout with `ShutdownTimedOut`,
+    /// leaving the loop thread stuck on the device behind.
+    pub fn shutdown(&self) -> Result<()> {
+        let timeout = self.config.shutdown.timeout();
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        state.stopping = true;
+        state.on_stop = self.config.shutdown.policy;
+        self.notify(state);
+
+        let handle = self.shared.thread.lock().unwrap().take();
+        if let Some(handle) = handle {
+            while !handle.is_finished() {
+                if Instant::now() >= deadline {
+                    return Err(ControllerError::ShutdownTimedOut { timeout });
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            let _ = handle.join();
+        }
+        self.shared.subscribers.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+
+    // Wakes the loop after a command changed the state.
+    fn notify(&self, mut state: MutexGuard<'_, State>) -> Status {
+        state.generation += 1;
+        self.shared.wake.notify_all();
+        state.status.clone()
+    }
+}
+
+// The frame a manual change starts from: the override, else the duties.
+fn held_frame(status: &Status) -> DutyFrame {
+    status
+        .manual
+        .clone()
+        .or_else(|| status.duties.clone())
+        .unwrap_or_else(|| DutyFrame::off(status.leds))
+}
+
+// What the loop needs from the shared state on each pass.
+struct Command {
+    mode: Mode,
+    profile: String,
+    manual: Option<DutyFrame>,
+    generation: u64,
+    speed_changed: bool,
+}
+
+fn control_loop(mut backend: Box<dyn LedBackend + Send>, config: &Config, shared: &Shared) {
+    // Fade from whatever the LEDs show now, if the backend can tell us
+    let initial = backend.read_duties().ok();
+    if let Some(frame) = &initial {
+        shared.state.lock().unwrap().status.duties = Some(frame.clone());
+    }
+    let mut control = ControlState::new(config, config.daemon.poll_interval(), initial.clone());
+
+    let mut profile = DEFAULT_PROFILE.to_string();
+    let mut seen = 0;
+    loop {
+        let command = {
+            let mut state = shared.state.lock().unwrap();
+            if state.stopping {
+                let policy = state.on_stop;
+                drop(state);
+                let applied = policy.apply(&mut *backend, initial.as_ref());
+                if let Err(err) = applied.and(backend.release()) {
+                    eprintln!("Error: {}", err);
+                }
+                return;
+            }
+            Command {
+                mode: state.status.mode,
+                profile: state.status.profile.clone(),
+                manual: state.status.manual.clone(),
+                generation: state.generation,
+                speed_changed: mem::take(&mut state.speed_changed),
+            }
+        };
+
+        // React to a new command straight away instead of at the next read
+        let now = Instant::now();
+        let started = now;
+        if command.generation != seen || command.speed_changed {
+            seen = command.generation;
+            control.read_now(now);
+        }
+        if command.profile != profile {
+            control.set_mapping(config.profile(&command.profile).unwrap_or(&config.mapping));
+            profile = command.profile;
+        }
+
+        if control.until_read(now).is_none() {
+            match backend.read_speed() {
+                Ok(speed) => {
+                    let reading = control.observe(speed, now);
+                    if command.mode == Mode::Auto {
+                        control.retarget(reading.target, now);
+                    }
+                    let mut state = shared.state.lock().unwrap();
+                    state.status.speed = Some(speed);
+                    state.status.filtered_speed = Some(reading.filtered_speed);
+                    state.status.error = None;
+                    publish(shared, &state.status, EventKind::Speed);
+                }
+                Err(err) => {
+                    control.skip_read(now);
+                    record_error(shared, err, |metrics| &mut metrics.read_errors);
+                }
+            }
+        }
+
+        if let (Mode::Manual, Some(manual)) = (command.mode, &command.manual) {
+            if control.target() != Some(manual) {
+                control.retarget(manual.clone(), now);
+            }
+        }
+
+        if command.mode != Mode::Paused {
+            if let Some(frame) = control.next_frame(now) {
+                match backend.write_duties(&frame) {
+                    Ok(()) => {
+                        let mut state = shared.state.lock().unwrap();
+                        state.status.duties = Some(frame);
+                        publish(shared, &state.status, EventKind::Duties);
+                    }
+                    Err(err) => record_error(shared, err, |metrics| &mut metrics.write_errors),
+                }
+            }
+        }
+
+        // Sleep until the next fade step or speed reading, or a command
+        let now = Instant::now();
+        let timeout = if command.mode == Mode::Paused {
+            control.until_read(now)
+        } else {
+            control.wait(now)
+        }
+        .unwrap_or_default();
+        let mut state = shared.state.lock().unwrap();
+        state.status.target = control.target().cloned();
+        state.metrics.iterations += 1;
+        state
+            .metrics
+            .loop_latency
+            .observe(started.elapsed().as_secs_f64());
+        if state.generation == seen && !state.speed_changed && !state.stopping && !timeout.is_zero()
+        {
+            let _ = shared.wake.wait_timeout(state, timeout);
+        }
+    }
+}
+
+// Wakes the loop whenever the backend signals a speed change, until the
+// service stops or the notifier fails. `timeout` bounds how long a stopped
+// service keeps this thread around.
+fn watch_speed(mut notifier: Box<dyn SpeedNotifier>, shared: &Shared, timeout: Duration) {
+    loop {
+        let changed = match notifier.wait(timeout) {
+            Ok(changed) => changed,
+            Err(err) => {
+                eprintln!("Error: {} (polling the speed instead)", err);
+                return;
+            }
+        };
+        let mut state = shared.state.lock().unwrap();
+        if state.stopping {
+            return;
+        }
+        if changed {
+            state.speed_changed = true;
+            shared.wake.notify_all();
+        }
+    }
+}
+
+// Keeps the loop going after a failed read or write; the error shows up in
+// the status until the next good reading and is counted in `counter`.
+fn record_error(shared: &Shared, err: ControllerError, counter: fn(&mut Metrics) -> &mut u64) {
+    eprintln!("Error: {}", err);
+    let mut state = shared.state.lock().unwrap();
+    state.status.error = Some(err.to_string());
+    *counter(&mut state.metrics) += 1;
+}
+
+// Sends an event to every subscriber, forgetting the ones that hung up.
+fn publish(shared: &Shared, status: &Status, kind: EventKind) {
+    let mut subscribers = shared.subscribers.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let event = Event {
+        kind,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64),
+        speed: status.speed,
+        filtered_speed: status.filtered_speed,
+        duties: status.duties.clone(),
+    };
+    subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+        Ok(()) | Err(TrySendError::Full(_)) => true,
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
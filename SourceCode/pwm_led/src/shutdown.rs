@@ -0,0 +1,183 @@
+//! Stopping cleanly on SIGINT and SIGTERM.
+//!
+//! The clients block both signals before starting any thread and wait for
+//! them with `sigwait` on a thread of their own, so no code runs in a
+//! signal handler. Once one arrives the control loop finishes the frame it
+//! is writing, the `[shutdown]` policy decides what the LEDs show
+//! afterwards, and the process exits even if the device stops answering.
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::backend::LedBackend;
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+
+/// What the LEDs show once the control loop has stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownPolicy {
+    /// Every LED off.
+    #[default]
+    Off,
+    /// The duties the LEDs showed when the loop started, or off if the
+    /// backend could not report them.
+    Restore,
+    /// Whatever was written last.
+    Leave,
+}
+
+impl ShutdownPolicy {
+    /// The frame to write on the way out, if any. `initial` is what the
+    /// LEDs showed when the loop started.
+    pub fn final_frame(self, leds: usize, initial: Option<&DutyFrame>) -> Option<DutyFrame> {
+        match self {
+            ShutdownPolicy::Off => Some(DutyFrame::off(leds)),
+            ShutdownPolicy::Restore => {
+                Some(initial.cloned().unwrap_or_else(|| DutyFrame::off(leds)))
+            }
+            ShutdownPolicy::Leave => None,
+        }
+    }
+
+    /// Writes the final frame to `backend`.
+    pub fn apply<B: LedBackend + ?Sized>(
+        self,
+        backend: &mut B,
+        initial: Option<&DutyFrame>,
+    ) -> Result<()> {
+        match self.final_frame(backend.led_count(), initial) {
+            Some(frame) => backend.write_duties(&frame),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `[shutdown]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    pub policy: ShutdownPolicy,
+    /// Time allowed to finish the current write and apply the policy before
+    /// exiting anyway.
+    pub timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            policy: ShutdownPolicy::Off,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.timeout_ms == 0 {
+            return Err("timeout_ms must be above 0".into());
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// SIGINT and SIGTERM, blocked so they can be waited for with `wait`.
+pub struct ShutdownSignals {
+    set: libc::sigset_t,
+}
+
+impl ShutdownSignals {
+    /// Blocks SIGINT and SIGTERM in the calling thread. Threads keep the
+    /// mask they were started with, so call it before starting any.
+    pub fn block() -> io::Result<Self> {
+        // SAFETY: `set` is initialised by `sigemptyset` before any other use
+        let set = unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+            set
+        };
+        // SAFETY: `set` is a valid signal set and the old mask is not wanted
+        let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        Ok(ShutdownSignals { set })
+    }
+
+    /// Waits for one of the signals and returns its number.
+    pub fn wait(&self) -> io::Result<i32> {
+        let mut signal = 0;
+        // SAFETY: both pointers are valid for the duration of the call
+        let err = unsafe { libc::sigwait(&self.set, &mut signal) };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        Ok(signal)
+    }
+}
+
+/// Name of a signal `ShutdownSignals::wait` returns, for messages.
+pub fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        _ => "signal",
+    }
+}
+
+/// Runs `work` on its own thread, handing it a flag that is set once
+/// `shutdown` returns; `work` should then wind down and return.
+///
+/// Returns what `work` returned, or `ShutdownTimedOut` if it is still busy
+/// `timeout` after the flag was set. Its thread is then left running, for
+/// the process to exit regardless.
+pub fn run_until_shutdown<S, W>(shutdown: S, timeout: Duration, work: W) -> Result<()>
+where
+    S: FnOnce() + Send + 'static,
+    W: FnOnce(&AtomicBool) -> Result<()> + Send + 'static,
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    // `None` once the timeout has passed
+    let (sender, outcome) = mpsc::channel();
+
+    let spawned = {
+        let stop = Arc::clone(&stop);
+        let sender = sender.clone();
+        thread::Builder::new()
+            .name("pwm_led-shutdown".into())
+            .spawn(move || {
+                shutdown();
+                stop.store(true, Ordering::SeqCst);
+                thread::sleep(timeout);
+                let _ = sender.send(None);
+            })
+    };
+    spawned.map_err(|err| ControllerError::from_io("<shutdown thread>", err))?;
+
+    thread::Builder::new()
+        .name("pwm_led-control".into())
+        .spawn(move || {
+            let _ = sender.send(Some(work(&stop)));
+        })
+        .map_err(|err| ControllerError::from_io("<control thread>", err))?;
+
+    match outcome.recv() {
+        Ok(Some(result)) => result,
+        Ok(None) | Err(_) => Err(ControllerError::ShutdownTimedOut { timeout }),
+    }
+}
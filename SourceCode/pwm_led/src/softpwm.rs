@@ -0,0 +1,456 @@
+//! Software PWM on GPIO lines from userspace, toggling them through a line
+//! request of `/dev/gpiochipN` the way the module's hrtimer toggles the LED
+//! pins.
+//!
+//! Each LED has its own duty and period. A dedicated thread sleeps until the
+//! next edge of any LED and sets every line due at that moment in one call,
+//! keeping how late it woke up as jitter statistics. A new duty takes effect
+//! at the start of the LED's next period, so a cycle is never cut short.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::backend::{check_duty, check_frame, check_led, LedBackend};
+use crate::error::{ControllerError, Result, MAX_DUTY};
+use crate::frame::DutyFrame;
+use crate::gpio::{LineSink, OutputLines, GPIO_CHIP_PATH};
+use crate::metrics::Histogram;
+use crate::speed::{SpeedFile, SpeedSource};
+use crate::sysfs::SYSFS_PATH;
+use crate::watch::SpeedNotifier;
+
+/// Default PWM period, the module's 10 ms.
+pub const DEFAULT_PERIOD_US: u64 = 10_000;
+
+/// Shortest period accepted, below which sleeping threads cannot keep up.
+pub const MIN_PERIOD_US: u64 = 100;
+
+const MIN_PERIOD: Duration = Duration::from_micros(MIN_PERIOD_US);
+
+/// `[softpwm]` section of the config file, used by `--backend softpwm`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoftPwmConfig {
+    /// GPIO chip the LEDs are on.
+    pub chip: PathBuf,
+    /// Line offset of each LED, in LED order.
+    pub lines: Vec<u32>,
+    /// PWM period of every LED, in microseconds.
+    pub period_us: u64,
+    /// SCHED_FIFO priority (1-99) of the PWM thread; normal scheduling
+    /// without one.
+    pub priority: Option<u8>,
+    /// File the speed is read from, as `SpeedFile` reads it.
+    pub speed: PathBuf,
+}
+
+impl Default for SoftPwmConfig {
+    fn default() -> Self {
+        SoftPwmConfig {
+            chip: PathBuf::from(GPIO_CHIP_PATH),
+            lines: vec![17, 27, 22],
+            period_us: DEFAULT_PERIOD_US,
+            priority: None,
+            speed: Path::new(SYSFS_PATH).join("button_speed"),
+        }
+    }
+}
+
+impl SoftPwmConfig {
+    /// Checks the settings, returning a description of the problem.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.lines.is_empty() || self.lines.len() > 64 {
+            return Err("lines must list between 1 and 64 lines".into());
+        }
+        for (i, line) in self.lines.iter().enumerate() {
+            if self.lines[..i].contains(line) {
+                return Err(format!("line {} is listed twice", line));
+            }
+        }
+        if self.period_us < MIN_PERIOD_US {
+            return Err(format!("period_us must be at least {}", MIN_PERIOD_US));
+        }
+        if let Some(priority) = self.priority {
+            if !(1..=99).contains(&priority) {
+                return Err("priority must be between 1 and 99".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The period as a `Duration`.
+    pub fn period(&self) -> Duration {
+        Duration::from_micros(self.period_us)
+    }
+}
+
+/// How late the PWM thread woke up for the edges it was due to set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JitterStats {
+    /// Lateness of each wake-up, in seconds.
+    pub histogram: Histogram,
+    /// Latest wake-up seen.
+    pub max: Duration,
+}
+
+impl JitterStats {
+    /// Number of wake-ups measured.
+    pub fn wakeups(&self) -> u64 {
+        self.histogram.count
+    }
+
+    /// Average lateness, zero before the first wake-up.
+    pub fn mean(&self) -> Duration {
+        if self.histogram.count == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(self.histogram.sum / self.histogram.count as f64)
+        }
+    }
+
+    fn observe(&mut self, late: Duration) {
+        self.histogram.observe(late.as_secs_f64());
+        self.max = self.max.max(late);
+    }
+}
+
+// What the caller and the PWM thread share.
+#[derive(Debug)]
+struct Shared {
+    duties: Vec<AtomicU32>,
+    periods_ns: Vec<AtomicU64>,
+    stop: AtomicBool,
+    jitter: Mutex<JitterStats>,
+    // Why the thread gave up, reported by the next `set_duties`
+    error: Mutex<Option<ControllerError>>,
+}
+
+/// A running software PWM engine. The lines are driven low and the thread
+/// ends when it is stopped or dropped.
+#[derive(Debug)]
+pub struct SoftPwm {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    realtime: bool,
+}
+
+impl SoftPwm {
+    /// Starts toggling the lines of `sink`, one per entry of `periods`, all
+    /// at duty 0. Periods below `MIN_PERIOD_US` are raised to it. With a
+    /// `priority`, the thread asks for SCHED_FIFO at that priority and keeps
+    /// normal scheduling if it is not allowed to.
+    pub fn start(
+        mut sink: Box<dyn LineSink>,
+        periods: &[Duration],
+        priority: Option<u8>,
+    ) -> Result<Self> {
+        if periods.is_empty() || periods.len() > 64 {
+            return Err(ControllerError::Unsupported(
+                "software PWM drives between 1 and 64 lines",
+            ));
+        }
+        if sink.lines() != periods.len() {
+            return Err(ControllerError::ChannelMismatch {
+                expected: sink.lines(),
+                found: periods.len(),
+            });
+        }
+        sink.set_values(0, line_mask(periods.len()))?;
+        let shared = Arc::new(Shared {
+            duties: periods.iter().map(|_| AtomicU32::new(0)).collect(),
+            periods_ns: periods
+                .iter()
+                .map(|period| AtomicU64::new(period.as_nanos() as u64))
+                .collect(),
+            stop: AtomicBool::new(false),
+            jitter: Mutex::new(JitterStats::default()),
+            error: Mutex::new(None),
+        });
+
+        let (started, realtime) = mpsc::channel();
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("pwm_led-softpwm".into())
+                .spawn(move || {
+                    let realtime = match priority.map(set_fifo_priority) {
+                        Some(Err(err)) => {
+                            eprintln!(
+                                "Warning: no SCHED_FIFO priority for the PWM thread: {}",
+                                err
+                            );
+                            false
+                        }
+                        Some(Ok(())) => true,
+                        None => false,
+                    };
+                    let _ = started.send(realtime);
+                    toggle_lines(&mut *sink, &shared);
+                })
+                .map_err(|e| ControllerError::Io {
+                    path: "<softpwm thread>".into(),
+                    source: e,
+                })?
+        };
+        Ok(SoftPwm {
+            shared,
+            thread: Some(thread),
+            realtime: realtime.recv().unwrap_or(false),
+        })
+    }
+
+    /// Number of lines driven.
+    pub fn lines(&self) -> usize {
+        self.shared.duties.len()
+    }
+
+    /// Whether the thread runs with SCHED_FIFO priority.
+    pub fn realtime(&self) -> bool {
+        self.realtime
+    }
+
+    /// Duty of every line, as last set.
+    pub fn duties(&self) -> DutyFrame {
+        self.shared
+            .duties
+            .iter()
+            .map(|duty| duty.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Sets the duty of every line, from its next period on. Fails with the
+    /// error that stopped the thread, if one did.
+    pub fn set_duties(&self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.lines())?;
+        if let Some(err) = self.shared.error.lock().unwrap().take() {
+            return Err(err);
+        }
+        for (slot, &duty) in self.shared.duties.iter().zip(frame.iter()) {
+            slot.store(duty, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Changes the period of one line, from its next period on. Periods
+    /// below `MIN_PERIOD_US` are raised to it.
+    pub fn set_period(&self, line: usize, period: Duration) -> Result<()> {
+        check_led(line, self.lines())?;
+        self.shared.periods_ns[line].store(period.as_nanos() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Jitter measured so far.
+    pub fn jitter(&self) -> JitterStats {
+        self.shared.jitter.lock().unwrap().clone()
+    }
+
+    /// Drives every line low and waits for the thread to end.
+    pub fn stop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SoftPwm {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn line_mask(lines: usize) -> u64 {
+    u64::MAX >> (64 - lines)
+}
+
+fn set_fifo_priority(priority: u8) -> io::Result<()> {
+    let param = libc::sched_param {
+        sched_priority: priority.into(),
+    };
+    // SAFETY: `param` outlives the call, which only affects this thread
+    let ret =
+        unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(ret))
+    }
+}
+
+// One line's position in its current period.
+struct Channel {
+    cycle_start: Instant,
+    period: Duration,
+    high: bool,
+    // Next edge: the end of the on time while high, else the next period
+    next: Instant,
+}
+
+impl Channel {
+    // Moves past every edge due by `now`.
+    fn advance(&mut self, now: Instant, line: usize, shared: &Shared) {
+        while self.next <= now {
+            if self.high && self.next < self.cycle_start + self.period {
+                self.high = false;
+                self.next = self.cycle_start + self.period;
+                continue;
+            }
+            // A new period, picking up the latest duty and period. One
+            // missed by more than a period starts over from now.
+            self.cycle_start = if now - self.next > self.period {
+                now
+            } else {
+                self.next
+            };
+            let period_ns = shared.periods_ns[line].load(Ordering::Relaxed);
+            self.period = Duration::from_nanos(period_ns).max(MIN_PERIOD);
+            let duty = shared.duties[line].load(Ordering::Relaxed).min(MAX_DUTY);
+            self.high = duty > 0;
+            self.next = if duty > 0 && duty < MAX_DUTY {
+                self.cycle_start + self.period * duty / MAX_DUTY
+            } else {
+                self.cycle_start + self.period
+            };
+        }
+    }
+}
+
+// The PWM thread: sets every line due, then sleeps until the next edge.
+fn toggle_lines(sink: &mut dyn LineSink, shared: &Shared) {
+    let start = Instant::now();
+    let mut channels: Vec<Channel> = (0..shared.duties.len())
+        .map(|_| Channel {
+            cycle_start: start,
+            period: Duration::ZERO,
+            high: false,
+            next: start,
+        })
+        .collect();
+    let mut levels = 0u64;
+    let mut deadline = None;
+
+    while !shared.stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if let Some(deadline) = deadline {
+            shared
+                .jitter
+                .lock()
+                .unwrap()
+                .observe(now.saturating_duration_since(deadline));
+        }
+        let mut wanted = 0u64;
+        for (line, channel) in channels.iter_mut().enumerate() {
+            channel.advance(now, line, shared);
+            wanted |= u64::from(channel.high) << line;
+        }
+        if wanted != levels {
+            if let Err(err) = sink.set_values(wanted, wanted ^ levels) {
+                *shared.error.lock().unwrap() = Some(err);
+                return;
+            }
+            levels = wanted;
+        }
+
+        let next = channels
+            .iter()
+            .map(|channel| channel.next)
+            .min()
+            .unwrap_or(now);
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+        deadline = Some(next);
+    }
+    if let Err(err) = sink.set_values(0, line_mask(channels.len())) {
+        eprintln!("Error: cannot turn the LEDs off: {}", err);
+    }
+}
+
+/// LEDs driven by software PWM on GPIO lines, with the speed from `speed`.
+pub struct SoftPwmBackend {
+    engine: SoftPwm,
+    speed: Box<dyn SpeedSource + Send>,
+}
+
+impl SoftPwmBackend {
+    /// Drives the LEDs with a running `engine`.
+    pub fn new(engine: SoftPwm, speed: Box<dyn SpeedSource + Send>) -> Self {
+        SoftPwmBackend { engine, speed }
+    }
+
+    /// Requests `lines` of `chip` as outputs and starts toggling them with
+    /// `period`.
+    pub fn open(
+        chip: impl Into<PathBuf>,
+        lines: &[u32],
+        period: Duration,
+        priority: Option<u8>,
+        speed: Box<dyn SpeedSource + Send>,
+    ) -> Result<Self> {
+        let sink = OutputLines::request(chip, lines)?;
+        let engine = SoftPwm::start(Box::new(sink), &vec![period; lines.len()], priority)?;
+        Ok(Self::new(engine, speed))
+    }
+
+    /// Opens the lines of a `[softpwm]` section, reading the speed from its
+    /// `speed` file.
+    pub fn from_config(config: &SoftPwmConfig) -> Result<Self> {
+        Self::open(
+            &config.chip,
+            &config.lines,
+            config.period(),
+            config.priority,
+            Box::new(SpeedFile::new(&config.speed)),
+        )
+    }
+
+    /// The engine toggling the lines, for its jitter statistics.
+    pub fn engine(&self) -> &SoftPwm {
+        &self.engine
+    }
+}
+
+impl fmt::Debug for SoftPwmBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SoftPwmBackend")
+            .field("engine", &self.engine)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LedBackend for SoftPwmBackend {
+    fn led_count(&self) -> usize {
+        self.engine.lines()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.speed.read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.engine.lines())?;
+        Ok(self.engine.duties()[led])
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.engine.lines())?;
+        check_duty(led, duty)?;
+        let mut frame = self.engine.duties();
+        frame[led] = duty;
+        self.engine.set_duties(&frame)
+    }
+
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        self.engine.set_duties(frame)
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        self.speed.speed_notifier()
+    }
+}
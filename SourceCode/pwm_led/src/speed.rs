@@ -0,0 +1,100 @@
+//! Button press speed values and where they come from.
+//!
+//! Speeds are presses/second as `f64`, so a reading of 1.9 is not truncated
+//! to 1 and the mapping gets sub-integer resolution.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backend::LedBackend;
+use crate::error::{ControllerError, Result};
+use crate::sysfs::INTERVAL_ATTRIBUTE;
+use crate::watch::{PollNotifier, SpeedNotifier};
+
+/// Nanoseconds per second.
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+/// Parses a speed as printed by the kernel module, either an integer
+/// ("3") or a decimal ("3.75"). Negative and non-finite values are refused.
+pub fn parse_speed(text: &str) -> Option<f64> {
+    let speed: f64 = text.parse().ok()?;
+    if speed.is_finite() && speed >= 0.0 {
+        Some(speed)
+    } else {
+        None
+    }
+}
+
+/// Converts an average interval between alternating presses to presses per
+/// second. An interval of 0 means no presses have been measured yet.
+pub fn speed_from_interval_ns(interval_ns: u64) -> f64 {
+    if interval_ns == 0 {
+        0.0
+    } else {
+        NANOS_PER_SEC / interval_ns as f64
+    }
+}
+
+/// Where a backend that only drives LEDs gets the button speed from.
+///
+/// Every `LedBackend` is one, so the kernel module's interfaces can feed
+/// LEDs driven some other way.
+pub trait SpeedSource {
+    /// Reads the current button press speed in presses/second.
+    fn read_speed(&mut self) -> Result<f64>;
+
+    /// Opens a way to wait for the speed to change, like
+    /// `LedBackend::speed_notifier`.
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        None
+    }
+}
+
+impl<B: LedBackend + ?Sized> SpeedSource for B {
+    fn read_speed(&mut self) -> Result<f64> {
+        LedBackend::read_speed(self)
+    }
+
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        LedBackend::speed_notifier(self)
+    }
+}
+
+/// A file holding the speed, such as the module's `button_speed`
+/// attribute. A file named `press_interval_ns` holds the interval between
+/// presses instead, converted like the sysfs backend does.
+#[derive(Debug, Clone)]
+pub struct SpeedFile {
+    path: PathBuf,
+}
+
+impl SpeedFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SpeedFile { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SpeedSource for SpeedFile {
+    fn read_speed(&mut self) -> Result<f64> {
+        let path = &self.path;
+        let buffer = fs::read_to_string(path).map_err(|e| ControllerError::from_io(path, e))?;
+        let text = buffer.trim();
+        let speed = if path.ends_with(INTERVAL_ATTRIBUTE) {
+            text.parse().ok().map(speed_from_interval_ns)
+        } else {
+            parse_speed(text)
+        };
+        speed.ok_or_else(|| ControllerError::malformed(path, &buffer))
+    }
+
+    // Sysfs attributes the module notifies wake the reader; plain files
+    // just time out
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        let notifier = PollNotifier::open(&self.path).ok()?;
+        Some(Box::new(notifier))
+    }
+}
@@ -0,0 +1,224 @@
+//! Access to the kernel module through its sysfs attributes.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::backend::{check_duty, check_frame, check_led, read_from_start, LedBackend};
+use crate::error::{ControllerError, Result};
+use crate::frame::DutyFrame;
+use crate::speed::{parse_speed, speed_from_interval_ns};
+use crate::watch::{PollNotifier, SpeedNotifier};
+
+/// Base path to sysfs entries.
+pub const SYSFS_PATH: &str = "/sys/kernel/pwm_led_controller";
+
+/// Attribute with the average interval between alternating presses in
+/// nanoseconds, present on modules that export it.
+pub const INTERVAL_ATTRIBUTE: &str = "press_interval_ns";
+
+/// Backend using the `button_speed` and `ledN_duty` sysfs attributes.
+///
+/// The channel count is discovered when the backend is opened: every
+/// `led1_duty`, `led2_duty`, ... present without a gap is one channel.
+///
+/// Each access opens the attribute again unless `keep_open` was called.
+#[derive(Debug)]
+pub struct SysfsBackend {
+    base: PathBuf,
+    channels: usize,
+    handles: Option<Handles>,
+}
+
+// Descriptors kept open by `keep_open`.
+#[derive(Debug)]
+struct Handles {
+    speed_path: PathBuf,
+    speed: File,
+    duties: Vec<File>,
+    // Whether the duty attributes could be opened for writing
+    writable: bool,
+}
+
+impl SysfsBackend {
+    /// Opens the default sysfs directory.
+    pub fn new() -> Result<Self> {
+        Self::with_path(SYSFS_PATH)
+    }
+
+    /// Opens the attributes found in another directory.
+    pub fn with_path(base: impl Into<PathBuf>) -> Result<Self> {
+        let base = base.into();
+        let channels = discover_channels(&base);
+        if channels == 0 {
+            return Err(ControllerError::DeviceMissing {
+                path: duty_path(&base, 0),
+            });
+        }
+        Ok(SysfsBackend {
+            base,
+            channels,
+            handles: None,
+        })
+    }
+
+    /// Opens the speed attribute and every `ledN_duty` once and keeps them
+    /// open, re-reading them with `pread` at offset 0 instead of opening
+    /// them on every access. Which speed attribute is read is settled here.
+    ///
+    /// Without write access to the duties, they are opened read-only and
+    /// writes fail with `PermissionDenied`.
+    pub fn keep_open(mut self) -> Result<Self> {
+        let speed_path = self.speed_path();
+        let speed =
+            File::open(&speed_path).map_err(|e| ControllerError::from_io(&speed_path, e))?;
+
+        let open_duties = |write: bool| -> std::io::Result<Vec<File>> {
+            (0..self.channels)
+                .map(|led| {
+                    OpenOptions::new()
+                        .read(true)
+                        .write(write)
+                        .open(duty_path(&self.base, led))
+                })
+                .collect()
+        };
+        let (duties, writable) = match open_duties(true) {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => (open_duties(false), false),
+            duties => (duties, true),
+        };
+        let duties = duties.map_err(|e| ControllerError::from_io(&self.base, e))?;
+
+        self.handles = Some(Handles {
+            speed_path,
+            speed,
+            duties,
+            writable,
+        });
+        Ok(self)
+    }
+
+    /// Directory holding the attributes.
+    pub fn path(&self) -> &Path {
+        &self.base
+    }
+
+    // The attribute `read_speed` reads.
+    fn speed_path(&self) -> PathBuf {
+        if let Some(handles) = &self.handles {
+            return handles.speed_path.clone();
+        }
+        let interval_path = self.base.join(INTERVAL_ATTRIBUTE);
+        if interval_path.exists() {
+            interval_path
+        } else {
+            self.base.join("button_speed")
+        }
+    }
+}
+
+pub(crate) fn duty_path(base: &Path, led: usize) -> PathBuf {
+    base.join(format!("led{}_duty", led + 1))
+}
+
+/// Counts the consecutive `ledN_duty` attributes in `base`, starting at 1.
+pub fn discover_channels(base: &Path) -> usize {
+    (0..)
+        .take_while(|&led| duty_path(base, led).exists())
+        .count()
+}
+
+pub(crate) fn read_text(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| ControllerError::from_io(path, e))
+}
+
+// Parses a single-value attribute read from `path`.
+fn parse_attribute<T: FromStr>(path: &Path, buffer: &str) -> Result<T> {
+    buffer
+        .trim()
+        .parse()
+        .map_err(|_| ControllerError::malformed(path, buffer))
+}
+
+pub(crate) fn read_attribute<T: FromStr>(path: &Path) -> Result<T> {
+    parse_attribute(path, &read_text(path)?)
+}
+
+impl LedBackend for SysfsBackend {
+    fn led_count(&self) -> usize {
+        self.channels
+    }
+
+    // Prefers the averaged press interval, which has nanosecond resolution,
+    // over `button_speed`, which older modules truncate to an integer.
+    fn read_speed(&mut self) -> Result<f64> {
+        let path = self.speed_path();
+        let buffer = match &self.handles {
+            Some(handles) => read_from_start(&handles.speed, &path)?,
+            None => read_text(&path)?,
+        };
+        if path.ends_with(INTERVAL_ATTRIBUTE) {
+            let interval_ns: u64 = parse_attribute(&path, &buffer)?;
+            return Ok(speed_from_interval_ns(interval_ns));
+        }
+
+        parse_speed(buffer.trim()).ok_or_else(|| ControllerError::malformed(&path, &buffer))
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        check_led(led, self.channels)?;
+        let path = duty_path(&self.base, led);
+        match &self.handles {
+            Some(handles) => parse_attribute(&path, &read_from_start(&handles.duties[led], &path)?),
+            None => read_attribute(&path),
+        }
+    }
+
+    fn write_duty(&mut self, led: usize, duty: u32) -> Result<()> {
+        check_led(led, self.channels)?;
+        check_duty(led, duty)?;
+        let path = duty_path(&self.base, led);
+        let io_err = |e| ControllerError::from_io(&path, e);
+        let value = duty.to_string();
+        if let Some(handles) = &self.handles {
+            if !handles.writable {
+                return Err(ControllerError::PermissionDenied { path });
+            }
+            // Sysfs takes each write as a whole value whatever the offset;
+            // the truncation is for plain files, as below
+            let file = &handles.duties[led];
+            return file
+                .set_len(0)
+                .and_then(|()| file.write_all_at(value.as_bytes(), 0))
+                .map_err(io_err);
+        }
+
+        // Truncate like `echo 40 > ledN_duty` does, so a shorter value written
+        // to a plain file (as in tests) does not leave stale digits behind
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(io_err)?;
+        file.write_all(value.as_bytes()).map_err(io_err)
+    }
+
+    // Sysfs has one attribute per LED, so the update is one write per channel.
+    // The frame is validated first so a bad value cannot leave it half applied.
+    fn write_duties(&mut self, frame: &DutyFrame) -> Result<()> {
+        check_frame(frame, self.channels)?;
+        for (led, &duty) in frame.iter().enumerate() {
+            self.write_duty(led, duty)?;
+        }
+        Ok(())
+    }
+
+    // The module notifies both speed attributes on every press that changes
+    // the average.
+    fn speed_notifier(&mut self) -> Option<Box<dyn SpeedNotifier>> {
+        let notifier = PollNotifier::open(self.speed_path()).ok()?;
+        Some(Box::new(notifier))
+    }
+}
@@ -0,0 +1,224 @@
+//! Waiting for the button speed to change instead of sleeping a fixed
+//! interval between readings.
+//!
+//! The kernel module calls `sysfs_notify` on `button_speed` and
+//! `press_interval_ns` whenever a press updates the average, which wakes a
+//! `poll()` on the attribute. Interfaces that cannot signal changes (the
+//! character device, older modules, plain files in tests) are polled
+//! instead, at an interval that shrinks while the speed keeps changing and
+//! grows back while it holds still.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::backend::LedBackend;
+use crate::error::{ControllerError, Result};
+
+/// Shortest time between two polled speed readings.
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Something that can tell when the speed may have changed.
+pub trait SpeedNotifier: Send {
+    /// Waits up to `timeout` for a change. Returns whether one was signalled;
+    /// an error means no more will be and callers should fall back to
+    /// polling.
+    fn wait(&mut self, timeout: Duration) -> Result<bool>;
+}
+
+/// Waits for `sysfs_notify` on a sysfs attribute with `poll()`.
+///
+/// On a file that is never notified, such as a regular file, `wait` just
+/// times out.
+#[derive(Debug)]
+pub struct PollNotifier {
+    file: File,
+    path: PathBuf,
+}
+
+impl PollNotifier {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = File::open(&path).map_err(|e| ControllerError::from_io(&path, e))?;
+        let notifier = PollNotifier { file, path };
+        notifier.arm()?;
+        Ok(notifier)
+    }
+
+    /// The attribute being watched.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Sysfs only reports the next notification after the attribute has been
+    // read through the same descriptor.
+    fn arm(&self) -> Result<()> {
+        let mut buffer = [0; 64];
+        self.file
+            .read_at(&mut buffer, 0)
+            .map(drop)
+            .map_err(|e| ControllerError::from_io(&self.path, e))
+    }
+}
+
+impl SpeedNotifier for PollNotifier {
+    fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLPRI | libc::POLLERR,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: `fds` is one valid pollfd that outlives the call
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout_ms) };
+        match ready {
+            0 => Ok(false),
+            n if n > 0 => self.arm().map(|()| true),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(ControllerError::from_io(&self.path, err))
+                }
+            }
+        }
+    }
+}
+
+/// Notifications sent by hand, for tests and for sources other than sysfs.
+impl SpeedNotifier for Receiver<()> {
+    fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        match self.recv_timeout(timeout) {
+            Ok(()) => Ok(true),
+            Err(RecvTimeoutError::Timeout) => Ok(false),
+            Err(RecvTimeoutError::Disconnected) => Err(ControllerError::Unsupported(
+                "the notification sender is gone",
+            )),
+        }
+    }
+}
+
+/// Polling interval that drops to `min` when a reading differs from the
+/// previous one and doubles back up to `max` while readings repeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveInterval {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl AdaptiveInterval {
+    /// Starts at `max`, as nothing is known to be changing yet. A `min`
+    /// above `max` is lowered to it.
+    pub fn new(min: Duration, max: Duration) -> Self {
+        AdaptiveInterval {
+            min: min.min(max),
+            max,
+            current: max,
+        }
+    }
+
+    /// Time to wait before the next reading.
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Adjusts the interval after a reading.
+    pub fn update(&mut self, changed: bool) {
+        self.current = if changed {
+            self.min
+        } else {
+            (self.current * 2).clamp(self.min, self.max)
+        };
+    }
+}
+
+/// Iterator over the speed changes of a backend, returned by
+/// `speed_changes`.
+///
+/// Yields the first reading, then each reading that differs from the last
+/// one yielded; `next` blocks until then. Failed readings are yielded as
+/// errors without ending the iteration.
+pub struct SpeedChanges<'a, B: LedBackend + ?Sized> {
+    backend: &'a mut B,
+    notifier: Option<Box<dyn SpeedNotifier>>,
+    interval: AdaptiveInterval,
+    last: Option<f64>,
+    started: bool,
+}
+
+/// Watches `backend` for speed changes, waiting for notifications when it
+/// can signal them and polling between `MIN_POLL_INTERVAL` and
+/// `max_interval` otherwise. With notifications, `max_interval` is how
+/// often the speed is read anyway in case one is missed.
+pub fn speed_changes<B: LedBackend + ?Sized>(
+    backend: &mut B,
+    max_interval: Duration,
+) -> SpeedChanges<'_, B> {
+    let notifier = backend.speed_notifier();
+    SpeedChanges {
+        backend,
+        notifier,
+        interval: AdaptiveInterval::new(MIN_POLL_INTERVAL, max_interval),
+        last: None,
+        started: false,
+    }
+}
+
+impl<B: LedBackend + ?Sized> SpeedChanges<'_, B> {
+    /// Whether changes are signalled rather than found by polling.
+    pub fn is_notified(&self) -> bool {
+        self.notifier.is_some()
+    }
+}
+
+/// Sleeps for `timeout`, or less if `notifier` signals a change first.
+/// Returns whether it did. A failing notifier is dropped, leaving plain
+/// sleeps.
+pub(crate) fn wait_for_change(
+    notifier: &mut Option<Box<dyn SpeedNotifier>>,
+    timeout: Duration,
+) -> bool {
+    if let Some(waiting) = notifier {
+        match waiting.wait(timeout) {
+            Ok(changed) => return changed,
+            Err(err) => {
+                eprintln!("Error: {} (polling the speed instead)", err);
+                *notifier = None;
+            }
+        }
+    }
+    sleep(timeout);
+    false
+}
+
+impl<B: LedBackend + ?Sized> Iterator for SpeedChanges<'_, B> {
+    type Item = Result<f64>;
+
+    fn next(&mut self) -> Option<Result<f64>> {
+        loop {
+            if self.started {
+                wait_for_change(&mut self.notifier, self.interval.current());
+            }
+            self.started = true;
+
+            let speed = match self.backend.read_speed() {
+                Ok(speed) => speed,
+                Err(err) => return Some(Err(err)),
+            };
+            let changed = self.last != Some(speed);
+            // The first reading is not a change, so polling starts slow
+            self.interval.update(changed && self.last.is_some());
+            if changed {
+                self.last = Some(speed);
+                return Some(Ok(speed));
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Live event stream over WebSocket (`websocket` feature): after the
+//! upgrade on `GET /events`, every service `Event` is sent as one JSON text
+//! message.
+
+use std::io::{self, ErrorKind, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Error, Message, WebSocket};
+
+use crate::service::Service;
+
+/// Time without events after which a ping checks the client is still there.
+pub const KEEPALIVE: Duration = Duration::from_secs(15);
+
+// How long each turn of the loop waits for the client, then for an event.
+const CLIENT_POLL: Duration = Duration::from_millis(5);
+const EVENT_POLL: Duration = Duration::from_millis(50);
+
+/// Completes the upgrade for the client that sent `key` as
+/// `Sec-WebSocket-Key`, then streams events until it goes away or closes
+/// the connection.
+pub(crate) fn stream_events(mut stream: TcpStream, key: &str, service: &Service) -> io::Result<()> {
+    // Subscribe first so nothing is missed while the client handles the reply
+    let events = service.subscribe();
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    )?;
+    stream.flush()?;
+    stream.set_read_timeout(Some(CLIENT_POLL))?;
+
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let mut last_sent = Instant::now();
+    loop {
+        if !answer_client(&mut socket) {
+            return Ok(());
+        }
+        let message = match events.recv_timeout(EVENT_POLL) {
+            Ok(event) => Message::text(serde_json::to_string(&event)?),
+            Err(RecvTimeoutError::Timeout) if last_sent.elapsed() >= KEEPALIVE => {
+                Message::Ping(Default::default())
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        // A failed send means the client hung up, which is how streams end
+        if socket.send(message).is_err() {
+            return Ok(());
+        }
+        last_sent = Instant::now();
+    }
+}
+
+// Reads whatever the client sent, so tungstenite answers pings with pongs
+// and a close frame with its own; each reply goes out with the next read.
+// Returns false once the connection is closed.
+fn answer_client(socket: &mut WebSocket<TcpStream>) -> bool {
+    loop {
+        match socket.read() {
+            // The stream is one way; what the client says is not used
+            Ok(_) => {}
+            Err(Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                return true
+            }
+            Err(_) => return false,
+        }
+    }
+}
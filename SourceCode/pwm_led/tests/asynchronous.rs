@@ -0,0 +1,138 @@
+#![cfg(feature = "tokio")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use pwm_led::{
+    run_until, speed_samples, AsyncCharDevBackend, AsyncLedBackend, AsyncSysfsBackend, Config,
+    ControllerError, FakeSysfsBackend,
+};
+
+// A plain file standing in for the character device, removed on drop.
+struct FakeDevice(PathBuf);
+
+impl FakeDevice {
+    fn new(name: &str, contents: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pwm_led-{}-{}", name, process::id()));
+        fs::write(&path, contents).unwrap();
+        FakeDevice(path)
+    }
+}
+
+impl Drop for FakeDevice {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn sysfs_matches_the_blocking_backend() {
+    let fake = FakeSysfsBackend::with_channels(4).unwrap();
+    fake.set_speed(3.0).unwrap();
+    let mut backend = AsyncSysfsBackend::with_path(fake.path()).await.unwrap();
+    assert_eq!(backend.led_count(), 4);
+    assert_eq!(backend.read_speed().await.unwrap(), 3.0);
+
+    // Which speed attribute is read is settled when opening
+    fake.set_interval_ns(Some(400_000_000)).unwrap();
+    assert_eq!(backend.read_speed().await.unwrap(), 3.0);
+    let mut backend = AsyncSysfsBackend::with_path(fake.path()).await.unwrap();
+    assert_eq!(backend.read_speed().await.unwrap(), 2.5);
+
+    backend.write_duties(&[100, 5, 0, 40].into()).await.unwrap();
+    backend.write_duty(1, 7).await.unwrap();
+    assert_eq!(fake.duties().unwrap(), [100, 7, 0, 40]);
+    assert_eq!(backend.read_duties().await.unwrap(), [100, 7, 0, 40]);
+
+    // Validated before anything is written
+    assert!(matches!(
+        backend.write_duties(&[1, 2, 3, 101].into()).await,
+        Err(ControllerError::DutyOutOfRange { led: 3, duty: 101 })
+    ));
+    assert!(matches!(
+        backend.write_duties(&[1, 2, 3].into()).await,
+        Err(ControllerError::ChannelMismatch { .. })
+    ));
+    assert_eq!(fake.duties().unwrap(), [100, 7, 0, 40]);
+
+    assert!(matches!(
+        AsyncSysfsBackend::with_path("/nonexistent/pwm_led").await,
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+}
+
+#[tokio::test]
+async fn chardev_text_protocol() {
+    let device = FakeDevice::new("chardev", "Button Press Speed: 4 presses/second\n");
+    let mut backend = AsyncCharDevBackend::with_path(&device.0);
+    assert_eq!(backend.read_speed().await.unwrap(), 4.0);
+
+    assert!(matches!(
+        backend.read_duty(0).await,
+        Err(ControllerError::Unsupported(_))
+    ));
+    fs::write(&device.0, "").unwrap();
+    backend.write_duties(&[10, 20, 30].into()).await.unwrap();
+    assert_eq!(fs::read_to_string(&device.0).unwrap(), "10 20 30");
+    backend.write_duty(2, 5).await.unwrap();
+    // Like the device node, the file is overwritten in place
+    assert_eq!(fs::read_to_string(&device.0).unwrap(), "10 20 50");
+    assert_eq!(backend.read_duties().await.unwrap(), [10, 20, 5]);
+
+    let garbage = FakeDevice::new("garbage", "hello\n");
+    assert!(matches!(
+        AsyncCharDevBackend::with_path(&garbage.0)
+            .read_speed()
+            .await,
+        Err(ControllerError::MalformedResponse { .. })
+    ));
+}
+
+#[tokio::test]
+async fn stream_of_speed_samples() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(2.0).unwrap();
+    let mut backend = AsyncSysfsBackend::with_path(fake.path()).await.unwrap();
+
+    let samples = speed_samples(&mut backend, Duration::from_millis(5));
+    tokio::pin!(samples);
+    assert_eq!(samples.next().await.unwrap().unwrap(), 2.0);
+    assert_eq!(samples.next().await.unwrap().unwrap(), 2.0);
+    fake.set_speed(6.5).unwrap();
+    assert_eq!(samples.next().await.unwrap().unwrap(), 6.5);
+
+    // Errors do not end the stream
+    fs::remove_file(fake.path().join("button_speed")).unwrap();
+    assert!(samples.next().await.unwrap().is_err());
+    fake.set_speed(1.0).unwrap();
+    assert_eq!(samples.next().await.unwrap().unwrap(), 1.0);
+}
+
+#[tokio::test]
+async fn control_loop_until_cancelled() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let mut backend = AsyncSysfsBackend::with_path(fake.path()).await.unwrap();
+
+    // Cancelled once the LEDs reach full duty
+    let shutdown = async {
+        while fake.duties().ok() != Some([100, 100, 100].into()) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    };
+    let config = Config::default();
+    run_until(&mut backend, &config, Duration::from_millis(10), shutdown)
+        .await
+        .unwrap();
+
+    // A missing speed attribute ends the loop with the error instead
+    fs::remove_file(fake.path().join("button_speed")).unwrap();
+    let never = std::future::pending::<()>();
+    assert!(matches!(
+        run_until(&mut backend, &config, Duration::from_millis(10), never).await,
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+}
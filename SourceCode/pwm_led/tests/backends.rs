@@ -0,0 +1,210 @@
+use std::io::ErrorKind;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use pwm_led::chardev::format_command;
+use pwm_led::{
+    run_every, run_reporting, step, CharDevBackend, Config, ControllerError, DutyFrame,
+    FakeSysfsBackend, LedBackend, Mapping, MockBackend, SysfsBackend,
+};
+
+#[test]
+fn control_loop_against_mock() {
+    let mut mock = MockBackend::new([0.0, 5.0, 10.0]);
+
+    match run_every(&mut mock, &Config::default(), Duration::ZERO) {
+        Err(ControllerError::Io { source, .. }) => {
+            assert_eq!(source.kind(), ErrorKind::UnexpectedEof)
+        }
+        other => panic!("expected end of script, got {:?}", other),
+    }
+    assert_eq!(
+        mock.writes(),
+        [[10, 0, 0], [50, 17, 0], [100, 100, 100]].map(DutyFrame::from)
+    );
+}
+
+#[test]
+fn control_loop_reports_each_reading() {
+    let mut mock = MockBackend::new([0.0, 5.0]);
+    let mut readings = Vec::new();
+    let stop = AtomicBool::new(false);
+    run_reporting(
+        &mut mock,
+        &Config::default(),
+        Duration::ZERO,
+        &stop,
+        |reading| readings.push((reading.speed, reading.target.clone())),
+    )
+    .unwrap_err();
+    assert_eq!(
+        readings,
+        [(0.0, [10, 0, 0].into()), (5.0, [50, 17, 0].into())]
+    );
+}
+
+#[test]
+fn mock_single_led_write_keeps_others() {
+    let mut mock = MockBackend::new([]);
+    mock.write_duties(&[20, 30, 40].into()).unwrap();
+    mock.write_duty(1, 90).unwrap();
+
+    assert_eq!(*mock.duties(), [20, 90, 40]);
+    assert_eq!(mock.read_duty(2).unwrap(), 40);
+    assert!(matches!(
+        mock.write_duty(3, 0),
+        Err(ControllerError::LedOutOfRange { led: 3, count: 3 })
+    ));
+    assert!(matches!(
+        mock.write_duty(0, 101),
+        Err(ControllerError::DutyOutOfRange { led: 0, duty: 101 })
+    ));
+}
+
+#[test]
+fn control_loop_against_fake_sysfs() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+
+    fake.set_speed(7.0).unwrap();
+    assert_eq!(step(&mut fake, &Mapping::default()).unwrap(), [70, 50, 1]);
+    assert_eq!(fake.duties().unwrap(), [70, 50, 1]);
+
+    fake.set_speed(1.0).unwrap();
+    step(&mut fake, &Mapping::default()).unwrap();
+    assert_eq!(fake.duties().unwrap(), [10, 0, 0]);
+}
+
+#[test]
+fn fake_sysfs_per_led_access() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    fake.write_duty(2, 75).unwrap();
+
+    assert_eq!(fake.read_duty(2).unwrap(), 75);
+    assert_eq!(fake.duties().unwrap(), [0, 0, 75]);
+}
+
+#[test]
+fn fake_sysfs_removes_its_directory() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    let dir = fake.path().to_path_buf();
+    assert!(dir.join("button_speed").is_file());
+
+    drop(fake);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn sixteen_channel_panel() {
+    let mut fake = FakeSysfsBackend::with_channels(16).unwrap();
+    assert_eq!(fake.led_count(), 16);
+
+    let frame: DutyFrame = (0..16).map(|led| led * 5).collect();
+    fake.write_duties(&frame).unwrap();
+    assert_eq!(fake.duties().unwrap(), frame);
+    assert_eq!(fake.read_duty(15).unwrap(), 75);
+
+    assert!(matches!(
+        fake.write_duties(&[1, 2, 3].into()),
+        Err(ControllerError::ChannelMismatch {
+            expected: 16,
+            found: 3
+        })
+    ));
+}
+
+#[test]
+fn discovery_stops_at_the_first_gap() {
+    let fake = FakeSysfsBackend::with_channels(8).unwrap();
+    std::fs::remove_file(fake.path().join("led5_duty")).unwrap();
+
+    let backend = SysfsBackend::with_path(fake.path()).unwrap();
+    assert_eq!(backend.led_count(), 4);
+}
+
+#[test]
+fn mapping_must_match_the_panel() {
+    let mut mock = MockBackend::with_channels(8, [3.0]);
+    assert!(matches!(
+        run_every(&mut mock, &Config::default(), Duration::ZERO),
+        Err(ControllerError::ChannelMismatch {
+            expected: 8,
+            found: 3
+        })
+    ));
+
+    let mut config = Config::default();
+    let ramp = config.mapping.leds[0].clone();
+    config.mapping.leds = vec![ramp; 8];
+    assert_eq!(step(&mut mock, &config.mapping).unwrap(), [30; 8]);
+}
+
+#[test]
+fn chardev_command_has_one_number_per_channel() {
+    assert_eq!(format_command(&[10, 0, 0].into()), "10 0 0");
+    assert_eq!(
+        format_command(&vec![100; 8].into()),
+        "100 100 100 100 100 100 100 100"
+    );
+
+    let frame = DutyFrame::from([40, 0, 100]);
+    assert_eq!(frame.to_string(), "L1=40%, L2=0%, L3=100%");
+}
+
+#[test]
+fn sysfs_with_open_handles() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    let mut backend = SysfsBackend::with_path(fake.path())
+        .unwrap()
+        .keep_open()
+        .unwrap();
+
+    // Every read sees what the attribute holds now
+    fake.set_speed(4.0).unwrap();
+    assert_eq!(backend.read_speed().unwrap(), 4.0);
+    fake.set_speed(12.5).unwrap();
+    assert_eq!(backend.read_speed().unwrap(), 12.5);
+
+    backend.write_duties(&[100, 50, 7].into()).unwrap();
+    assert_eq!(fake.duties().unwrap(), [100, 50, 7]);
+    // A shorter value leaves no stale digits behind
+    backend.write_duty(0, 3).unwrap();
+    assert_eq!(fake.duties().unwrap(), [3, 50, 7]);
+    assert_eq!(backend.read_duties().unwrap(), [3, 50, 7]);
+
+    // The speed attribute is the one found when the files were opened
+    fake.set_interval_ns(Some(500_000_000)).unwrap();
+    assert_eq!(backend.read_speed().unwrap(), 12.5);
+    let mut backend = SysfsBackend::with_path(fake.path())
+        .unwrap()
+        .keep_open()
+        .unwrap();
+    assert_eq!(backend.read_speed().unwrap(), 2.0);
+}
+
+#[test]
+fn chardev_with_open_handle() {
+    let path = std::env::temp_dir().join(format!("pwm_led-chardev-{}", std::process::id()));
+    std::fs::write(&path, "Button Press Speed: 6 presses/second\n").unwrap();
+    let mut backend = CharDevBackend::with_path(&path).keep_open().unwrap();
+
+    assert_eq!(backend.read_speed().unwrap(), 6.0);
+    assert_eq!(backend.read_speed().unwrap(), 6.0);
+
+    // An empty re-read, as from modules that fill the message on open,
+    // reopens the device
+    std::fs::write(&path, "").unwrap();
+    assert!(matches!(
+        backend.read_speed(),
+        Err(ControllerError::MalformedResponse { .. })
+    ));
+
+    backend.write_duties(&[10, 20, 30].into()).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "10 20 30");
+    assert_eq!(backend.read_duty(2).unwrap(), 30);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        CharDevBackend::with_path("/nonexistent/pwm_led").keep_open(),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+}
@@ -0,0 +1,192 @@
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use pwm_led::backend::{self, BackendKind, BackendPaths};
+use pwm_led::{
+    map_speed_to_duty_cycles, step, ButtonSpeed, Config, ControllerError, Edge, EventNotifier,
+    FakePwmChip, InputLines, LedBackend, LineEvent, Mapping, MockLineEvents, PwmChipBackend,
+    SpeedNotifier, SpeedSource,
+};
+
+const MS: u64 = 1_000_000;
+const BUTTON1: u32 = 23;
+const BUTTON2: u32 = 24;
+
+fn buttons() -> (MockLineEvents, ButtonSpeed) {
+    let events = MockLineEvents::new();
+    let speed = ButtonSpeed::new(Box::new(events.clone()), [BUTTON1, BUTTON2]);
+    (events, speed)
+}
+
+#[test]
+fn alternating_presses() {
+    let (events, mut speed) = buttons();
+    assert_eq!(speed.read_speed().unwrap(), 0.0);
+
+    // One press is not an interval yet
+    events.press(BUTTON1, 1_000 * MS);
+    assert_eq!(speed.read_speed().unwrap(), 0.0);
+
+    events.press(BUTTON2, 1_250 * MS);
+    events.press(BUTTON1, 1_500 * MS);
+    assert_eq!(speed.read_speed().unwrap(), 4.0);
+    assert_eq!(speed.press_interval_ns(), 250 * MS);
+    assert_eq!(speed.presses(), 3);
+    assert_eq!(events.pending(), 0);
+
+    // Nothing new: the speed holds, like the module's
+    assert_eq!(speed.read_speed().unwrap(), 4.0);
+}
+
+#[test]
+fn repeated_presses_only_move_the_last_press() {
+    let (events, mut speed) = buttons();
+    events.press(BUTTON1, 0);
+    events.press(BUTTON1, 100 * MS);
+    events.press(BUTTON2, 300 * MS);
+    speed.update().unwrap();
+    assert_eq!(speed.press_interval_ns(), 200 * MS);
+    assert_eq!(speed.presses(), 3);
+
+    // Lines other than the buttons' are ignored
+    events.press(7, 310 * MS);
+    events.push(LineEvent {
+        offset: 8,
+        rising: false,
+        timestamp_ns: 320 * MS,
+    });
+    speed.update().unwrap();
+    assert_eq!(speed.presses(), 3);
+}
+
+#[test]
+fn average_truncates_like_the_module() {
+    let (events, mut speed) = buttons();
+    // Intervals of 1, 2 and 2 ns: (1 + 2) / 2 = 1, kept as a total of 2,
+    // then (2 + 2) / 3 = 1
+    for (button, at) in [(BUTTON1, 0), (BUTTON2, 1), (BUTTON1, 3), (BUTTON2, 5)] {
+        events.press(button, at);
+    }
+    speed.update().unwrap();
+    assert_eq!(speed.press_interval_ns(), 1);
+}
+
+#[test]
+fn rescaled_to_20_after_100() {
+    let (events, mut speed) = buttons();
+    let mut at = 0;
+    events.press(BUTTON1, at);
+    for i in 0..101 {
+        at += 10 * MS;
+        events.press(if i % 2 == 0 { BUTTON2 } else { BUTTON1 }, at);
+    }
+    speed.update().unwrap();
+    assert_eq!(speed.press_interval_ns(), 10 * MS);
+
+    // Weighted as 20 samples, not 101: (20 * 10 + 220) / 21 = 20 ms
+    events.press(BUTTON1, at + 220 * MS);
+    assert_eq!(speed.read_speed().unwrap(), 50.0);
+    assert_eq!(speed.press_interval_ns(), 20 * MS);
+}
+
+#[test]
+fn presses_drive_the_leds() {
+    let chip = FakePwmChip::new(3).unwrap();
+    for channel in 0..3 {
+        chip.export(channel).unwrap();
+    }
+    let (events, mut speed) = buttons();
+    let mut notifier = speed.speed_notifier().unwrap();
+    let mut backend =
+        PwmChipBackend::open(chip.path(), &[0, 1, 2], 1_000_000, Box::new(speed)).unwrap();
+
+    assert!(!notifier.wait(Duration::from_millis(10)).unwrap());
+    events.press(BUTTON1, 0);
+    events.press(BUTTON2, 400 * MS);
+    assert!(notifier.wait(Duration::from_secs(1)).unwrap());
+
+    let frame = step(&mut backend, &Mapping::default()).unwrap();
+    let (led1, led2, led3) = map_speed_to_duty_cycles(2.5);
+    assert_eq!(frame, [led1, led2, led3]);
+}
+
+#[test]
+fn event_notifier_is_edge_triggered() {
+    let (mut writer, reader) = UnixStream::pair().unwrap();
+    let mut notifier = EventNotifier::watch(&reader, "<socket>").unwrap();
+    assert!(!notifier.wait(Duration::from_millis(10)).unwrap());
+
+    writer.write_all(b"x").unwrap();
+    assert!(notifier.wait(Duration::from_secs(1)).unwrap());
+    // Still unread, but nothing new arrived
+    assert!(!notifier.wait(Duration::from_millis(10)).unwrap());
+
+    writer.write_all(b"y").unwrap();
+    assert!(notifier.wait(Duration::from_secs(1)).unwrap());
+}
+
+#[test]
+fn requesting_lines_needs_a_gpio_chip() {
+    assert!(matches!(
+        InputLines::request("/nonexistent/gpiochip9", &[23, 24], Edge::Rising, 0),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+
+    // A regular file does not know the ioctl
+    let chip = FakePwmChip::new(1).unwrap();
+    let file = chip.path().join("npwm");
+    assert!(matches!(
+        InputLines::request(&file, &[0], Edge::Rising, 0),
+        Err(ControllerError::Io { .. })
+    ));
+    assert!(matches!(
+        InputLines::request(&file, &[], Edge::Rising, 0),
+        Err(ControllerError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn buttons_section() {
+    let parsed = Config::from_toml(
+        "[buttons]\nchip = \"/dev/gpiochip4\"\nlines = [5, 6]\nedge = \"falling\"\ndebounce_us = 2000\n",
+        Path::new("test.toml"),
+    )
+    .unwrap();
+    let buttons = parsed.buttons.as_ref().unwrap();
+    assert_eq!(buttons.lines, [5, 6]);
+    assert_eq!(buttons.edge, Edge::Falling);
+    assert_eq!(buttons.debounce_us, 2000);
+    assert!(Config::default().buttons.is_none());
+
+    let err = Config::from_toml("[buttons]\nlines = [5, 5]\n", Path::new("test.toml"))
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("[buttons]") && err.contains("line 5"),
+        "{}",
+        err
+    );
+
+    // The PWM backend measures the speed from the buttons instead of its
+    // speed file
+    let chip = FakePwmChip::new(3).unwrap();
+    for channel in 0..3 {
+        chip.export(channel).unwrap();
+    }
+    let text = format!(
+        "[pwm]\nchip = {:?}\n[buttons]\nchip = \"/nonexistent/gpiochip9\"\n",
+        chip.path()
+    );
+    let parsed = Config::from_toml(&text, Path::new("test.toml")).unwrap();
+    match backend::open_configured(BackendKind::PwmChip, &BackendPaths::default(), &parsed) {
+        Err(ControllerError::DeviceMissing { path }) => {
+            assert_eq!(path, Path::new("/nonexistent/gpiochip9"))
+        }
+        other => panic!(
+            "expected a missing GPIO chip, got {:?}",
+            other.map(|b| b.led_count())
+        ),
+    }
+}
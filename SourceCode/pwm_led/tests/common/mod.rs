@@ -0,0 +1,13 @@
+//! Helpers shared by the integration tests.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Polls `check` until it holds, failing the test after 3 s.
+pub fn wait_for(what: &str, mut check: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while !check() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use pwm_led::{map_speed_to_duty_cycles, Config, ConfigError, Mapping};
+
+const EXAMPLE: &str = include_str!("../../pwm_led.toml");
+
+fn parse(text: &str) -> Result<Config, ConfigError> {
+    Config::from_toml(text, Path::new("test.toml"))
+}
+
+#[test]
+fn example_file_is_the_default_profile() {
+    assert_eq!(parse(EXAMPLE).unwrap(), Config::default());
+    assert_eq!(parse("").unwrap(), Config::default());
+}
+
+#[test]
+fn default_profile_matches_legacy_mapping() {
+    let mapping = Mapping::default();
+    for speed in (0..=12).map(f64::from) {
+        let (led1, led2, led3) = map_speed_to_duty_cycles(speed);
+        assert_eq!(mapping.map(speed), [led1, led2, led3], "speed {}", speed);
+    }
+}
+
+#[test]
+fn site_profile() {
+    let config = parse(
+        r#"
+        [mapping]
+        min_speed = 2
+        max_speed = 6
+
+        [[mapping.led]]
+        start = 0.0
+        floor = 20
+        ceiling = 80
+        slope = 60.0
+
+        [[mapping.led]]
+        start = 0.5
+        floor = 30
+        slope = 140.0
+
+        [[mapping.led]]
+        start = 1.0
+        floor = 100
+        slope = 0.0
+        "#,
+    )
+    .unwrap();
+
+    let mapping = &config.mapping;
+    assert_eq!(mapping.map(2.0), [20, 0, 0]);
+    assert_eq!(mapping.map(4.0), [50, 30, 0]);
+    assert_eq!(mapping.map(5.0), [65, 65, 0]);
+    assert_eq!(mapping.map(6.0), [80, 100, 100]);
+}
+
+#[test]
+fn speed_range_alone_keeps_default_ramps() {
+    let config = parse("[mapping]\nmin_speed = 0\nmax_speed = 4\n").unwrap();
+    assert_eq!(config.mapping.leds, Mapping::default().leds);
+    assert_eq!(config.mapping.map(4.0), [100, 100, 100]);
+}
+
+#[test]
+fn invalid_profiles_are_rejected() {
+    let cases = [
+        "[mapping]\nmin_speed = 5\nmax_speed = 5\n",
+        "[mapping]\nled = []\n",
+        "[[mapping.led]]\nstart = 1.5\nslope = 1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n",
+        "[[mapping.led]]\nstart = 0.0\nslope = -1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n",
+        "[[mapping.led]]\nstart = 0.0\nfloor = 50\nceiling = 40\nslope = 1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n[[mapping.led]]\nstart = 0.0\nslope = 1.0\n",
+    ];
+    for text in cases {
+        assert!(
+            matches!(parse(text), Err(ConfigError::Invalid { .. })),
+            "accepted {:?}",
+            text
+        );
+    }
+}
+
+#[test]
+fn unknown_keys_are_parse_errors() {
+    assert!(matches!(
+        parse("[mapping]\nmax_sped = 3\n"),
+        Err(ConfigError::Parse { .. })
+    ));
+}
+
+#[test]
+fn config_flag() {
+    let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    assert!(matches!(
+        Config::from_args_or_env(args(&["--config"])),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(
+        Config::from_args_or_env(args(&["--verbose"])),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(
+        Config::from_args_or_env(args(&["--config=/nonexistent/pwm_led.toml"])),
+        Err(ConfigError::Io { .. })
+    ));
+}
+
+#[test]
+fn any_number_of_leds() {
+    let one = parse("[[mapping.led]]\nstart = 0.0\nslope = 100.0\n").unwrap();
+    assert_eq!(one.mapping.map(10.0), [100]);
+
+    let eight = "[[mapping.led]]\nstart = 0.0\nslope = 100.0\n".repeat(8);
+    assert_eq!(parse(&eight).unwrap().mapping.channels(), 8);
+}
+
+#[test]
+fn steep_slopes_stop_at_the_ceiling() {
+    // Accepted by validation, and far past what a u32 holds
+    let steep = parse("[[mapping.led]]\nstart = 0.0\nfloor = 10\nslope = 1e300\n").unwrap();
+    let led = &steep.mapping.leds[0];
+    assert_eq!(led.level(1.0), 100);
+    assert_eq!(led.level(0.5), 100);
+    assert_eq!(led.level(0.0), 10);
+    assert_eq!(steep.mapping.map(1e9), [100]);
+}
+
+#[test]
+fn named_profiles() {
+    let led = "[[profiles.night.led]]\nstart = 0.0\nslope = 10.0\n";
+    let config = parse(&led.repeat(3)).unwrap();
+    assert_eq!(config.profile_names(), ["default", "night"]);
+    assert_eq!(config.profile("default"), Some(&config.mapping));
+    assert_eq!(config.profile("night").unwrap().map(10.0), [10, 10, 10]);
+    assert_eq!(config.profile("party"), None);
+
+    // Every profile must drive the same LEDs as [mapping]
+    assert!(matches!(
+        parse(&led.repeat(2)),
+        Err(ConfigError::Invalid { .. })
+    ));
+    let reserved = "[[profiles.default.led]]\nstart = 0.0\nslope = 10.0\n".repeat(3);
+    assert!(matches!(parse(&reserved), Err(ConfigError::Invalid { .. })));
+    assert!(matches!(
+        parse("[daemon]\npoll_interval_ms = 0\n"),
+        Err(ConfigError::Invalid { .. })
+    ));
+}
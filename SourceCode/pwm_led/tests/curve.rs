@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use pwm_led::{Config, ResponseCurve};
+
+fn curve(text: &str) -> ResponseCurve {
+    let config = Config::from_toml(
+        &format!(
+            "[[mapping.led]]\nstart = 0.0\nslope = 100.0\ncurve = {}\n\
+             [[mapping.led]]\nstart = 0.0\nslope = 100.0\n\
+             [[mapping.led]]\nstart = 0.0\nslope = 100.0\n",
+            text
+        ),
+        Path::new("test.toml"),
+    )
+    .unwrap();
+    config.mapping.leds[0].curve.clone()
+}
+
+#[test]
+fn every_curve_keeps_the_end_points() {
+    let curves = [
+        ResponseCurve::Linear,
+        ResponseCurve::Gamma { exponent: 2.2 },
+        ResponseCurve::Logarithmic { base: 50.0 },
+        ResponseCurve::Cie1931,
+        ResponseCurve::SCurve {
+            steepness: 10.0,
+            midpoint: 0.5,
+        },
+        ResponseCurve::Lut {
+            table: vec![0, 5, 30, 100],
+        },
+    ];
+    for curve in &curves {
+        assert_eq!(curve.apply(0), 0, "{:?}", curve);
+        assert_eq!(curve.apply(100), 100, "{:?}", curve);
+        assert_eq!(curve.apply(250), 100, "{:?}", curve);
+
+        let outputs: Vec<u32> = (0..=100).map(|duty| curve.apply(duty)).collect();
+        assert!(
+            outputs.windows(2).all(|w| w[0] <= w[1]),
+            "{:?} is not monotonic",
+            curve
+        );
+    }
+}
+
+#[test]
+fn perceptual_curves_stay_low_at_the_bottom() {
+    assert_eq!(ResponseCurve::Linear.apply(50), 50);
+    assert_eq!(ResponseCurve::Gamma { exponent: 2.0 }.apply(50), 25);
+    assert_eq!(ResponseCurve::Cie1931.apply(50), 18);
+    assert_eq!(ResponseCurve::Cie1931.apply(5), 1);
+    assert!(ResponseCurve::Logarithmic { base: 100.0 }.apply(50) < 10);
+}
+
+#[test]
+fn s_curve_is_symmetric_around_its_midpoint() {
+    let curve = ResponseCurve::SCurve {
+        steepness: 8.0,
+        midpoint: 0.5,
+    };
+    assert_eq!(curve.apply(50), 50);
+    assert_eq!(curve.apply(25) + curve.apply(75), 100);
+    assert!(curve.apply(25) < 25);
+}
+
+#[test]
+fn lut_interpolates() {
+    let curve = ResponseCurve::Lut {
+        table: vec![0, 10, 100],
+    };
+    assert_eq!(curve.apply(25), 5);
+    assert_eq!(curve.apply(50), 10);
+    assert_eq!(curve.apply(75), 55);
+}
+
+#[test]
+fn curves_from_config() {
+    assert_eq!(curve("{ type = \"linear\" }"), ResponseCurve::Linear);
+    assert_eq!(curve("{ type = \"cie1931\" }"), ResponseCurve::Cie1931);
+    assert_eq!(
+        curve("{ type = \"gamma\", exponent = 2.2 }"),
+        ResponseCurve::Gamma { exponent: 2.2 }
+    );
+    assert_eq!(
+        curve("{ type = \"s_curve\", steepness = 6.0, midpoint = 0.4 }"),
+        ResponseCurve::SCurve {
+            steepness: 6.0,
+            midpoint: 0.4
+        }
+    );
+    assert_eq!(
+        curve("{ type = \"lut\", table = [0, 50, 100] }"),
+        ResponseCurve::Lut {
+            table: vec![0, 50, 100]
+        }
+    );
+}
+
+#[test]
+fn curve_applies_after_the_ramp() {
+    let mut config = Config::default();
+    config.mapping.leds[0].curve = ResponseCurve::Gamma { exponent: 2.0 };
+
+    // Speed 5 puts LED1's ramp at 50%.
+    assert_eq!(config.mapping.map(5.0), [25, 17, 0]);
+}
+
+#[test]
+fn invalid_curves() {
+    let bad = [
+        ResponseCurve::Gamma { exponent: 0.0 },
+        ResponseCurve::Logarithmic { base: 1.0 },
+        ResponseCurve::SCurve {
+            steepness: 5.0,
+            midpoint: 2.0,
+        },
+        ResponseCurve::Lut { table: vec![50] },
+        ResponseCurve::Lut {
+            table: vec![0, 120],
+        },
+    ];
+    for curve in bad {
+        assert!(curve.validate().is_err(), "accepted {:?}", curve);
+    }
+}
@@ -0,0 +1,202 @@
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use pwm_led::service::EVENT_BACKLOG;
+use pwm_led::{
+    Client, ClientError, Config, DaemonConfig, EventKind, FakeSysfsBackend, Mode, Server, Service,
+    Status,
+};
+
+mod common;
+
+use common::wait_for;
+
+const PROFILES: &str = r#"
+[daemon]
+poll_interval_ms = 10
+
+[profiles.night]
+[[profiles.night.led]]
+start = 0.0
+floor = 5
+slope = 0.0
+[[profiles.night.led]]
+start = 1.0
+slope = 0.0
+[[profiles.night.led]]
+start = 1.0
+slope = 0.0
+"#;
+
+fn config() -> Config {
+    Config::from_toml(PROFILES, "test.toml".as_ref()).unwrap()
+}
+
+fn start(fake: &FakeSysfsBackend) -> Service {
+    let backend = pwm_led::SysfsBackend::with_path(fake.path()).unwrap();
+    Service::start(Box::new(backend), config()).unwrap()
+}
+
+// Polls `check` until it holds, failing the test after two seconds.
+// Whether the fake shows `expected`; a file caught mid-write shows nothing.
+fn shows(fake: &FakeSysfsBackend, expected: [u32; 3]) -> bool {
+    fake.duties().is_ok_and(|duties| duties == expected)
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("pwmledd-{}-{}.sock", process::id(), name))
+}
+
+fn serve(service: &Service, name: &str) -> PathBuf {
+    let path = socket_path(name);
+    let server = Server::bind(&path, service.clone()).unwrap();
+    thread::spawn(move || server.serve());
+    path
+}
+
+#[test]
+fn auto_mode_follows_the_speed() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let service = start(&fake);
+
+    wait_for("full duty", || shows(&fake, [100, 100, 100]));
+    fake.set_speed(1.0).unwrap();
+    wait_for("idle duty", || shows(&fake, [10, 0, 0]));
+
+    let status = service.status();
+    assert_eq!(status.mode, Mode::Auto);
+    assert_eq!(status.speed, Some(1.0));
+    assert_eq!(status.profiles, ["default", "night"]);
+    service.stop();
+}
+
+#[test]
+fn override_pause_and_profiles() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let service = start(&fake);
+
+    service.set_override([40, 0, 100].into()).unwrap();
+    wait_for("override", || shows(&fake, [40, 0, 100]));
+    assert_eq!(service.status().mode, Mode::Manual);
+
+    // Paused: the LEDs keep the override even though the speed changes
+    service.pause();
+    fake.set_speed(1.0).unwrap();
+    wait_for("speed reading", || service.status().speed == Some(1.0));
+    assert_eq!(fake.duties().unwrap(), [40, 0, 100]);
+
+    service.set_profile("night").unwrap();
+    service.resume();
+    wait_for("night profile", || shows(&fake, [5, 0, 0]));
+    assert_eq!(service.status().manual, None);
+
+    assert!(service.set_profile("party").is_err());
+    assert!(service.set_override([40, 0].into()).is_err());
+    assert!(service.set_override([40, 0, 101].into()).is_err());
+    service.stop();
+}
+
+#[test]
+fn subscribers_get_speed_and_duty_events() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let service = start(&fake);
+    let events = service.subscribe();
+
+    let duties = events
+        .iter()
+        .find(|event| event.kind == EventKind::Duties)
+        .unwrap();
+    assert_eq!(duties.duties.unwrap(), [100, 100, 100]);
+    let speed = events
+        .iter()
+        .find(|event| event.kind == EventKind::Speed)
+        .unwrap();
+    assert_eq!(speed.speed, Some(10.0));
+    assert_eq!(speed.filtered_speed, Some(10.0));
+
+    // Stopping the service ends the stream
+    service.stop();
+    assert!(events.iter().count() <= EVENT_BACKLOG);
+}
+
+#[test]
+fn client_over_the_socket() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let service = start(&fake);
+    let path = serve(&service, "client");
+
+    let mut client = Client::connect(&path).unwrap();
+    let status = client.set_override([1, 2, 3]).unwrap();
+    assert_eq!(status.mode, Mode::Manual);
+    wait_for("override", || shows(&fake, [1, 2, 3]));
+
+    let status = client.set_profile("night").unwrap();
+    assert_eq!(status.profile, "night");
+    assert_eq!(client.pause().unwrap().mode, Mode::Paused);
+    assert_eq!(client.resume().unwrap().mode, Mode::Auto);
+    wait_for("night profile", || shows(&fake, [5, 0, 0]));
+
+    match client.set_profile("party") {
+        Err(ClientError::Daemon(msg)) => assert!(msg.contains("party"), "{}", msg),
+        other => panic!("expected a daemon error, got {:?}", other),
+    }
+    let status: Status = client.status().unwrap();
+    assert_eq!(status.duties.unwrap(), [5, 0, 0]);
+    service.stop();
+}
+
+#[test]
+fn raw_json_lines() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    let service = start(&fake);
+    let path = serve(&service, "raw");
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"{\"cmd\":\"set_override\",\"duties\":[7,8,9]}\n{\"cmd\":\"dance\"}\n")
+        .unwrap();
+    let mut lines = BufReader::new(stream).lines();
+
+    let reply: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(reply["ok"], true);
+    assert_eq!(reply["status"]["mode"], "manual");
+    assert_eq!(reply["status"]["override"], serde_json::json!([7, 8, 9]));
+
+    let reply: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(reply["ok"], false);
+    assert!(reply["error"].as_str().unwrap().starts_with("bad request"));
+    service.stop();
+}
+
+#[test]
+fn stale_socket_is_replaced() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    let service = start(&fake);
+    let path = socket_path("stale");
+    std::fs::write(&path, "").unwrap();
+
+    let server = Server::bind(&path, service.clone()).unwrap();
+    assert!(Server::bind(&path, service.clone()).is_err());
+    drop(server);
+    assert!(!path.exists());
+    service.stop();
+}
+
+#[test]
+fn daemon_config_defaults() {
+    assert_eq!(Config::default().daemon, DaemonConfig::default());
+    assert_eq!(
+        DaemonConfig::default().socket,
+        PathBuf::from("/run/pwmledd.sock")
+    );
+    assert_eq!(config().daemon.poll_interval(), Duration::from_millis(10));
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+
+use pwm_led::chardev::parse_speed_line;
+use pwm_led::{CharDevBackend, ControllerError, FakeSysfsBackend, LedBackend, SysfsBackend};
+
+#[test]
+fn chardev_speed_line() {
+    assert_eq!(
+        parse_speed_line("Button Press Speed: 0 presses/second\n"),
+        Some(0.0)
+    );
+    assert_eq!(
+        parse_speed_line("Button Press Speed: 7 presses/second\n"),
+        Some(7.0)
+    );
+    assert_eq!(
+        parse_speed_line("Button Press Speed 7 presses/second"),
+        None
+    );
+    assert_eq!(
+        parse_speed_line("Button Press Speed: fast presses/second"),
+        None
+    );
+    assert_eq!(parse_speed_line(""), None);
+}
+
+#[test]
+fn missing_interfaces() {
+    assert!(matches!(
+        SysfsBackend::with_path("/nonexistent/pwm_led_controller"),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+
+    let mut chardev = CharDevBackend::with_path("/nonexistent/pwm_led_controller");
+    assert!(matches!(
+        chardev.read_speed(),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+    assert!(matches!(
+        chardev.read_duty(0),
+        Err(ControllerError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn malformed_sysfs_speed_is_not_idle() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    fs::write(fake.path().join("button_speed"), "garbage\n").unwrap();
+
+    match fake.read_speed() {
+        Err(ControllerError::MalformedResponse { response, .. }) => assert_eq!(response, "garbage"),
+        other => panic!("expected malformed response, got {:?}", other),
+    }
+}
+
+#[test]
+fn malformed_chardev_response() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    let node = fake.path().join("device");
+    fs::write(&node, "Button Press Speed 3\n").unwrap();
+
+    let mut chardev = CharDevBackend::with_path(&node);
+    assert!(matches!(
+        chardev.read_speed(),
+        Err(ControllerError::MalformedResponse { .. })
+    ));
+}
+
+#[test]
+fn out_of_range_frame_writes_nothing() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    assert!(matches!(
+        fake.write_duties(&[50, 150, 50].into()),
+        Err(ControllerError::DutyOutOfRange { led: 1, duty: 150 })
+    ));
+    assert_eq!(fake.duties().unwrap(), [0, 0, 0]);
+}
+
+#[test]
+fn io_errors_are_classified() {
+    let einval = io::Error::from_raw_os_error(22);
+    assert!(matches!(
+        ControllerError::from_io("/sys/x", einval),
+        ControllerError::KernelRejected { .. }
+    ));
+
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    assert!(matches!(
+        ControllerError::from_io("/dev/x", denied),
+        ControllerError::PermissionDenied { .. }
+    ));
+}
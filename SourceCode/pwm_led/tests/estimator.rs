@@ -0,0 +1,177 @@
+use std::path::Path;
+use std::time::Duration;
+
+use pwm_led::gpio::monotonic_ns;
+use pwm_led::{ButtonSpeed, Config, MockLineEvents, SpeedEstimator, SpeedSource};
+
+const MS: u64 = 1_000_000;
+
+// `button1_handler`/`button2_handler` of pwm_led_controller.c, line for line.
+#[derive(Default)]
+struct Module {
+    last_press_time: u64,
+    last_button: u32,
+    button_press_count: u64,
+    valid_alternating_count: u64,
+    total_press_time: u64,
+    avg_press_interval: u64,
+}
+
+impl Module {
+    fn handler(&mut self, button: u32, current_press_time: u64) {
+        let other = if button == 1 { 2 } else { 1 };
+        if self.last_button == other {
+            let interval_ns = current_press_time - self.last_press_time;
+            self.total_press_time = self.total_press_time.wrapping_add(interval_ns);
+            self.valid_alternating_count += 1;
+            // The module checks for a count of 0 here, which cannot happen
+            self.total_press_time /= self.valid_alternating_count;
+            self.avg_press_interval = self.total_press_time;
+            self.total_press_time = self.avg_press_interval * self.valid_alternating_count;
+            if self.valid_alternating_count > 100 {
+                self.total_press_time = self.avg_press_interval * 20;
+                self.valid_alternating_count = 20;
+            }
+        }
+        self.last_button = button;
+        self.last_press_time = current_press_time;
+        self.button_press_count += 1;
+    }
+}
+
+#[test]
+fn matches_the_module() {
+    let mut module = Module::default();
+    let mut estimator = SpeedEstimator::new();
+    let mut at = 0;
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    for _ in 0..5_000 {
+        // Mostly alternating, sometimes the same button twice
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let button = if (seed >> 60) < 12 {
+            if module.last_button == 1 {
+                2
+            } else {
+                1
+            }
+        } else {
+            module.last_button.max(1)
+        };
+        at += 20 * MS + (seed >> 33) % (900 * MS);
+
+        module.handler(button, at);
+        estimator.press(button, at);
+        assert_eq!(estimator.press_interval_ns(), module.avg_press_interval);
+        assert_eq!(estimator.presses(), module.button_press_count);
+    }
+}
+
+#[test]
+fn only_alternating_presses_count() {
+    let mut estimator = SpeedEstimator::new();
+    assert_eq!(estimator.speed(), 0.0);
+    assert!(!estimator.press(1, 0));
+    assert!(!estimator.press(1, 100 * MS));
+    assert_eq!(estimator.speed(), 0.0);
+
+    // Measured from the second press of button 1
+    assert!(estimator.press(2, 350 * MS));
+    assert_eq!(estimator.press_interval_ns(), 250 * MS);
+    assert_eq!(estimator.speed(), 4.0);
+    assert!(estimator.press(1, 850 * MS));
+    assert_eq!(estimator.press_interval_ns(), 375 * MS);
+    assert_eq!(estimator.presses(), 4);
+
+    // Any ids work, as long as they differ
+    let mut estimator = SpeedEstimator::default();
+    estimator.press(23, 0);
+    estimator.press(24, 500 * MS);
+    assert_eq!(estimator.speed(), 2.0);
+}
+
+#[test]
+fn running_average_is_rescaled() {
+    let mut estimator = SpeedEstimator::new();
+    let mut at = 0;
+    estimator.press(1, at);
+    for i in 0..101 {
+        at += 10 * MS;
+        estimator.press(2 - i % 2, at);
+    }
+    assert_eq!(estimator.press_interval_ns(), 10 * MS);
+
+    // Weighted as 20 samples: (20 * 10 + 220) / 21 = 20 ms
+    estimator.press(1, at + 220 * MS);
+    assert_eq!(estimator.press_interval_ns(), 20 * MS);
+
+    // And it never expires
+    estimator.expire(at + 3_600_000 * MS);
+    assert_eq!(estimator.press_interval_ns(), 20 * MS);
+}
+
+#[test]
+fn windowed_average() {
+    let mut estimator = SpeedEstimator::windowed(Duration::from_secs(10));
+    let mut at = 0;
+    estimator.press(1, at);
+    // Slow presses for a while, then fast ones
+    for i in 0..20 {
+        at += 1_000 * MS;
+        estimator.press(2 - i % 2, at);
+    }
+    assert_eq!(estimator.press_interval_ns(), 1_000 * MS);
+    for i in 0..44 {
+        at += 250 * MS;
+        estimator.press(2 - i % 2, at);
+    }
+    // Only the last 10 s count: all fast, unlike the running average
+    assert_eq!(estimator.press_interval_ns(), 250 * MS);
+    assert_eq!(estimator.speed(), 4.0);
+
+    // Half the window later, the last 5 s of fast presses are left
+    estimator.expire(at + 5_000 * MS);
+    assert_eq!(estimator.press_interval_ns(), 250 * MS);
+    at += 5_000 * MS;
+    estimator.press(2, at);
+    // 21 intervals of 250 ms and the 5 s pause
+    assert_eq!(estimator.press_interval_ns(), (21 * 250 + 5_000) * MS / 22);
+
+    // Resting a whole window brings the speed back to 0
+    estimator.expire(at + 10_001 * MS);
+    assert_eq!(estimator.speed(), 0.0);
+    assert_eq!(estimator.presses(), 66);
+}
+
+#[test]
+fn windowed_buttons() {
+    let events = MockLineEvents::new();
+    let estimator = SpeedEstimator::windowed(Duration::from_millis(500));
+    let mut speed = ButtonSpeed::with_estimator(Box::new(events.clone()), [23, 24], estimator);
+
+    // Event timestamps are on CLOCK_MONOTONIC, like the kernel's
+    let now = monotonic_ns();
+    events.press(23, now - 2_000 * MS);
+    events.press(24, now - 1_500 * MS);
+    assert_eq!(speed.read_speed().unwrap(), 0.0);
+
+    // The 900 ms between the two press pairs is already out of the window
+    events.press(23, now - 600 * MS);
+    events.press(24, now - 400 * MS);
+    events.press(23, now - 200 * MS);
+    assert_eq!(speed.read_speed().unwrap(), 5.0);
+
+    let parsed =
+        Config::from_toml("[buttons]\nwindow_ms = 10000\n", Path::new("test.toml")).unwrap();
+    let mut estimator = parsed.buttons.unwrap().estimator();
+    estimator.press(1, 0);
+    estimator.press(2, 100 * MS);
+    estimator.expire(20_000 * MS);
+    assert_eq!(estimator.speed(), 0.0);
+
+    let err = Config::from_toml("[buttons]\nwindow_ms = 0\n", Path::new("test.toml"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("[buttons] window_ms"), "{}", err);
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use pwm_led::{run_every, Config, DutyFrame, Easing, FadeConfig, Fader, MockBackend};
+
+fn fader(duration_ms: u64, easing: Easing) -> Fader {
+    Fader::new(FadeConfig {
+        duration_ms,
+        update_interval_ms: 10,
+        easing,
+    })
+}
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+#[test]
+fn easings_keep_the_end_points() {
+    for easing in [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+        Easing::Exponential,
+    ] {
+        assert_eq!(easing.apply(0.0), 0.0, "{:?}", easing);
+        assert!((easing.apply(1.0) - 1.0).abs() < 1e-9, "{:?}", easing);
+        assert!(easing.apply(0.25) <= easing.apply(0.75), "{:?}", easing);
+    }
+    assert!(Easing::EaseIn.apply(0.5) < 0.5);
+    assert!(Easing::EaseOut.apply(0.5) > 0.5);
+    assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    assert!(Easing::Exponential.apply(0.5) < 0.05);
+}
+
+#[test]
+fn linear_fade() {
+    let start = Instant::now();
+    let mut fader = fader(100, Easing::Linear);
+    fader.jump_to([0, 100, 50].into());
+    fader.retarget([100, 0, 50].into(), start);
+
+    assert_eq!(fader.sample(start).unwrap(), [0, 100, 50]);
+    assert_eq!(fader.sample(start + ms(25)).unwrap(), [25, 75, 50]);
+    assert_eq!(fader.sample(start + ms(50)).unwrap(), [50, 50, 50]);
+    assert!(fader.is_fading(start + ms(99)));
+    assert_eq!(fader.sample(start + ms(100)).unwrap(), [100, 0, 50]);
+    assert!(!fader.is_fading(start + ms(100)));
+}
+
+#[test]
+fn new_target_cancels_the_running_fade() {
+    let start = Instant::now();
+    let mut fader = fader(100, Easing::Linear);
+    fader.jump_to([0].into());
+    fader.retarget([100].into(), start);
+
+    // Halfway there the target drops back to 0: fade down from 50.
+    fader.retarget([0].into(), start + ms(50));
+    assert_eq!(fader.sample(start + ms(50)).unwrap(), [50]);
+    assert_eq!(fader.sample(start + ms(100)).unwrap(), [25]);
+    assert_eq!(fader.sample(start + ms(150)).unwrap(), [0]);
+    assert_eq!(fader.target().unwrap(), &[0]);
+}
+
+#[test]
+fn zero_duration_jumps() {
+    let start = Instant::now();
+    let mut fader = fader(0, Easing::EaseInOut);
+    assert_eq!(fader.sample(start), None);
+
+    fader.retarget([40, 0, 100].into(), start);
+    assert_eq!(fader.sample(start).unwrap(), [40, 0, 100]);
+    assert!(!fader.is_fading(start));
+}
+
+#[test]
+fn unchanged_frames_are_not_emitted_twice() {
+    let start = Instant::now();
+    let mut fader = fader(100, Easing::Linear);
+    fader.jump_to([0].into());
+    fader.retarget([100].into(), start);
+
+    assert_eq!(fader.next_frame(start), Some(DutyFrame::from([0])));
+    assert_eq!(fader.next_frame(start), None);
+    assert_eq!(
+        fader.next_frame(start + ms(10)),
+        Some(DutyFrame::from([10]))
+    );
+    assert_eq!(
+        fader.next_frame(start + ms(200)),
+        Some(DutyFrame::from([100]))
+    );
+    assert_eq!(fader.next_frame(start + ms(300)), None);
+
+    // A repeated target is still written once so the LEDs get refreshed.
+    fader.retarget([100].into(), start + ms(300));
+    assert_eq!(
+        fader.next_frame(start + ms(300)),
+        Some(DutyFrame::from([100]))
+    );
+}
+
+#[test]
+fn control_loop_writes_intermediate_frames() {
+    let config = Config {
+        fade: FadeConfig {
+            duration_ms: 60,
+            update_interval_ms: 5,
+            easing: Easing::Linear,
+        },
+        ..Config::default()
+    };
+    let mut mock = MockBackend::new([10.0]);
+
+    run_every(&mut mock, &config, ms(100)).unwrap_err();
+
+    let writes = mock.writes();
+    assert!(writes.len() > 3, "only {} writes", writes.len());
+    assert_eq!(writes.last().unwrap(), &[100, 100, 100]);
+    assert!(writes.windows(2).all(|w| w[0][0] <= w[1][0]));
+}
+
+#[test]
+fn fade_section_in_config() {
+    let config = Config::from_toml(
+        "[fade]\nduration_ms = 300\neasing = \"ease_in_out\"\n",
+        Path::new("test.toml"),
+    )
+    .unwrap();
+    assert_eq!(config.fade.duration_ms, 300);
+    assert_eq!(config.fade.update_interval_ms, 20);
+    assert_eq!(config.fade.easing, Easing::EaseInOut);
+
+    let zero_rate = Config::from_toml("[fade]\nupdate_interval_ms = 0\n", Path::new("test.toml"));
+    assert!(zero_rate.is_err());
+}
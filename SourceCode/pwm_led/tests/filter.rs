@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use pwm_led::filter::{Ema, Median, MovingAverage};
+use pwm_led::{Config, FilterChain, FilterConfig, Hysteresis, Mapping, Smoothing, SpeedFilter};
+
+// Loads a recorded speed trace: one reading per line, `#` starts a comment.
+fn trace(name: &str) -> Vec<f64> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name);
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse().unwrap())
+        .collect()
+}
+
+// Runs a trace through a filter chain and returns LED3's on/off state after
+// each reading.
+fn led3_states(config: &FilterConfig, speeds: &[f64]) -> Vec<bool> {
+    led3_states_with(&Mapping::default(), config, speeds)
+}
+
+fn led3_states_with(mapping: &Mapping, config: &FilterConfig, speeds: &[f64]) -> Vec<bool> {
+    let mut chain = FilterChain::new(config);
+    speeds
+        .iter()
+        .map(|&speed| {
+            let filtered = chain.smooth(speed);
+            chain.map(mapping, filtered)[2] > 0
+        })
+        .collect()
+}
+
+fn toggles(states: &[bool]) -> usize {
+    states.windows(2).filter(|w| w[0] != w[1]).count()
+}
+
+#[test]
+fn unfiltered_trace_flickers() {
+    let states = led3_states(&FilterConfig::default(), &trace("steady_near_led3.trace"));
+    assert!(toggles(&states) > 8, "{:?}", states);
+}
+
+#[test]
+fn ema_with_hysteresis_holds_steady() {
+    let config = FilterConfig {
+        smoothing: Smoothing::Ema { alpha: 0.3 },
+        hysteresis: 0.1,
+    };
+    let states = led3_states(&config, &trace("steady_near_led3.trace"));
+    assert!(toggles(&states) <= 1, "{:?}", states);
+}
+
+#[test]
+fn hysteresis_alone_needs_a_clear_move() {
+    let config = FilterConfig {
+        smoothing: Smoothing::None,
+        hysteresis: 0.2,
+    };
+    // LED3 starts at 0.66 with a 20% floor: on from 0.76 (speed 7.84), off
+    // below 0.56 (speed 6.04).
+    let mut mapping = Mapping::default();
+    mapping.leds[2].floor = 20;
+    let states = led3_states_with(&mapping, &config, &[7.0, 8.0, 7.0, 6.5, 6.0, 7.0, 7.5, 8.0]);
+    assert_eq!(states, [false, true, true, true, false, false, false, true]);
+}
+
+#[test]
+fn median_drops_the_bounce() {
+    let mut median = Median::new(3);
+    let out: Vec<f64> = trace("bounce_spike.trace")
+        .into_iter()
+        .map(|speed| median.update(speed))
+        .collect();
+    assert!(out.iter().all(|&speed| speed == 4.0), "{:?}", out);
+}
+
+#[test]
+fn moving_average_window() {
+    let mut average = MovingAverage::new(4);
+    let out: Vec<f64> = [4.0, 8.0, 0.0, 4.0, 8.0]
+        .into_iter()
+        .map(|speed| average.update(speed))
+        .collect();
+    assert_eq!(out, [4.0, 6.0, 4.0, 4.0, 5.0]);
+
+    average.reset();
+    assert_eq!(average.update(2.0), 2.0);
+}
+
+#[test]
+fn ema_follows_gradually() {
+    let mut ema = Ema::new(0.5);
+    let out: Vec<f64> = [4.0, 8.0, 8.0, 0.0]
+        .into_iter()
+        .map(|speed| ema.update(speed))
+        .collect();
+    assert_eq!(out, [4.0, 6.0, 7.0, 3.5]);
+}
+
+#[test]
+fn median_of_even_window() {
+    let mut median = Median::new(4);
+    let out: Vec<f64> = [1.0, 9.0, 3.0, 5.0]
+        .into_iter()
+        .map(|speed| median.update(speed))
+        .collect();
+    assert_eq!(out, [1.0, 5.0, 3.0, 4.0]);
+}
+
+#[test]
+fn no_band_matches_plain_mapping() {
+    let mapping = Mapping::default();
+    let mut hysteresis = Hysteresis::new(0.0);
+    for speed in (0..=12).chain((0..=12).rev()).map(f64::from) {
+        let position = mapping.position(speed);
+        assert_eq!(
+            hysteresis.map(&mapping, position),
+            mapping.map(speed),
+            "speed {}",
+            speed
+        );
+    }
+}
+
+#[test]
+fn filter_section_in_config() {
+    let config = Config::from_toml(
+        "[filter]\nsmoothing = { type = \"median\", window = 5 }\nhysteresis = 0.05\n",
+        Path::new("test.toml"),
+    )
+    .unwrap();
+    assert_eq!(config.filter.smoothing, Smoothing::Median { window: 5 });
+    assert_eq!(config.filter.hysteresis, 0.05);
+
+    for bad in [
+        "[filter]\nsmoothing = { type = \"ema\", alpha = 0.0 }\n",
+        "[filter]\nsmoothing = { type = \"moving_average\", window = 0 }\n",
+        "[filter]\nhysteresis = 1.5\n",
+    ] {
+        assert!(
+            Config::from_toml(bad, Path::new("test.toml")).is_err(),
+            "{}",
+            bad
+        );
+    }
+}
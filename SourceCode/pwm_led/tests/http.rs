@@ -0,0 +1,258 @@
+#![cfg(feature = "http")]
+
+use std::io::{Read, Write};
+use std::iter;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+use pwm_led::{Config, HttpServer, MockBackend, Mode, Service};
+use serde_json::{json, Value};
+
+mod common;
+
+use common::wait_for;
+
+fn start(speed: f64) -> (Service, SocketAddr) {
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 10;
+    let backend = MockBackend::new(iter::repeat_n(speed, 100_000));
+    let service = Service::start(Box::new(backend), config).unwrap();
+
+    let server = HttpServer::bind("127.0.0.1:0", service.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+    (service, addr)
+}
+
+// Sends one request and returns the status code and the body.
+fn call(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, String) {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+fn json_call(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let (status, body) = call(addr, method, path, body);
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+fn duties(addr: SocketAddr) -> Value {
+    let (_, leds) = json_call(addr, "GET", "/leds", None);
+    leds["leds"].clone()
+}
+
+#[test]
+fn failed_reading_is_unavailable() {
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 10;
+    // Three readings, then every one fails
+    let service = Service::start(Box::new(MockBackend::new([4.0; 3])), config).unwrap();
+    let server = HttpServer::bind("127.0.0.1:0", service.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    wait_for("a failed reading", || service.status().error.is_some());
+    assert_eq!(service.status().speed, Some(4.0));
+    let (status, body) = json_call(addr, "GET", "/speed", None);
+    assert_eq!(status, 503);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("speed script exhausted"),
+        "{}",
+        body
+    );
+    service.stop();
+}
+
+#[test]
+fn speed_and_leds() {
+    let (service, addr) = start(10.0);
+
+    wait_for("first reading", || {
+        service.status().duties == Some([100, 100, 100].into())
+    });
+    let (status, speed) = json_call(addr, "GET", "/speed", None);
+    assert_eq!(status, 200);
+    assert_eq!(speed, json!({ "speed": 10.0, "filtered_speed": 10.0 }));
+
+    let (status, leds) = json_call(addr, "GET", "/leds", None);
+    assert_eq!(status, 200);
+    assert_eq!(leds["mode"], "auto");
+    assert_eq!(
+        leds["leds"],
+        json!([
+            { "led": 1, "duty": 100 },
+            { "led": 2, "duty": 100 },
+            { "led": 3, "duty": 100 },
+        ])
+    );
+    service.stop();
+}
+
+#[test]
+fn put_led_overrides_one_channel() {
+    let (service, addr) = start(1.0);
+    wait_for("first reading", || {
+        service.status().duties == Some([10, 0, 0].into())
+    });
+
+    let (status, leds) = json_call(addr, "PUT", "/leds/2", Some(json!({ "duty": 55 })));
+    assert_eq!(status, 200);
+    assert_eq!(leds["mode"], "manual");
+    assert_eq!(leds["leds"][1], json!({ "led": 2, "duty": 55 }));
+    wait_for("override", || duties(addr)[1]["duty"] == 55);
+    assert_eq!(duties(addr)[0]["duty"], 10);
+
+    assert_eq!(
+        call(addr, "PUT", "/leds/4", Some(json!({ "duty": 5 }))).0,
+        404
+    );
+    assert_eq!(
+        call(addr, "PUT", "/leds/0", Some(json!({ "duty": 5 }))).0,
+        404
+    );
+    assert_eq!(
+        call(addr, "PUT", "/leds/1", Some(json!({ "duty": 101 }))).0,
+        400
+    );
+    assert_eq!(
+        call(addr, "PUT", "/leds/1", Some(json!({ "level": 5 }))).0,
+        400
+    );
+    service.stop();
+}
+
+#[test]
+fn post_mode() {
+    let (service, addr) = start(1.0);
+    wait_for("first reading", || {
+        service.status().duties == Some([10, 0, 0].into())
+    });
+
+    let body = json!({ "mode": "manual", "duties": [1, 2, 3] });
+    let (status, state) = json_call(addr, "POST", "/mode", Some(body));
+    assert_eq!(status, 200);
+    assert_eq!(state["mode"], "manual");
+    assert_eq!(state["override"], json!([1, 2, 3]));
+    wait_for("override", || duties(addr)[2]["duty"] == 3);
+
+    let (_, state) = json_call(addr, "POST", "/mode", Some(json!({ "mode": "paused" })));
+    assert_eq!(state["mode"], "paused");
+
+    let (_, state) = json_call(addr, "POST", "/mode", Some(json!({ "mode": "auto" })));
+    assert_eq!(state["mode"], "auto");
+    assert_eq!(state["override"], Value::Null);
+    wait_for("auto duty", || duties(addr)[0]["duty"] == 10);
+
+    let body = json!({ "mode": "auto", "profile": "missing" });
+    assert_eq!(call(addr, "POST", "/mode", Some(body)).0, 400);
+    let body = json!({ "mode": "manual", "duties": [1, 2] });
+    assert_eq!(call(addr, "POST", "/mode", Some(body)).0, 400);
+    assert_eq!(
+        call(addr, "POST", "/mode", Some(json!({ "mode": "disco" }))).0,
+        400
+    );
+    service.stop();
+}
+
+#[test]
+fn post_mode_is_all_or_nothing() {
+    let mut config = Config::from_toml(
+        &"[[profiles.night.led]]\nstart = 0.0\nslope = 5.0\n".repeat(3),
+        std::path::Path::new("test.toml"),
+    )
+    .unwrap();
+    config.daemon.poll_interval_ms = 10;
+    let backend = MockBackend::new(iter::repeat_n(1.0, 100_000));
+    let service = Service::start(Box::new(backend), config).unwrap();
+    let server = HttpServer::bind("127.0.0.1:0", service.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    // A good profile does not get through with bad duties or a bad mode
+    for body in [
+        json!({ "mode": "manual", "duties": [1, 2], "profile": "night" }),
+        json!({ "mode": "manual", "duties": [1, 2, 101], "profile": "night" }),
+        json!({ "mode": "disco", "profile": "night" }),
+    ] {
+        assert_eq!(call(addr, "POST", "/mode", Some(body)).0, 400);
+    }
+    // Duties only go with manual mode
+    for mode in ["auto", "paused"] {
+        let body = json!({ "mode": mode, "duties": [1, 2, 3], "profile": "night" });
+        let (status, error) = json_call(addr, "POST", "/mode", Some(body));
+        assert_eq!(status, 400);
+        assert!(
+            error["error"].as_str().unwrap().contains("manual"),
+            "{}",
+            error
+        );
+    }
+    let status = service.status();
+    assert_eq!(
+        (status.mode, status.profile.as_str()),
+        (Mode::Auto, "default")
+    );
+    assert_eq!(status.manual, None);
+
+    let body = json!({ "mode": "manual", "duties": [1, 2, 3], "profile": "night" });
+    let (status, state) = json_call(addr, "POST", "/mode", Some(body));
+    assert_eq!(status, 200);
+    assert_eq!(state["profile"], "night");
+    assert_eq!(state["override"], json!([1, 2, 3]));
+
+    // Pausing keeps the override, auto drops it
+    let (_, state) = json_call(addr, "POST", "/mode", Some(json!({ "mode": "paused" })));
+    assert_eq!(state["override"], json!([1, 2, 3]));
+    let (_, state) = json_call(addr, "POST", "/mode", Some(json!({ "mode": "auto" })));
+    assert_eq!(state["override"], Value::Null);
+    service.stop();
+}
+
+#[test]
+fn metrics() {
+    let (service, addr) = start(10.0);
+    wait_for("first reading", || {
+        service.status().duties == Some([100, 100, 100].into())
+    });
+
+    let (status, text) = call(addr, "GET", "/metrics", None);
+    assert_eq!(status, 200);
+    assert!(text.contains("pwm_led_speed 10\n"), "{}", text);
+    assert!(text.contains("pwm_led_duty{led=\"2\"} 100\n"), "{}", text);
+    assert!(text.contains("pwm_led_loop_iterations_total "), "{}", text);
+    assert_eq!(call(addr, "POST", "/metrics", None).0, 405);
+    service.stop();
+}
+
+#[test]
+fn unknown_routes_and_openapi() {
+    let (service, addr) = start(1.0);
+
+    assert_eq!(call(addr, "GET", "/nothing", None).0, 404);
+    assert_eq!(call(addr, "DELETE", "/leds", None).0, 405);
+    assert_eq!(call(addr, "GET", "/mode", None).0, 405);
+
+    let (status, spec) = call(addr, "GET", "/openapi.yaml", None);
+    assert_eq!(status, 200);
+    for path in ["/speed:", "/leds:", "/leds/{n}:", "/mode:", "/metrics:"] {
+        assert!(spec.contains(path), "{} missing from the spec", path);
+    }
+    service.stop();
+}
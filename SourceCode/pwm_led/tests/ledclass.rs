@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use pwm_led::backend::{self, BackendKind, BackendPaths};
+use pwm_led::ledclass::{active_trigger, brightness_to_duty, duty_to_brightness};
+use pwm_led::{
+    map_speed_to_duty_cycles, step, Config, ControllerError, FakeLedClass, FakeSysfsBackend,
+    LedBackend, LedClassBackend, Mapping, MockBackend, Service,
+};
+
+mod common;
+
+use common::wait_for;
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn brightness_scaling() {
+    assert_eq!(duty_to_brightness(0, 255), 0);
+    assert_eq!(duty_to_brightness(40, 255), 102);
+    assert_eq!(duty_to_brightness(100, 255), 255);
+    // leds-gpio: on from 50 up
+    assert_eq!(duty_to_brightness(49, 1), 0);
+    assert_eq!(duty_to_brightness(50, 1), 1);
+
+    for duty in 0..=100 {
+        assert_eq!(brightness_to_duty(duty_to_brightness(duty, 255), 255), duty);
+    }
+    assert_eq!(brightness_to_duty(1, 1), 100);
+    assert_eq!(brightness_to_duty(300, 255), 100);
+    assert_eq!(brightness_to_duty(5, 0), 0);
+}
+
+#[test]
+fn trigger_lists() {
+    assert_eq!(active_trigger("none [timer] heartbeat\n"), Some("timer"));
+    assert_eq!(active_trigger("[none] timer"), Some("none"));
+    assert_eq!(active_trigger("none timer"), None);
+}
+
+#[test]
+fn legacy_mapping_on_led_class() {
+    let leds = FakeLedClass::new().unwrap();
+    for name in ["red", "green", "blue"] {
+        leds.add(name, 255, "none").unwrap();
+    }
+    let speeds = [0.0, 1.0, 4.0, 7.5, 10.0, 12.0];
+    let speed = MockBackend::new(speeds);
+    let mut backend = LedClassBackend::open(
+        leds.path(),
+        &names(&["red", "green", "blue"]),
+        true,
+        Box::new(speed),
+    )
+    .unwrap();
+    assert_eq!(backend.led_count(), 3);
+    assert_eq!(backend.max_brightness(), [255, 255, 255]);
+
+    for speed in speeds {
+        let frame = step(&mut backend, &Mapping::default()).unwrap();
+        let (led1, led2, led3) = map_speed_to_duty_cycles(speed);
+        assert_eq!(frame, [led1, led2, led3], "speed {}", speed);
+        for (name, duty) in ["red", "green", "blue"].into_iter().zip([led1, led2, led3]) {
+            assert_eq!(
+                leds.brightness(name).unwrap(),
+                duty_to_brightness(duty, 255)
+            );
+        }
+        assert_eq!(backend.read_duties().unwrap(), frame);
+    }
+
+    assert!(matches!(
+        backend.write_duties(&[10, 20, 101].into()),
+        Err(ControllerError::DutyOutOfRange { led: 2, duty: 101 })
+    ));
+}
+
+#[test]
+fn triggers_are_restored() {
+    let leds = FakeLedClass::new().unwrap();
+    leds.add("status", 1, "heartbeat").unwrap();
+    leds.add("pwm", 100, "none").unwrap();
+    let led_names = names(&["status", "pwm"]);
+
+    let mut backend = LedClassBackend::open(
+        leds.path(),
+        &led_names,
+        true,
+        Box::new(MockBackend::new([])),
+    )
+    .unwrap();
+    // Reading leaves the triggers alone; the first write takes them
+    assert_eq!(backend.read_duties().unwrap(), [0, 0]);
+    let trigger = leds.trigger("status").unwrap();
+    assert_eq!(active_trigger(&trigger), Some("heartbeat"));
+    backend.write_duty(1, 40).unwrap();
+    assert_eq!(leds.trigger("status").unwrap(), "none");
+    backend.release().unwrap();
+    assert_eq!(leds.trigger("status").unwrap(), "heartbeat");
+    assert_eq!(leds.trigger("pwm").unwrap(), "[none] timer heartbeat");
+    // Only once
+    leds.add("status", 1, "none").unwrap();
+    backend.release().unwrap();
+    drop(backend);
+    assert_eq!(leds.trigger("status").unwrap(), "[none] timer heartbeat");
+
+    // A one-shot command keeps the trigger off, or it would override the
+    // brightness just written
+    leds.add("status", 1, "heartbeat").unwrap();
+    let mut backend = LedClassBackend::open(
+        leds.path(),
+        &led_names,
+        true,
+        Box::new(MockBackend::new([])),
+    )
+    .unwrap();
+    backend.write_duties(&[100, 30].into()).unwrap();
+    drop(backend);
+    assert_eq!(leds.trigger("status").unwrap(), "none");
+    assert_eq!(leds.brightness("status").unwrap(), 1);
+    leds.add("status", 1, "heartbeat").unwrap();
+
+    // Left alone unless asked
+    let mut backend = LedClassBackend::open(
+        leds.path(),
+        &led_names,
+        false,
+        Box::new(MockBackend::new([])),
+    )
+    .unwrap();
+    backend.write_duties(&[100, 30].into()).unwrap();
+    assert_eq!(leds.brightness("status").unwrap(), 1);
+    assert_eq!(leds.brightness("pwm").unwrap(), 30);
+    drop(backend);
+    let trigger = leds.trigger("status").unwrap();
+    assert_eq!(active_trigger(&trigger), Some("heartbeat"));
+}
+
+#[test]
+fn service_shutdown_restores_triggers() {
+    let leds = FakeLedClass::new().unwrap();
+    leds.add("status", 100, "timer").unwrap();
+    leds.add("green", 100, "none").unwrap();
+    leds.add("blue", 100, "none").unwrap();
+    let backend = LedClassBackend::open(
+        leds.path(),
+        &names(&["status", "green", "blue"]),
+        true,
+        Box::new(MockBackend::new([5.0; 100])),
+    )
+    .unwrap();
+    let service = Service::start(Box::new(backend), Config::default()).unwrap();
+    wait_for("the trigger to be taken", || {
+        leds.trigger("status").unwrap() == "none"
+    });
+    service.shutdown().unwrap();
+    assert_eq!(leds.trigger("status").unwrap(), "timer");
+}
+
+#[test]
+fn missing_led_leaves_the_others() {
+    let leds = FakeLedClass::new().unwrap();
+    leds.add("status", 1, "timer").unwrap();
+    match LedClassBackend::open(
+        leds.path(),
+        &names(&["status", "absent"]),
+        true,
+        Box::new(MockBackend::new([])),
+    ) {
+        Err(ControllerError::DeviceMissing { path }) => assert!(path.ends_with("absent")),
+        other => panic!("expected a missing LED, got {:?}", other),
+    }
+    let trigger = leds.trigger("status").unwrap();
+    assert_eq!(active_trigger(&trigger), Some("timer"));
+}
+
+#[test]
+fn leds_section() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let leds = FakeLedClass::new().unwrap();
+    leds.add("a", 255, "none").unwrap();
+    leds.add("b", 1, "none").unwrap();
+
+    let text = format!(
+        "[leds]\npath = {:?}\nnames = [\"b\", \"a\"]\nspeed = {:?}\n",
+        leds.path(),
+        fake.path().join("button_speed")
+    );
+    let config = Config::from_toml(&text, Path::new("test.toml")).unwrap();
+    assert!(config.leds.disable_triggers);
+    let kind: BackendKind = "ledclass".parse().unwrap();
+    let mut backend = backend::open_configured(kind, &BackendPaths::default(), &config).unwrap();
+    assert_eq!(backend.read_speed().unwrap(), 10.0);
+    backend.write_duties(&[100, 20].into()).unwrap();
+    assert_eq!(leds.brightness("b").unwrap(), 1);
+    assert_eq!(leds.brightness("a").unwrap(), 51);
+
+    for (bad, reason) in [
+        ("names = []", "at least one"),
+        ("names = [\"a\", \"a\"]", "listed twice"),
+        ("names = [\"../a\"]", "not an LED name"),
+    ] {
+        let err = Config::from_toml(&format!("[leds]\n{}", bad), Path::new("test.toml"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[leds]") && err.contains(reason), "{}", err);
+    }
+}
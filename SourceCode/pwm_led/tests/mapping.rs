@@ -0,0 +1,43 @@
+use pwm_led::{map_speed_to_duty_cycles, MAX_SPEED, MIN_SPEED};
+
+#[test]
+fn floor_and_ceiling() {
+    assert_eq!(map_speed_to_duty_cycles(0.0), (10, 0, 0));
+    assert_eq!(map_speed_to_duty_cycles(MIN_SPEED), (10, 0, 0));
+    assert_eq!(map_speed_to_duty_cycles(MAX_SPEED), (100, 100, 100));
+    assert_eq!(map_speed_to_duty_cycles(MAX_SPEED + 5.0), (100, 100, 100));
+}
+
+#[test]
+fn leds_turn_on_in_sequence() {
+    let table: Vec<_> = (MIN_SPEED as u32..=MAX_SPEED as u32)
+        .map(|speed| map_speed_to_duty_cycles(speed.into()))
+        .collect();
+    assert_eq!(
+        table,
+        vec![
+            (10, 0, 0),
+            (20, 0, 0),
+            (30, 0, 0),
+            (40, 0, 0),
+            (50, 17, 0),
+            (60, 33, 0),
+            (70, 50, 1),
+            (80, 67, 35),
+            (90, 83, 68),
+            (100, 100, 100),
+        ]
+    );
+}
+
+#[test]
+fn fractional_speeds_fall_between_integer_steps() {
+    // 1.9 presses/second used to be truncated to 1 and land on the floor.
+    assert_eq!(map_speed_to_duty_cycles(1.9), (19, 0, 0));
+    assert_eq!(map_speed_to_duty_cycles(4.5), (45, 8, 0));
+
+    let (low, _, _) = map_speed_to_duty_cycles(3.0);
+    let (mid, _, _) = map_speed_to_duty_cycles(3.5);
+    let (high, _, _) = map_speed_to_duty_cycles(4.0);
+    assert!(low < mid && mid < high);
+}
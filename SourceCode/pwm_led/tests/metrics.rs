@@ -0,0 +1,117 @@
+use std::io;
+use std::iter;
+
+use pwm_led::{
+    Config, ControllerError, DutyFrame, Histogram, LedBackend, Metrics, MockBackend, Result,
+    Service, LATENCY_BUCKETS,
+};
+
+mod common;
+
+use common::wait_for;
+
+// Reads speeds from a mock but fails every write.
+struct ReadOnly(MockBackend);
+
+impl LedBackend for ReadOnly {
+    fn led_count(&self) -> usize {
+        self.0.led_count()
+    }
+
+    fn read_speed(&mut self) -> Result<f64> {
+        self.0.read_speed()
+    }
+
+    fn read_duty(&mut self, led: usize) -> Result<u32> {
+        self.0.read_duty(led)
+    }
+
+    fn write_duty(&mut self, _led: usize, _duty: u32) -> Result<()> {
+        Err(ControllerError::from_io("<read-only>", denied()))
+    }
+
+    fn write_duties(&mut self, _frame: &DutyFrame) -> Result<()> {
+        Err(ControllerError::from_io("<read-only>", denied()))
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "read-only")
+}
+
+fn start(backend: impl LedBackend + Send + 'static) -> Service {
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 5;
+    Service::start(Box::new(backend), config).unwrap()
+}
+
+#[test]
+fn histogram_buckets() {
+    let mut histogram = Histogram::default();
+    histogram.observe(0.00005);
+    histogram.observe(0.0001);
+    histogram.observe(0.003);
+    histogram.observe(1.0);
+
+    assert_eq!(histogram.count, 4);
+    assert!((histogram.sum - 1.00315).abs() < 1e-9);
+    assert_eq!(histogram.buckets[0], 2);
+    assert_eq!(histogram.buckets[5], 1);
+    assert_eq!(histogram.buckets[LATENCY_BUCKETS.len()], 1);
+    assert_eq!(histogram.buckets.iter().sum::<u64>(), 4);
+}
+
+#[test]
+fn counts_loop_passes_and_read_errors() {
+    // Five readings, then every read fails
+    let service = start(MockBackend::new(iter::repeat_n(4.0, 5)));
+    wait_for("read errors", || service.metrics().read_errors >= 3);
+
+    let metrics = service.metrics();
+    assert_eq!(metrics.write_errors, 0);
+    assert!(metrics.iterations >= 8);
+    assert_eq!(metrics.loop_latency.count, metrics.iterations);
+    service.stop();
+}
+
+#[test]
+fn counts_write_errors() {
+    let service = start(ReadOnly(MockBackend::new(iter::repeat_n(10.0, 100_000))));
+    wait_for("write errors", || service.metrics().write_errors >= 2);
+    assert_eq!(service.metrics().read_errors, 0);
+    assert!(service.status().error.is_some());
+    service.stop();
+}
+
+#[test]
+fn renders_prometheus_text() {
+    let service = start(MockBackend::new(iter::repeat_n(1.0, 100_000)));
+    wait_for("first write", || {
+        service.status().duties == Some([10, 0, 0].into())
+    });
+    let text = service.metrics().render(&service.status());
+
+    for line in [
+        "# TYPE pwm_led_speed gauge",
+        "pwm_led_speed 1\n",
+        "pwm_led_duty{led=\"1\"} 10\n",
+        "pwm_led_duty{led=\"3\"} 0\n",
+        "# TYPE pwm_led_read_errors_total counter",
+        "pwm_led_write_errors_total 0\n",
+        "# TYPE pwm_led_loop_duration_seconds histogram",
+        "pwm_led_loop_duration_seconds_bucket{le=\"0.0001\"} ",
+        "pwm_led_loop_duration_seconds_bucket{le=\"+Inf\"} ",
+        "pwm_led_loop_duration_seconds_sum ",
+    ] {
+        assert!(text.contains(line), "{:?} missing from\n{}", line, text);
+    }
+    service.stop();
+
+    // Before the first reading there are no samples for the gauges
+    let service = start(MockBackend::new([]));
+    let text = Metrics::default().render(&service.status());
+    assert!(text.contains("# TYPE pwm_led_duty gauge"));
+    assert!(!text.contains("\npwm_led_speed "));
+    assert!(text.contains("pwm_led_loop_duration_seconds_count 0\n"));
+    service.stop();
+}
@@ -0,0 +1,210 @@
+use std::io::Cursor;
+use std::iter;
+
+use pwm_led::mqtt::{topic_matches, Packet, Will};
+use pwm_led::{Config, FakeBroker, MockBackend, Mode, MqttBridge, MqttConfig, Service};
+use serde_json::Value;
+
+mod common;
+
+use common::wait_for;
+
+fn start(broker: &FakeBroker) -> (Service, MqttBridge) {
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 10;
+    let backend = MockBackend::new(iter::repeat_n(10.0, 100_000));
+    let service = Service::start(Box::new(backend), config).unwrap();
+
+    let mqtt = MqttConfig {
+        broker: broker.addr().to_string(),
+        client_id: "test-node".into(),
+        reconnect_min_ms: 20,
+        reconnect_max_ms: 100,
+        ..MqttConfig::default()
+    };
+    let bridge = MqttBridge::start(service.clone(), mqtt);
+    (service, bridge)
+}
+
+fn json(text: Option<String>) -> Value {
+    serde_json::from_str(&text.expect("no retained message")).unwrap()
+}
+
+#[test]
+fn packets_round_trip() {
+    let packets = [
+        Packet::Connect {
+            client_id: "node".into(),
+            keep_alive: 30,
+            will: Some(Will {
+                topic: "pwm_led/status".into(),
+                payload: b"offline".to_vec(),
+                retain: true,
+            }),
+            username: Some("user".into()),
+            password: Some("secret".into()),
+        },
+        Packet::ConnAck { code: 5 },
+        Packet::Publish {
+            topic: "pwm_led/led1/state".into(),
+            // Long enough for a two-byte remaining length
+            payload: vec![b'x'; 300],
+            retain: true,
+        },
+        Packet::Subscribe {
+            id: 7,
+            topics: vec!["pwm_led/+/set".into(), "a/#".into()],
+        },
+        Packet::SubAck { id: 7, count: 2 },
+        Packet::PingReq,
+        Packet::PingResp,
+        Packet::Disconnect,
+    ];
+    for packet in packets {
+        let mut bytes = Vec::new();
+        packet.write(&mut bytes).unwrap();
+        assert_eq!(Packet::read(&mut Cursor::new(bytes)).unwrap(), packet);
+    }
+}
+
+#[test]
+fn topic_filters() {
+    assert!(topic_matches("pwm_led/+/set", "pwm_led/led2/set"));
+    assert!(!topic_matches("pwm_led/+/set", "pwm_led/led2/state"));
+    assert!(!topic_matches("pwm_led/+/set", "pwm_led/set"));
+    assert!(topic_matches("pwm_led/#", "pwm_led/led2/state"));
+    assert!(topic_matches("pwm_led/speed", "pwm_led/speed"));
+    assert!(!topic_matches("pwm_led/speed", "pwm_led/speed/raw"));
+}
+
+#[test]
+fn publishes_state_and_discovery() {
+    let broker = FakeBroker::start().unwrap();
+    let (service, bridge) = start(&broker);
+
+    wait_for("LED state", || {
+        broker
+            .retained("pwm_led/led3/state")
+            .is_some_and(|state| state.contains("100"))
+    });
+    assert_eq!(broker.retained("pwm_led/speed").unwrap(), "10");
+    assert_eq!(broker.retained("pwm_led/status").unwrap(), "online");
+    assert_eq!(broker.retained("pwm_led/mode").unwrap(), "auto");
+    assert_eq!(
+        json(broker.retained("pwm_led/led1/state")),
+        serde_json::json!({ "state": "ON", "brightness": 100 })
+    );
+
+    let light = json(broker.retained("homeassistant/light/test_node/led2/config"));
+    assert_eq!(light["schema"], "json");
+    assert_eq!(light["command_topic"], "pwm_led/led2/set");
+    assert_eq!(light["state_topic"], "pwm_led/led2/state");
+    assert_eq!(light["brightness_scale"], 100);
+    assert_eq!(light["unique_id"], "test_node_led2");
+    let sensor = json(broker.retained("homeassistant/sensor/test_node/speed/config"));
+    assert_eq!(sensor["state_topic"], "pwm_led/speed");
+    assert!(broker
+        .retained("homeassistant/select/test_node/mode/config")
+        .is_some());
+
+    // A clean stop marks the controller offline
+    bridge.stop();
+    assert_eq!(broker.retained("pwm_led/status").unwrap(), "offline");
+    service.stop();
+}
+
+#[test]
+fn light_and_mode_commands() {
+    let broker = FakeBroker::start().unwrap();
+    let (service, bridge) = start(&broker);
+    wait_for("subscription", || broker.retained("pwm_led/mode").is_some());
+
+    broker.publish("pwm_led/led2/set", r#"{"state": "ON", "brightness": 40}"#);
+    wait_for("override", || {
+        service.status().duties == Some([100, 40, 100].into())
+    });
+    assert_eq!(service.status().mode, Mode::Manual);
+    wait_for("manual mode", || {
+        broker.retained("pwm_led/mode").as_deref() == Some("manual")
+    });
+    wait_for("LED2 state", || {
+        broker
+            .retained("pwm_led/led2/state")
+            .is_some_and(|state| state.contains("40"))
+    });
+
+    broker.publish("pwm_led/led1/set", r#"{"state": "OFF"}"#);
+    broker.publish("pwm_led/led3/set", "25");
+    wait_for("more overrides", || {
+        service.status().duties == Some([0, 40, 25].into())
+    });
+
+    // Bad commands are ignored
+    broker.publish("pwm_led/led9/set", "10");
+    broker.publish("pwm_led/led1/set", "{}");
+
+    broker.publish("pwm_led/mode/set", "auto");
+    wait_for("auto mode", || {
+        service.status().duties == Some([100, 100, 100].into())
+    });
+    assert_eq!(service.status().mode, Mode::Auto);
+
+    bridge.stop();
+    service.stop();
+}
+
+#[test]
+fn reconnects_after_the_broker_drops() {
+    let broker = FakeBroker::start().unwrap();
+    let (service, bridge) = start(&broker);
+    wait_for("first connection", || broker.connections() == 1);
+    wait_for("online", || {
+        broker.retained("pwm_led/status").as_deref() == Some("online")
+    });
+
+    broker.disconnect_all();
+    wait_for("will", || {
+        broker
+            .messages("pwm_led/status")
+            .contains(&"offline".to_string())
+    });
+    wait_for("second connection", || broker.connections() == 2);
+    wait_for("online again", || {
+        broker.retained("pwm_led/status").as_deref() == Some("online")
+    });
+
+    // Commands still work on the new connection
+    broker.publish("pwm_led/led1/set", "5");
+    wait_for("override", || {
+        service.status().duties == Some([5, 100, 100].into())
+    });
+
+    bridge.stop();
+    service.stop();
+}
+
+#[test]
+fn mqtt_config_section() {
+    let config = Config::from_toml(
+        "[mqtt]\nbroker = \"hub.local:1883\"\ntopic_prefix = \"desk\"\n",
+        "test.toml".as_ref(),
+    )
+    .unwrap();
+    let mqtt = config.mqtt.unwrap();
+    assert_eq!(mqtt.broker, "hub.local:1883");
+    assert_eq!(mqtt.topic("led1/set"), "desk/led1/set");
+    assert_eq!(mqtt.discovery_prefix, "homeassistant");
+    assert_eq!(Config::default().mqtt, None);
+
+    for bad in [
+        "[mqtt]\ntopic_prefix = \"desk/#\"\n",
+        "[mqtt]\nbroker = \"\"\n",
+        "[mqtt]\nreconnect_min_ms = 0\n",
+    ] {
+        assert!(
+            Config::from_toml(bad, "test.toml".as_ref()).is_err(),
+            "{}",
+            bad
+        );
+    }
+}
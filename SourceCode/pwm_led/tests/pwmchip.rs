@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pwm_led::backend::{self, BackendKind, BackendPaths};
+use pwm_led::pwmchip::{duty_to_ns, ns_to_duty};
+use pwm_led::{
+    Config, ControllerError, FakePwmChip, FakeSysfsBackend, LedBackend, MockBackend,
+    PwmChipBackend, SpeedFile,
+};
+
+const PERIOD_NS: u64 = 1_000_000;
+
+fn config(text: &str) -> Result<Config, pwm_led::ConfigError> {
+    Config::from_toml(text, Path::new("test.toml"))
+}
+
+#[test]
+fn duty_in_nanoseconds() {
+    assert_eq!(duty_to_ns(0, PERIOD_NS), 0);
+    assert_eq!(duty_to_ns(40, PERIOD_NS), 400_000);
+    assert_eq!(duty_to_ns(100, PERIOD_NS), PERIOD_NS);
+    // No overflow on long periods
+    assert_eq!(duty_to_ns(100, u64::MAX), u64::MAX);
+
+    for duty in 0..=100 {
+        assert_eq!(ns_to_duty(duty_to_ns(duty, 3_333), 3_333), duty);
+    }
+    // Rounded to the nearest duty, capped at 100
+    assert_eq!(ns_to_duty(404_999, PERIOD_NS), 40);
+    assert_eq!(ns_to_duty(405_000, PERIOD_NS), 41);
+    assert_eq!(ns_to_duty(2 * PERIOD_NS, PERIOD_NS), 100);
+    assert_eq!(ns_to_duty(5, 0), 0);
+}
+
+#[test]
+fn drives_exported_channels() {
+    let chip = FakePwmChip::new(4).unwrap();
+    for channel in 0..3 {
+        chip.export(channel).unwrap();
+    }
+    let speed = MockBackend::new([2.5]);
+    let mut backend =
+        PwmChipBackend::open(chip.path(), &[0, 1, 2], PERIOD_NS, Box::new(speed)).unwrap();
+    assert_eq!(backend.led_count(), 3);
+    assert_eq!(chip.export_request().unwrap(), None);
+    for channel in 0..3 {
+        assert_eq!(chip.attribute(channel, "period").unwrap(), PERIOD_NS);
+        assert_eq!(chip.attribute(channel, "duty_cycle").unwrap(), 0);
+        assert_eq!(chip.attribute(channel, "enable").unwrap(), 1);
+    }
+
+    backend.write_duties(&[40, 0, 100].into()).unwrap();
+    assert_eq!(chip.attribute(0, "duty_cycle").unwrap(), 400_000);
+    assert_eq!(chip.attribute(1, "duty_cycle").unwrap(), 0);
+    assert_eq!(chip.attribute(2, "duty_cycle").unwrap(), PERIOD_NS);
+    backend.write_duty(1, 55).unwrap();
+    assert_eq!(backend.read_duties().unwrap(), [40, 55, 100]);
+
+    // Validated before anything is written
+    assert!(matches!(
+        backend.write_duties(&[1, 2, 101].into()),
+        Err(ControllerError::DutyOutOfRange { led: 2, duty: 101 })
+    ));
+    assert!(matches!(
+        backend.read_duty(3),
+        Err(ControllerError::LedOutOfRange { led: 3, count: 3 })
+    ));
+    assert_eq!(backend.read_duties().unwrap(), [40, 55, 100]);
+
+    // The speed comes from the separate source
+    assert_eq!(backend.read_speed().unwrap(), 2.5);
+    assert!(backend.read_speed().is_err());
+}
+
+#[test]
+fn keeps_the_duties_of_set_up_channels() {
+    let chip = FakePwmChip::new(3).unwrap();
+    for channel in 0..3 {
+        chip.export(channel).unwrap();
+    }
+    let channel = |n: u32| chip.path().join(format!("pwm{}", n));
+    // Lit by an earlier run with the same period
+    fs::write(channel(0).join("period"), PERIOD_NS.to_string()).unwrap();
+    fs::write(channel(0).join("duty_cycle"), "550000").unwrap();
+    fs::write(channel(0).join("enable"), "1").unwrap();
+    // Another period: a duty that fits is kept, one that does not goes to 0
+    fs::write(channel(1).join("period"), "2000000").unwrap();
+    fs::write(channel(1).join("duty_cycle"), "300000").unwrap();
+    fs::write(channel(2).join("period"), "2000000").unwrap();
+    fs::write(channel(2).join("duty_cycle"), "1500000").unwrap();
+
+    let speed = MockBackend::new([]);
+    let mut backend =
+        PwmChipBackend::open(chip.path(), &[0, 1, 2], PERIOD_NS, Box::new(speed)).unwrap();
+    assert_eq!(backend.read_duties().unwrap(), [55, 30, 0]);
+    for channel in 0..3 {
+        assert_eq!(chip.attribute(channel, "period").unwrap(), PERIOD_NS);
+        assert_eq!(chip.attribute(channel, "enable").unwrap(), 1);
+    }
+
+    // Another one-shot command leaves the other LEDs as they are
+    backend.write_duty(1, 80).unwrap();
+    drop(backend);
+    let mut backend = PwmChipBackend::open(
+        chip.path(),
+        &[0, 1, 2],
+        PERIOD_NS,
+        Box::new(MockBackend::new([])),
+    )
+    .unwrap();
+    assert_eq!(backend.read_duties().unwrap(), [55, 80, 0]);
+}
+
+#[test]
+fn exports_missing_channels() {
+    let chip = FakePwmChip::new(4).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(2);
+
+    thread::scope(|scope| {
+        // Answers export requests like the kernel
+        scope.spawn(|| {
+            let mut exported = Vec::new();
+            while exported.len() < 2 && Instant::now() < deadline {
+                if let Some(channel) = chip.export_request().unwrap() {
+                    if !exported.contains(&channel) {
+                        chip.export(channel).unwrap();
+                        exported.push(channel);
+                    }
+                }
+                thread::sleep(Duration::from_millis(2));
+            }
+        });
+
+        let speed = MockBackend::new([]);
+        let mut backend =
+            PwmChipBackend::open(chip.path(), &[3, 1], PERIOD_NS, Box::new(speed)).unwrap();
+        backend.write_duties(&[10, 90].into()).unwrap();
+    });
+    assert_eq!(chip.attribute(3, "duty_cycle").unwrap(), 100_000);
+    assert_eq!(chip.attribute(1, "duty_cycle").unwrap(), 900_000);
+}
+
+#[test]
+fn missing_chip_or_channel() {
+    let speed = || Box::new(MockBackend::new([]));
+    assert!(matches!(
+        PwmChipBackend::open("/nonexistent/pwmchip9", &[0], PERIOD_NS, speed()),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+
+    // An export the kernel never answers
+    let chip = FakePwmChip::new(1).unwrap();
+    match PwmChipBackend::open(chip.path(), &[0], PERIOD_NS, speed()) {
+        Err(ControllerError::DeviceMissing { path }) => {
+            assert!(path.starts_with(chip.path().join("pwm0")))
+        }
+        other => panic!("expected a missing channel, got {:?}", other),
+    }
+    assert_eq!(chip.export_request().unwrap(), Some(0));
+}
+
+#[test]
+fn speed_files() {
+    // Not imported at the top: every `LedBackend` has its `read_speed` too
+    use pwm_led::SpeedSource;
+
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(3.5).unwrap();
+    let mut speed = SpeedFile::new(fake.path().join("button_speed"));
+    assert_eq!(speed.read_speed().unwrap(), 3.5);
+    assert!(speed.speed_notifier().is_some());
+
+    fake.set_interval_ns(Some(400_000_000)).unwrap();
+    let mut interval = SpeedFile::new(fake.path().join("press_interval_ns"));
+    assert_eq!(interval.read_speed().unwrap(), 2.5);
+
+    fs::write(fake.path().join("button_speed"), "fast\n").unwrap();
+    assert!(matches!(
+        speed.read_speed(),
+        Err(ControllerError::MalformedResponse { .. })
+    ));
+    assert!(matches!(
+        SpeedFile::new("/nonexistent/speed").read_speed(),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+}
+
+#[test]
+fn pwm_section() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(6.0).unwrap();
+    let chip = FakePwmChip::new(2).unwrap();
+    chip.export(0).unwrap();
+    chip.export(1).unwrap();
+
+    let text = format!(
+        "[pwm]\nchip = {:?}\nchannels = [1, 0]\nperiod_ns = 20000\nspeed = {:?}\n",
+        chip.path(),
+        fake.path().join("button_speed")
+    );
+    let parsed = config(&text).unwrap();
+    let kind: BackendKind = "pwmchip".parse().unwrap();
+    assert_eq!(kind.to_string(), "pwmchip");
+    let mut backend = backend::open_configured(kind, &BackendPaths::default(), &parsed).unwrap();
+    assert_eq!(backend.led_count(), 2);
+    assert_eq!(backend.read_speed().unwrap(), 6.0);
+    backend.write_duties(&[25, 50].into()).unwrap();
+    assert_eq!(chip.attribute(1, "duty_cycle").unwrap(), 5_000);
+    assert_eq!(chip.attribute(0, "duty_cycle").unwrap(), 10_000);
+
+    for (bad, reason) in [
+        ("channels = []", "at least one"),
+        ("channels = [0, 2, 0]", "channel 0 is listed twice"),
+        ("period_ns = 0", "period_ns"),
+    ] {
+        let err = config(&format!("[pwm]\n{}", bad)).unwrap_err().to_string();
+        assert!(err.contains("[pwm]") && err.contains(reason), "{}", err);
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pwm_led::{
+    run_until_shutdown, run_until_stopped, Config, ControllerError, DutyFrame, FakeSysfsBackend,
+    LedBackend, Service, ShutdownPolicy, ShutdownSignals, SysfsBackend,
+};
+
+mod common;
+
+use common::wait_for;
+
+// Reports a speed but never finishes a write, like a device that hung.
+struct HangingBackend;
+
+impl LedBackend for HangingBackend {
+    fn led_count(&self) -> usize {
+        3
+    }
+
+    fn read_speed(&mut self) -> pwm_led::Result<f64> {
+        Ok(5.0)
+    }
+
+    fn read_duty(&mut self, _led: usize) -> pwm_led::Result<u32> {
+        Ok(0)
+    }
+
+    fn write_duty(&mut self, _led: usize, _duty: u32) -> pwm_led::Result<()> {
+        thread::sleep(Duration::from_secs(3600));
+        Ok(())
+    }
+
+    fn write_duties(&mut self, _frame: &DutyFrame) -> pwm_led::Result<()> {
+        thread::sleep(Duration::from_secs(3600));
+        Ok(())
+    }
+}
+
+fn config(text: &str) -> Config {
+    Config::from_toml(text, Path::new("test.toml")).unwrap()
+}
+
+#[test]
+fn final_frames() {
+    let initial = DutyFrame::from([30, 20, 10]);
+    assert_eq!(
+        ShutdownPolicy::Off.final_frame(3, Some(&initial)),
+        Some([0, 0, 0].into())
+    );
+    assert_eq!(
+        ShutdownPolicy::Restore.final_frame(3, Some(&initial)),
+        Some(initial)
+    );
+    // The character device cannot report the duties it started with
+    assert_eq!(
+        ShutdownPolicy::Restore.final_frame(3, None),
+        Some([0, 0, 0].into())
+    );
+    assert_eq!(ShutdownPolicy::Leave.final_frame(3, None), None);
+}
+
+#[test]
+fn shutdown_section() {
+    let parsed = config(
+        r#"
+        [shutdown]
+        policy = "restore"
+        timeout_ms = 500
+        "#,
+    );
+    assert_eq!(parsed.shutdown.policy, ShutdownPolicy::Restore);
+    assert_eq!(parsed.shutdown.timeout(), Duration::from_millis(500));
+    assert_eq!(Config::default().shutdown.policy, ShutdownPolicy::Off);
+
+    let zero = Config::from_toml("[shutdown]\ntimeout_ms = 0", Path::new("test.toml"));
+    assert!(zero
+        .unwrap_err()
+        .to_string()
+        .contains("[shutdown] timeout_ms"));
+    assert!(Config::from_toml("[shutdown]\npolicy = \"dim\"", Path::new("test.toml")).is_err());
+}
+
+#[test]
+fn loop_stops_between_frames() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let mut backend = SysfsBackend::with_path(fake.path()).unwrap();
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            wait_for("the first frame", || {
+                fake.duties().ok() == Some([100, 100, 100].into())
+            });
+            stop.store(true, Ordering::SeqCst);
+        });
+        // The default interval is long; stopping must not wait for it
+        let start = Instant::now();
+        run_until_stopped(
+            &mut backend,
+            &Config::default(),
+            Duration::from_secs(60),
+            &stop,
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    });
+}
+
+#[test]
+fn signalled_loop_restores_the_leds() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let mut backend = SysfsBackend::with_path(fake.path()).unwrap();
+    backend.write_duties(&[30, 20, 10].into()).unwrap();
+
+    // Stands in for the signal `pwmledctl run` waits for
+    let (signal, signalled) = mpsc::channel::<()>();
+    let shutdown = move || {
+        let _ = signalled.recv();
+    };
+    let config = config("[shutdown]\npolicy = \"restore\"");
+    let running = thread::spawn(move || {
+        run_until_shutdown(shutdown, config.shutdown.timeout(), move |stop| {
+            let initial = backend.read_duties().ok();
+            run_until_stopped(&mut backend, &config, Duration::from_millis(10), stop)?;
+            config.shutdown.policy.apply(&mut backend, initial.as_ref())
+        })
+    });
+
+    wait_for("the first frame", || {
+        fake.duties().ok() == Some([100, 100, 100].into())
+    });
+    signal.send(()).unwrap();
+    running.join().unwrap().unwrap();
+    assert_eq!(fake.duties().unwrap(), [30, 20, 10]);
+}
+
+#[test]
+fn hung_write_times_out() {
+    let start = Instant::now();
+    // Shut down once the loop is stuck in its first write
+    let result = run_until_shutdown(
+        || thread::sleep(Duration::from_millis(50)),
+        Duration::from_millis(100),
+        |stop| {
+            run_until_stopped(
+                &mut HangingBackend,
+                &Config::default(),
+                Duration::from_millis(10),
+                stop,
+            )
+        },
+    );
+    assert!(matches!(
+        result,
+        Err(ControllerError::ShutdownTimedOut { .. })
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn service_shutdown_applies_the_policy() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let mut backend = SysfsBackend::with_path(fake.path()).unwrap();
+    backend.write_duties(&[30, 20, 10].into()).unwrap();
+
+    let service = Service::start(
+        Box::new(backend),
+        config("[shutdown]\npolicy = \"restore\""),
+    )
+    .unwrap();
+    wait_for("the first frame", || {
+        fake.duties().ok() == Some([100, 100, 100].into())
+    });
+    let events = service.subscribe();
+    service.shutdown().unwrap();
+    assert_eq!(fake.duties().unwrap(), [30, 20, 10]);
+    // Event streams end like with `stop`
+    while events.recv().is_ok() {}
+
+    // `stop` leaves the LEDs alone whatever the policy
+    let backend = SysfsBackend::with_path(fake.path()).unwrap();
+    let service = Service::start(Box::new(backend), Config::default()).unwrap();
+    wait_for("the first frame", || {
+        fake.duties().ok() == Some([100, 100, 100].into())
+    });
+    service.stop();
+    assert_eq!(fake.duties().unwrap(), [100, 100, 100]);
+}
+
+#[test]
+fn service_shutdown_times_out_on_a_hung_device() {
+    let service = Service::start(
+        Box::new(HangingBackend),
+        config("[shutdown]\ntimeout_ms = 100"),
+    )
+    .unwrap();
+    thread::sleep(Duration::from_millis(20));
+    let start = Instant::now();
+    assert!(matches!(
+        service.shutdown(),
+        Err(ControllerError::ShutdownTimedOut { timeout }) if timeout == Duration::from_millis(100)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn blocked_signals_are_waited_for() {
+    // Only this thread blocks SIGTERM, and `raise` sends it to this thread,
+    // so the rest of the test process never sees it
+    let signals = ShutdownSignals::block().unwrap();
+    // SAFETY: raising a blocked signal only marks it pending
+    assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+    assert_eq!(signals.wait().unwrap(), libc::SIGTERM);
+    assert_eq!(pwm_led::signal_name(libc::SIGTERM), "SIGTERM");
+}
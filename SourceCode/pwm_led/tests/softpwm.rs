@@ -0,0 +1,198 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pwm_led::backend::{self, BackendKind, BackendPaths};
+use pwm_led::{
+    Config, ControllerError, LedBackend, LineSink, MockBackend, RecordingLineSink, SoftPwm,
+    SoftPwmBackend,
+};
+
+const PERIOD: Duration = Duration::from_millis(10);
+
+// Sleeps through `settle`, then returns the window measured over `span`.
+fn window(settle: Duration, span: Duration) -> (Instant, Instant) {
+    thread::sleep(settle);
+    let from = Instant::now();
+    thread::sleep(span);
+    (from, Instant::now())
+}
+
+fn fraction(sink: &RecordingLineSink, line: usize, (from, to): (Instant, Instant)) -> f64 {
+    sink.high_time(line, from, to).as_secs_f64() / (to - from).as_secs_f64()
+}
+
+#[test]
+fn duties_become_duty_cycles() {
+    let sink = RecordingLineSink::new(3);
+    let engine = SoftPwm::start(Box::new(sink.clone()), &[PERIOD; 3], None).unwrap();
+    assert_eq!(engine.lines(), 3);
+    assert_eq!(sink.levels(), 0);
+
+    engine.set_duties(&[25, 0, 100].into()).unwrap();
+    assert_eq!(engine.duties(), [25, 0, 100]);
+    let measured = window(Duration::from_millis(30), Duration::from_millis(300));
+    let quarter = fraction(&sink, 0, measured);
+    assert!((0.15..0.35).contains(&quarter), "LED1 high {:.2}", quarter);
+    assert_eq!(fraction(&sink, 1, measured), 0.0);
+    assert_eq!(fraction(&sink, 2, measured), 1.0);
+    // Always on is one edge, not one per period
+    assert_eq!(sink.rising_edges(2), 1);
+    assert_eq!(sink.rising_edges(1), 0);
+
+    engine.set_duties(&[75, 50, 0].into()).unwrap();
+    let measured = window(Duration::from_millis(30), Duration::from_millis(300));
+    let three_quarters = fraction(&sink, 0, measured);
+    assert!(
+        (0.65..0.85).contains(&three_quarters),
+        "LED1 high {:.2}",
+        three_quarters
+    );
+    assert_eq!(fraction(&sink, 2, measured), 0.0);
+}
+
+#[test]
+fn each_line_has_its_own_period() {
+    let sink = RecordingLineSink::new(2);
+    let periods = [Duration::from_millis(5), Duration::from_millis(20)];
+    let engine = SoftPwm::start(Box::new(sink.clone()), &periods, None).unwrap();
+    engine.set_duties(&[50, 50].into()).unwrap();
+    thread::sleep(Duration::from_millis(400));
+
+    let fast = sink.rising_edges(0) as f64;
+    let slow = sink.rising_edges(1) as f64;
+    assert!((2.5..5.5).contains(&(fast / slow)), "{} vs {}", fast, slow);
+
+    engine.set_period(1, Duration::from_millis(5)).unwrap();
+    assert!(matches!(
+        engine.set_period(2, PERIOD),
+        Err(ControllerError::LedOutOfRange { led: 2, count: 2 })
+    ));
+}
+
+#[test]
+fn stopping_turns_the_lines_off() {
+    let sink = RecordingLineSink::new(2);
+    let mut engine = SoftPwm::start(Box::new(sink.clone()), &[PERIOD; 2], None).unwrap();
+    engine.set_duties(&[100, 30].into()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(sink.levels() & 1, 1);
+
+    engine.stop();
+    assert_eq!(sink.levels(), 0);
+    let last = *sink.changes().last().unwrap();
+    assert_eq!((last.values, last.mask), (0, 0b11));
+
+    // One wake-up per edge at most, each measured
+    let jitter = engine.jitter();
+    assert!(jitter.wakeups() >= 5, "{:?}", jitter);
+    assert!(jitter.max >= jitter.mean());
+    assert_eq!(
+        jitter.histogram.buckets.iter().sum::<u64>(),
+        jitter.wakeups()
+    );
+}
+
+#[test]
+fn priority_is_optional() {
+    // Granted as root, refused otherwise; the engine runs either way
+    let sink = RecordingLineSink::new(1);
+    let engine = SoftPwm::start(Box::new(sink.clone()), &[PERIOD], Some(10)).unwrap();
+    engine.set_duties(&[100].into()).unwrap();
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(sink.levels(), 1);
+    let _ = engine.realtime();
+}
+
+struct BrokenSink;
+
+impl LineSink for BrokenSink {
+    fn lines(&self) -> usize {
+        1
+    }
+
+    fn set_values(&mut self, values: u64, _mask: u64) -> pwm_led::Result<()> {
+        if values == 0 {
+            Ok(())
+        } else {
+            Err(ControllerError::Unsupported("broken line"))
+        }
+    }
+}
+
+#[test]
+fn sink_errors_are_reported() {
+    let engine = SoftPwm::start(Box::new(BrokenSink), &[PERIOD], None).unwrap();
+    engine.set_duties(&[50].into()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(matches!(
+        engine.set_duties(&[60].into()),
+        Err(ControllerError::Unsupported("broken line"))
+    ));
+
+    assert!(matches!(
+        SoftPwm::start(Box::new(RecordingLineSink::new(2)), &[PERIOD; 3], None),
+        Err(ControllerError::ChannelMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+}
+
+#[test]
+fn backend_takes_duty_frames() {
+    let sink = RecordingLineSink::new(3);
+    let engine = SoftPwm::start(Box::new(sink.clone()), &[PERIOD; 3], None).unwrap();
+    let mut backend = SoftPwmBackend::new(engine, Box::new(MockBackend::new([3.0])));
+    assert_eq!(backend.led_count(), 3);
+    assert_eq!(backend.read_speed().unwrap(), 3.0);
+
+    backend.write_duties(&[100, 0, 0].into()).unwrap();
+    backend.write_duty(2, 100).unwrap();
+    assert_eq!(backend.read_duties().unwrap(), [100, 0, 100]);
+    assert!(matches!(
+        backend.write_duties(&[1, 2, 101].into()),
+        Err(ControllerError::DutyOutOfRange { led: 2, duty: 101 })
+    ));
+    assert!(matches!(
+        backend.write_duties(&[1, 2].into()),
+        Err(ControllerError::ChannelMismatch { .. })
+    ));
+    thread::sleep(Duration::from_millis(30));
+    assert_eq!(sink.levels(), 0b101);
+
+    // Dropping the backend stops the engine
+    drop(backend);
+    assert_eq!(sink.levels(), 0);
+}
+
+#[test]
+fn softpwm_section() {
+    let parsed = Config::from_toml(
+        "[softpwm]\nchip = \"/nonexistent/gpiochip9\"\nlines = [5, 6]\nperiod_us = 2000\npriority = 50\n",
+        Path::new("test.toml"),
+    )
+    .unwrap();
+    assert_eq!(parsed.softpwm.lines, [5, 6]);
+    assert_eq!(parsed.softpwm.period(), Duration::from_millis(2));
+    assert_eq!(parsed.softpwm.priority, Some(50));
+
+    let kind: BackendKind = "softpwm".parse().unwrap();
+    assert_eq!(kind.to_string(), "softpwm");
+    assert!(matches!(
+        backend::open_configured(kind, &BackendPaths::default(), &parsed),
+        Err(ControllerError::DeviceMissing { .. })
+    ));
+
+    for (bad, reason) in [
+        ("lines = []", "between 1 and 64"),
+        ("lines = [5, 6, 5]", "line 5 is listed twice"),
+        ("period_us = 50", "at least 100"),
+        ("priority = 0", "between 1 and 99"),
+    ] {
+        let err = Config::from_toml(&format!("[softpwm]\n{}", bad), Path::new("test.toml"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[softpwm]") && err.contains(reason), "{}", err);
+    }
+}
@@ -0,0 +1,67 @@
+use pwm_led::chardev::parse_speed_line;
+use pwm_led::speed::{parse_speed, speed_from_interval_ns};
+use pwm_led::{ControllerError, FakeSysfsBackend, LedBackend, MockBackend};
+
+#[test]
+fn decimal_and_integer_speeds() {
+    assert_eq!(parse_speed("3"), Some(3.0));
+    assert_eq!(parse_speed("3.75"), Some(3.75));
+    assert_eq!(parse_speed("-1"), None);
+    assert_eq!(parse_speed("NaN"), None);
+    assert_eq!(parse_speed("inf"), None);
+
+    assert_eq!(
+        parse_speed_line("Button Press Speed: 3.75 presses/second\n"),
+        Some(3.75)
+    );
+}
+
+#[test]
+fn interval_to_speed() {
+    assert_eq!(speed_from_interval_ns(0), 0.0);
+    assert_eq!(speed_from_interval_ns(500_000_000), 2.0);
+    assert_eq!(
+        speed_from_interval_ns(526_315_789).to_string()[..5],
+        *"1.900"
+    );
+}
+
+#[test]
+fn sysfs_decimal_speed() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(3.75).unwrap();
+    assert_eq!(fake.read_speed().unwrap(), 3.75);
+}
+
+#[test]
+fn sysfs_prefers_the_interval_attribute() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+
+    // The module truncates 1.9 presses/second to 1 in button_speed.
+    fake.set_speed(1.0).unwrap();
+    fake.set_interval_ns(Some(400_000_000)).unwrap();
+    assert_eq!(fake.read_speed().unwrap(), 2.5);
+
+    fake.set_interval_ns(Some(0)).unwrap();
+    assert_eq!(fake.read_speed().unwrap(), 0.0);
+
+    fake.set_interval_ns(None).unwrap();
+    assert_eq!(fake.read_speed().unwrap(), 1.0);
+}
+
+#[test]
+fn malformed_interval() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    std::fs::write(fake.path().join("press_interval_ns"), "1.5e9\n").unwrap();
+    assert!(matches!(
+        fake.read_speed(),
+        Err(ControllerError::MalformedResponse { .. })
+    ));
+}
+
+#[test]
+fn mock_replays_fractional_speeds() {
+    let mut mock = MockBackend::new([1.9, 2.25]);
+    assert_eq!(mock.read_speed().unwrap(), 1.9);
+    assert_eq!(mock.read_speed().unwrap(), 2.25);
+}
@@ -0,0 +1,115 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pwm_led::{
+    run_every, speed_changes, AdaptiveInterval, Config, DutyFrame, FakeSysfsBackend, LedBackend,
+    MockBackend, Service,
+};
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn interval_adapts_to_changes() {
+    let mut interval = AdaptiveInterval::new(ms(20), ms(500));
+    assert_eq!(interval.current(), ms(500));
+    interval.update(false);
+    assert_eq!(interval.current(), ms(500));
+
+    interval.update(true);
+    assert_eq!(interval.current(), ms(20));
+    let backoff: Vec<_> = (0..6)
+        .map(|_| {
+            interval.update(false);
+            interval.current()
+        })
+        .collect();
+    assert_eq!(backoff, [40, 80, 160, 320, 500, 500].map(ms));
+
+    // A floor above the ceiling is lowered to it
+    let mut interval = AdaptiveInterval::new(ms(20), ms(5));
+    interval.update(true);
+    assert_eq!(interval.current(), ms(5));
+}
+
+#[test]
+fn yields_only_changes() {
+    let mut mock = MockBackend::new([1.0, 1.0, 1.0, 2.5, 2.5, 3.0]);
+    let mut changes = speed_changes(&mut mock, Duration::ZERO);
+    assert!(!changes.is_notified());
+
+    let speeds: Vec<f64> = changes.by_ref().take(3).map(Result::unwrap).collect();
+    assert_eq!(speeds, [1.0, 2.5, 3.0]);
+    // Failed readings come through without ending the stream
+    assert!(changes.next().unwrap().is_err());
+    assert!(changes.next().unwrap().is_err());
+}
+
+#[test]
+fn notifications_wake_the_reader() {
+    let mut mock = MockBackend::new([1.0, 1.0, 5.0]);
+    let notify = mock.notify_with();
+    let mut changes = speed_changes(&mut mock, Duration::from_secs(60));
+    assert!(changes.is_notified());
+    assert_eq!(changes.next().unwrap().unwrap(), 1.0);
+
+    // The unchanged reading after the first notification is skipped
+    let start = Instant::now();
+    thread::spawn(move || {
+        for _ in 0..2 {
+            thread::sleep(ms(10));
+            notify.send(()).unwrap();
+        }
+    });
+    assert_eq!(changes.next().unwrap().unwrap(), 5.0);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn sysfs_notifier_times_out_on_plain_files() {
+    let mut fake = FakeSysfsBackend::new().unwrap();
+    let mut notifier = fake.speed_notifier().expect("sysfs can be polled");
+
+    // Regular files are never notified, so waiting is a plain timeout
+    let start = Instant::now();
+    assert!(!notifier.wait(ms(30)).unwrap());
+    assert!(start.elapsed() >= ms(25));
+}
+
+#[test]
+fn control_loop_reads_on_notification() {
+    let mut mock = MockBackend::new([1.0, 10.0]);
+    let notify = mock.notify_with();
+    notify.send(()).unwrap();
+    notify.send(()).unwrap();
+
+    let start = Instant::now();
+    run_every(&mut mock, &Config::default(), Duration::from_secs(60)).unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        mock.writes(),
+        [[10, 0, 0], [100, 100, 100]].map(DutyFrame::from)
+    );
+}
+
+#[test]
+fn service_reads_on_notification() {
+    let mut mock = MockBackend::new([1.0, 10.0, 10.0]);
+    let notify = mock.notify_with();
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 60_000;
+    let service = Service::start(Box::new(mock), config).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while service.status().duties != Some([10, 0, 0].into()) {
+        assert!(Instant::now() < deadline, "no first reading");
+        thread::sleep(ms(5));
+    }
+    notify.send(()).unwrap();
+    while service.status().duties != Some([100, 100, 100].into()) {
+        assert!(Instant::now() < deadline, "notification ignored");
+        thread::sleep(ms(5));
+    }
+    service.stop();
+}
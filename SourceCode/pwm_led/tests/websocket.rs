@@ -0,0 +1,111 @@
+#![cfg(feature = "websocket")]
+
+use std::io::{Read, Write};
+use std::iter;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use pwm_led::{Config, Event, EventKind, HttpServer, MockBackend, Service};
+use tungstenite::{Error, Message};
+
+fn start(speeds: impl IntoIterator<Item = f64>) -> (Service, SocketAddr) {
+    let mut config = Config::default();
+    config.daemon.poll_interval_ms = 10;
+    let backend = MockBackend::new(speeds);
+    let service = Service::start(Box::new(backend), config).unwrap();
+
+    let server = HttpServer::bind("127.0.0.1:0", service.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+    (service, addr)
+}
+
+fn next_event(socket: &mut tungstenite::WebSocket<TcpStream>) -> Event {
+    loop {
+        match socket.read().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Ping(_) | Message::Pong(_) => continue,
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn streams_speed_and_duty_events() {
+    // Hold 1.0 long enough to connect, then jump to full speed
+    let speeds = iter::repeat_n(1.0, 20).chain(iter::repeat_n(10.0, 10_000));
+    let (service, addr) = start(speeds);
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let url = format!("ws://{}/events", addr);
+    let (mut socket, response) = tungstenite::client(url.as_str(), stream).unwrap();
+    assert_eq!(response.status(), 101);
+
+    let mut saw_speed = false;
+    let event = loop {
+        let event = next_event(&mut socket);
+        assert!(event.timestamp_ms > 0);
+        match event.kind {
+            EventKind::Speed => {
+                saw_speed = true;
+                assert!(event.filtered_speed.is_some());
+            }
+            EventKind::Duties if event.duties == Some([100, 100, 100].into()) => break event,
+            EventKind::Duties => {}
+        }
+    };
+    assert!(saw_speed);
+    assert_eq!(event.speed, Some(10.0));
+    service.stop();
+}
+
+#[test]
+fn events_needs_an_upgrade() {
+    let (service, addr) = start(iter::repeat_n(1.0, 10_000));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    assert!(reply.starts_with("HTTP/1.1 400"), "{}", reply);
+    service.stop();
+}
+
+#[test]
+fn answers_pings_and_close() {
+    let (service, addr) = start(iter::repeat_n(1.0, 10_000));
+
+    let stream = TcpStream::connect(addr).unwrap();
+    // A missing reply fails the test instead of hanging it
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let url = format!("ws://{}/events", addr);
+    let (mut socket, _) = tungstenite::client(url.as_str(), stream).unwrap();
+
+    socket.send(Message::Ping("are you there".into())).unwrap();
+    loop {
+        match socket.read().unwrap() {
+            Message::Pong(payload) => break assert_eq!(&payload[..], b"are you there"),
+            Message::Text(_) => continue,
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    // The server answers the close frame, which completes the handshake
+    socket.close(None).unwrap();
+    loop {
+        match socket.read() {
+            Ok(Message::Text(_)) => continue,
+            Ok(Message::Close(None)) => break,
+            other => panic!("expected the close handshake, got {:?}", other),
+        }
+    }
+    assert!(matches!(socket.read(), Err(Error::ConnectionClosed)));
+    // And its thread is done with the connection
+    assert_eq!(socket.get_mut().read(&mut [0; 16]).unwrap(), 0);
+    service.stop();
+}
@@ -51,6 +51,8 @@ static int major;                   // number assigned to device
 static struct class *projectClass = NULL;    // Device class 
 static struct device *projectDevice = NULL;  // Device structure 
 static struct kobject *project_kobj;         // Kobject for sysfs entries 
+static struct kernfs_node *speed_dirent;     // button_speed, for poll() notifications 
+static struct kernfs_node *interval_dirent;  // press_interval_ns, for poll() notifications 
 
 // LED PWM duty cycles (percentage 0-100) 
 static int led1_duty = 0; 
@@ -74,7 +76,6 @@ static ktime_t pwm_off_time;        // Duration for PWM OFF state
 
 // for device Input-Output 
 static char message[BUF_LEN];       // Buffer for message to user space 
-static char *msg_ptr;               // Pointer to current position in message 
 
 // Function prototypes
 static int device_open(struct inode *, struct file *);
@@ -88,6 +89,7 @@ static ssize_t led2_duty_store(struct kobject *kobj, struct kobj_attribute *attr
 static ssize_t led3_duty_show(struct kobject *kobj, struct kobj_attribute *attr, char *buf);
 static ssize_t led3_duty_store(struct kobject *kobj, struct kobj_attribute *attr, const char *buf, size_t count);
 static ssize_t button_speed_show(struct kobject *kobj, struct kobj_attribute *attr, char *buf);
+static ssize_t press_interval_ns_show(struct kobject *kobj, struct kobj_attribute *attr, char *buf);
 
 //file operations for device driver 
 static struct file_operations project_fops = {
@@ -106,6 +108,8 @@ static struct kobj_attribute led3_attribute =
     __ATTR(led3_duty, 0664, led3_duty_show, led3_duty_store);  // LED3 duty cycle 
 static struct kobj_attribute speed_attribute = 
     __ATTR(button_speed, 0444, button_speed_show, NULL);       // Button speed 
+static struct kobj_attribute interval_attribute = 
+    __ATTR(press_interval_ns, 0444, press_interval_ns_show, NULL);  // Average press interval 
 
 // Grouping everything for sysfs 
 static struct attribute *attrs[] = {
@@ -113,6 +117,7 @@ static struct attribute *attrs[] = {
     &led2_attribute.attr,    // LED2 duty cycle 
     &led3_attribute.attr,    // LED3 duty cycle 
     &speed_attribute.attr,   // Button press speed 
+    &interval_attribute.attr, // Average press interval in ns 
     NULL,                    
 };
 
@@ -186,6 +191,16 @@ static enum hrtimer_restart pwm_timer_callback(struct hrtimer *timer) {
     return HRTIMER_RESTART;  // Keep the timer running 
 }
 
+ // notify_speed_change - Wakes user space waiting in poll() on the speed attributes
+// sysfs_notify_dirent is safe in interrupt context, unlike sysfs_notify
+
+static void notify_speed_change(void) {
+    if (speed_dirent)
+        sysfs_notify_dirent(speed_dirent);
+    if (interval_dirent)
+        sysfs_notify_dirent(interval_dirent);
+}
+
  // button1_handler - Interrupt handler for Button 1
  // Processes Button 1 presses and calculates timing if alternating with Button 2
 
@@ -210,6 +225,7 @@ static irqreturn_t button1_handler(int irq, void *dev_id) {
             total_press_time = avg_press_interval * 20; // weighted average
             valid_alternating_count = 20;
         }
+        notify_speed_change();
     }
     
     last_button = 1;  
@@ -243,6 +259,7 @@ static irqreturn_t button2_handler(int irq, void *dev_id) {
             total_press_time = avg_press_interval * 20; // average 
             valid_alternating_count = 20;
         }
+        notify_speed_change();
     }
     
     last_button = 2;  
@@ -349,20 +366,30 @@ static ssize_t button_speed_show(struct kobject *kobj, struct kobj_attribute *at
     return sprintf(buf, "%llu\n", speed);
 }
 
- //device_open - Called when the device is opened
- // Prepares the device for reading
+//press_interval_ns_show - Sysfs show function for the average press interval
+//Lets user space compute a fractional speed instead of the truncated button_speed
+
+static ssize_t press_interval_ns_show(struct kobject *kobj, struct kobj_attribute *attr, char *buf) {
+    return sprintf(buf, "%llu\n", avg_press_interval);  // 0 until two alternating presses 
+}
+
+ //format_speed_message - Writes the current speed into the message read from the device
  
-static int device_open(struct inode *inode, struct file *file) {
-    
-        if (avg_press_interval > 0) {
+static void format_speed_message(void) {
+    if (avg_press_interval > 0) {
         u64 speed = 1000000000ULL;
         do_div(speed, avg_press_interval);
         sprintf(message, "Button Press Speed: %llu presses/second\n", speed);
     } else {
         sprintf(message, "Button Press Speed: 0 presses/second\n");
     }
-    
-    msg_ptr = message;  
+}
+
+ //device_open - Called when the device is opened
+ // Prepares the device for reading
+ 
+static int device_open(struct inode *inode, struct file *file) {
+    format_speed_message();
     
     return SUCCESS;
 }
@@ -378,20 +405,13 @@ static int device_release(struct inode *inode, struct file *file) {
  // Sends data from kernel to user space
  
 static ssize_t device_read(struct file *filp, char __user *buffer, size_t length, loff_t *offset) {
-    int bytes_read = 0;
-    
-    // End of message
-    if (*msg_ptr == 0)
-        return 0;
-    
-    // Copy data to user space
-    while (length && *msg_ptr) {
-        put_user(*(msg_ptr++), buffer++);  
-        length--;
-        bytes_read++;
-    }
+    // Reading from the start regenerates the message, so a descriptor kept
+    // open can be re-read with pread() at offset 0
+    if (*offset == 0)
+        format_speed_message();
     
-    return bytes_read;
+    // Copy data to user space, up to the end of the message
+    return simple_read_from_buffer(buffer, length, offset, message, strlen(message));
 }
 
  //device_write - Called when the device is written to
@@ -486,6 +506,8 @@ static int __init project_init(void) {
         pr_alert("Failed to create sysfs group\n");
         return ret;
     }
+    speed_dirent = sysfs_get_dirent(project_kobj->sd, "button_speed");
+    interval_dirent = sysfs_get_dirent(project_kobj->sd, "press_interval_ns");
     
     // Sets up GPIO 
     ret = gpio_request(LED1_PIN, "LED1");
@@ -568,6 +590,8 @@ fail_irq:
     gpio_free(LED1_PIN);
     
 fail_gpio:
+    sysfs_put(interval_dirent);
+    sysfs_put(speed_dirent);
     sysfs_remove_group(project_kobj, &attr_group);
     kobject_put(project_kobj);
     device_destroy(projectClass, MKDEV(major, 0));
@@ -598,6 +622,8 @@ static void __exit project_exit(void) {
     gpio_free(LED3_PIN);
     
     // Removes sysfs entries 
+    sysfs_put(interval_dirent);
+    sysfs_put(speed_dirent);
     sysfs_remove_group(project_kobj, &attr_group);
     kobject_put(project_kobj);
     
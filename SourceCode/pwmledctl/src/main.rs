@@ -0,0 +1,246 @@
+// pwmledctl - Command line control of the PWM LED controller.
+// Reads the button press speed, reads and sets LED duty cycles, and runs
+// the automatic speed-to-brightness loop over either kernel interface.
+
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+
+use pwm_led::chardev::format_command;
+use pwm_led::control::REFRESH_INTERVAL;
+use pwm_led::{
+    backend, run_reporting, run_until_shutdown, BackendKind, BackendPaths, Config, DutyFrame,
+    LedBackend, ShutdownSignals, MAX_DUTY,
+};
+
+const USAGE: &str = "\
+Usage: pwmledctl [OPTIONS] <COMMAND>
+
+Commands:
+  get speed             Print the button press speed (presses/second)
+  get duty              Print the duty cycle of every LED (not with chardev)
+  set duty <D1> <D2>..  Set every LED at once (0-100 each)
+  set led <N> <DUTY>    Set LED N (1-based) to DUTY (0-100, not with chardev)
+  off                   Turn every LED off
+  status                Show the interface, speed and duty cycles
+  run                   Map speed to duty cycles continuously
+
+Options:
+  --backend <chardev|sysfs|pwmchip|ledclass|softpwm|auto>
+                                  Interface to use (default: auto)
+  --config <PATH>                 Mapping config (default: $PWM_LED_CONFIG)
+  --device <PATH>                 Character device (default: /dev/pwm_led_controller)
+  --sysfs-path <DIR>              Sysfs directory (default: /sys/kernel/pwm_led_controller)
+  -h, --help                      Show this help";
+
+// One parsed command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    GetSpeed,
+    GetDuty,
+    SetDuty(Vec<u32>),
+    SetLed { led: usize, duty: u32 },
+    Off,
+    Status,
+    Run,
+    Help,
+}
+
+#[derive(Debug)]
+struct Options {
+    backend: BackendKind,
+    config: Option<PathBuf>,
+    paths: BackendPaths,
+    command: Command,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("Error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if options.command == Command::Help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(err) = execute(options) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+// Parses global options and the subcommand.
+fn parse_args<I>(args: I) -> Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut backend = BackendKind::Auto;
+    let mut config = None;
+    let mut paths = BackendPaths::default();
+    let mut words = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--backend" => backend = value("--backend")?.parse()?,
+            "--config" => config = Some(PathBuf::from(value("--config")?)),
+            "--device" => paths.device = PathBuf::from(value("--device")?),
+            "--sysfs-path" => paths.sysfs = PathBuf::from(value("--sysfs-path")?),
+            "-h" | "--help" => words = vec!["help".to_string()],
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => words.push(arg),
+        }
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        ["get", "speed"] => Command::GetSpeed,
+        ["get", "duty"] => Command::GetDuty,
+        ["set", "duty", duties @ ..] if !duties.is_empty() => Command::SetDuty(
+            duties
+                .iter()
+                .map(|duty| parse_duty(duty))
+                .collect::<Result<_, _>>()?,
+        ),
+        ["set", "led", led, duty] => Command::SetLed {
+            led: parse_led(led)?,
+            duty: parse_duty(duty)?,
+        },
+        ["off"] => Command::Off,
+        ["status"] => Command::Status,
+        ["run"] => Command::Run,
+        ["help"] => Command::Help,
+        [] => return Err("missing command".into()),
+        _ => return Err(format!("unknown command '{}'", words.join(" "))),
+    };
+    check_backend(backend, &command)?;
+
+    Ok(Options {
+        backend,
+        config,
+        paths,
+        command,
+    })
+}
+
+// The character device cannot report duty cycles, and every command opens
+// it afresh, so nothing is known about the LEDs a command does not set.
+fn check_backend(kind: BackendKind, command: &Command) -> Result<(), String> {
+    match (kind, command) {
+        (BackendKind::CharDev, Command::GetDuty | Command::SetLed { .. }) => Err(
+            "the chardev backend cannot report duty cycles; use 'set duty' with every LED".into(),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn parse_duty(text: &str) -> Result<u32, String> {
+    match text.parse::<u32>() {
+        Ok(duty) if duty <= MAX_DUTY => Ok(duty),
+        _ => Err(format!(
+            "duty '{}' is not a number from 0 to {}",
+            text, MAX_DUTY
+        )),
+    }
+}
+
+// LEDs are numbered from 1 on the command line, like the ledN_duty files.
+fn parse_led(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(led) if led >= 1 => Ok(led - 1),
+        _ => Err(format!("LED '{}' is not a number from 1 up", text)),
+    }
+}
+
+fn execute(options: Options) -> Result<(), Box<dyn Error>> {
+    let config = Config::load_or_default(options.config.as_deref())?;
+    let kind = options.backend.resolve(&options.paths)?;
+    check_backend(kind, &options.command)?;
+    let mut backend = backend::open_configured(kind, &options.paths, &config)?;
+
+    match options.command {
+        Command::GetSpeed => println!("{}", backend.read_speed()?),
+        Command::GetDuty => println!("{}", format_command(&backend.read_duties()?)),
+        Command::SetDuty(duties) => backend.write_duties(&duties.into())?,
+        Command::SetLed { led, duty } => backend.write_duty(led, duty)?,
+        Command::Off => backend.write_duties(&DutyFrame::off(backend.led_count()))?,
+        Command::Status => print_status(&mut backend, kind, &options, &config)?,
+        Command::Run => {
+            // Before the loop thread starts, so it inherits the blocked mask
+            let signals = ShutdownSignals::block()?;
+            println!("Project LED Controller - {} interface", kind);
+            println!("Press Ctrl+C to exit");
+            run_until_signalled(backend, config, signals)?;
+        }
+        Command::Help => unreachable!("help is handled before opening the backend"),
+    }
+    Ok(())
+}
+
+// Runs the control loop until SIGINT or SIGTERM, then leaves the LEDs as
+// the `[shutdown]` policy says.
+fn run_until_signalled(
+    mut backend: Box<dyn LedBackend + Send>,
+    config: Config,
+    signals: ShutdownSignals,
+) -> pwm_led::Result<()> {
+    let timeout = config.shutdown.timeout();
+    let shutdown = move || {
+        if let Ok(signal) = signals.wait() {
+            println!("Received {}, stopping", pwm_led::signal_name(signal));
+        }
+    };
+    run_until_shutdown(shutdown, timeout, move |stop| {
+        let initial = backend.read_duties().ok();
+        // Triggers and the like are handed back even if the loop failed
+        let result = run_reporting(&mut backend, &config, REFRESH_INTERVAL, stop, |reading| {
+            println!(
+                "Current button press speed: {} presses/second (filtered {:.2})",
+                reading.speed, reading.filtered_speed
+            );
+            println!("Setting LED duty cycles: {}", reading.target);
+        })
+        .and_then(|()| config.shutdown.policy.apply(&mut backend, initial.as_ref()));
+        result.and(backend.release())
+    })
+}
+
+// Prints what the controller is doing right now. Values the interface cannot
+// report are shown as unavailable rather than failing the whole command.
+fn print_status(
+    backend: &mut Box<dyn LedBackend + Send>,
+    kind: BackendKind,
+    options: &Options,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let location = match kind {
+        BackendKind::CharDev => options.paths.device.display(),
+        BackendKind::PwmChip => config.pwm.chip.display(),
+        BackendKind::LedClass => config.leds.path.display(),
+        BackendKind::SoftPwm => config.softpwm.chip.display(),
+        _ => options.paths.sysfs.display(),
+    };
+    println!("Interface: {} ({})", kind, location);
+    println!("LEDs:      {}", backend.led_count());
+
+    let speed = backend.read_speed()?;
+    println!("Speed:     {} presses/second", speed);
+
+    match backend.read_duties() {
+        Ok(frame) => println!("Duty:      {}", frame),
+        Err(err) => println!("Duty:      unavailable ({})", err),
+    }
+
+    // What `run` would set at this speed, before filtering and fading
+    if config.mapping.channels() == backend.led_count() {
+        println!("Auto:      {}", config.mapping.map(speed));
+    }
+    Ok(())
+}
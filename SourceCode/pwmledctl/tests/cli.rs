@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command, Output};
+
+use pwm_led::ledclass::active_trigger;
+use pwm_led::{FakeLedClass, FakeSysfsBackend};
+
+fn pwmledctl(fake: &FakeSysfsBackend, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pwmledctl"))
+        .arg("--backend")
+        .arg("sysfs")
+        .arg("--sysfs-path")
+        .arg(fake.path())
+        .args(args)
+        .env_remove("PWM_LED_CONFIG")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn get_speed_and_duty() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(3.5).unwrap();
+
+    assert_eq!(stdout(&pwmledctl(&fake, &["get", "speed"])), "3.5\n");
+    assert_eq!(stdout(&pwmledctl(&fake, &["get", "duty"])), "0 0 0\n");
+}
+
+#[test]
+fn set_duty_led_and_off() {
+    let fake = FakeSysfsBackend::new().unwrap();
+
+    stdout(&pwmledctl(&fake, &["set", "duty", "40", "0", "100"]));
+    assert_eq!(fake.duties().unwrap(), [40, 0, 100]);
+
+    stdout(&pwmledctl(&fake, &["set", "led", "2", "55"]));
+    assert_eq!(fake.duties().unwrap(), [40, 55, 100]);
+
+    stdout(&pwmledctl(&fake, &["off"]));
+    assert_eq!(fake.duties().unwrap(), [0, 0, 0]);
+}
+
+#[test]
+fn status_report() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+
+    let report = stdout(&pwmledctl(&fake, &["status"]));
+    assert!(report.contains("Interface: sysfs"), "{}", report);
+    assert!(
+        report.contains("Speed:     10 presses/second"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("Duty:      L1=0%, L2=0%, L3=0%"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("Auto:      L1=100%, L2=100%, L3=100%"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn bad_input_is_rejected() {
+    let fake = FakeSysfsBackend::new().unwrap();
+
+    assert_eq!(
+        pwmledctl(&fake, &["set", "led", "0", "10"]).status.code(),
+        Some(2)
+    );
+    assert_eq!(
+        pwmledctl(&fake, &["set", "duty", "101"]).status.code(),
+        Some(2)
+    );
+    assert_eq!(pwmledctl(&fake, &["frobnicate"]).status.code(), Some(2));
+
+    // Wrong channel count and LEDs the panel lacks are runtime errors.
+    assert_eq!(
+        pwmledctl(&fake, &["set", "duty", "1", "2"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        pwmledctl(&fake, &["set", "led", "4", "10"]).status.code(),
+        Some(1)
+    );
+    assert_eq!(fake.duties().unwrap(), [0, 0, 0]);
+}
+
+fn pwmledctl_chardev(device: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pwmledctl"))
+        .arg("--backend")
+        .arg("chardev")
+        .arg("--device")
+        .arg(device)
+        .args(args)
+        .env_remove("PWM_LED_CONFIG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn chardev_needs_every_duty() {
+    // A plain file stands in for the device node
+    let device = std::env::temp_dir().join(format!("pwmledctl-chardev-{}", process::id()));
+    fs::write(&device, "Button Press Speed: 3 presses/second\n").unwrap();
+    assert_eq!(
+        stdout(&pwmledctl_chardev(&device, &["get", "speed"])),
+        "3\n"
+    );
+
+    // Unlike a device node, the file keeps what is not overwritten
+    fs::write(&device, "").unwrap();
+    stdout(&pwmledctl_chardev(
+        &device,
+        &["set", "duty", "40", "0", "100"],
+    ));
+    assert_eq!(fs::read_to_string(&device).unwrap(), "40 0 100");
+    fs::write(&device, "").unwrap();
+    stdout(&pwmledctl_chardev(&device, &["off"]));
+    assert_eq!(fs::read_to_string(&device).unwrap(), "0 0 0");
+
+    // Refused before the device is touched
+    for args in [&["get", "duty"][..], &["set", "led", "2", "55"]] {
+        let output = pwmledctl_chardev(&device, args);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("cannot report duty cycles"), "{}", stderr);
+    }
+    assert_eq!(fs::read_to_string(&device).unwrap(), "0 0 0");
+    fs::remove_file(&device).unwrap();
+}
+
+#[test]
+fn ledclass_reads_leave_triggers_alone() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(4.0).unwrap();
+    let leds = FakeLedClass::new().unwrap();
+    for name in ["red", "green", "blue"] {
+        leds.add(name, 255, "heartbeat").unwrap();
+    }
+    let config = leds.path().join("pwm_led.toml");
+    fs::write(
+        &config,
+        format!(
+            "[leds]\npath = {:?}\nnames = [\"red\", \"green\", \"blue\"]\nspeed = {:?}\n",
+            leds.path(),
+            fake.path().join("button_speed")
+        ),
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pwmledctl"))
+            .args(["--backend", "ledclass", "--config"])
+            .arg(&config)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    assert_eq!(stdout(&run(&["get", "duty"])), "0 0 0\n");
+    assert_eq!(stdout(&run(&["get", "speed"])), "4\n");
+    stdout(&run(&["status"]));
+    for name in ["red", "green", "blue"] {
+        let trigger = leds.trigger(name).unwrap();
+        assert_eq!(active_trigger(&trigger), Some("heartbeat"), "{}", name);
+    }
+
+    // Writing takes them, or the trigger would override the brightness
+    stdout(&run(&["set", "led", "2", "40"]));
+    assert_eq!(leds.trigger("green").unwrap(), "none");
+    assert_eq!(leds.brightness("green").unwrap(), 102);
+}
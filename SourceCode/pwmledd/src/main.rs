@@ -0,0 +1,162 @@
+// pwmledd - Runs the PWM LED control loop in the background.
+// Clients query the state, override the duties, switch mapping profiles or
+// pause the loop with JSON lines on a Unix socket, and optionally over HTTP
+// and MQTT.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+use pwm_led::{
+    backend, signal_name, BackendKind, BackendPaths, Config, MqttBridge, Server, Service,
+    ShutdownSignals,
+};
+
+const USAGE: &str = "\
+Usage: pwmledd [OPTIONS]
+
+Options:
+  --backend <chardev|sysfs|pwmchip|ledclass|softpwm|auto>
+                                  Interface to use (default: auto)
+  --config <PATH>                 Mapping config (default: $PWM_LED_CONFIG)
+  --device <PATH>                 Character device (default: /dev/pwm_led_controller)
+  --sysfs-path <DIR>              Sysfs directory (default: /sys/kernel/pwm_led_controller)
+  --socket <PATH>                 Control socket (default: [daemon] socket, /run/pwmledd.sock)
+  --http <ADDR:PORT>              Serve the HTTP API (default: [daemon] http, off)
+  -h, --help                      Show this help";
+
+#[derive(Debug, Default)]
+struct Options {
+    backend: BackendKind,
+    config: Option<PathBuf>,
+    paths: BackendPaths,
+    socket: Option<PathBuf>,
+    http: Option<SocketAddr>,
+    help: bool,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("Error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(err) = serve(options) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args<I>(args: I) -> Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--backend" => options.backend = value("--backend")?.parse()?,
+            "--config" => options.config = Some(PathBuf::from(value("--config")?)),
+            "--device" => options.paths.device = PathBuf::from(value("--device")?),
+            "--sysfs-path" => options.paths.sysfs = PathBuf::from(value("--sysfs-path")?),
+            "--socket" => options.socket = Some(PathBuf::from(value("--socket")?)),
+            "--http" => {
+                let addr = value("--http")?;
+                let addr = addr
+                    .parse()
+                    .map_err(|_| format!("--http '{}' is not an ADDR:PORT", addr))?;
+                options.http = Some(addr);
+            }
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn serve(options: Options) -> Result<(), Box<dyn Error>> {
+    // Before any thread starts, so only `shutdown_on_signal` receives them
+    let signals = ShutdownSignals::block()?;
+    let config = Config::load_or_default(options.config.as_deref())?;
+    let kind = options.backend.resolve(&options.paths)?;
+    let backend = backend::open_configured(kind, &options.paths, &config)?;
+
+    let socket = options
+        .socket
+        .unwrap_or_else(|| config.daemon.socket.clone());
+    let http = options.http.or(config.daemon.http);
+    let mqtt = config.mqtt.clone();
+    let service = Service::start(backend, config)?;
+    let server = Server::bind(&socket, service.clone())?;
+    println!(
+        "pwmledd - {} interface, listening on {}",
+        kind,
+        socket.display()
+    );
+    if let Some(mqtt) = mqtt {
+        println!("Publishing to MQTT broker {}", mqtt.broker);
+        // The bridge thread keeps running once the handle is dropped
+        MqttBridge::start(service.clone(), mqtt);
+    }
+    if let Some(addr) = http {
+        serve_http(addr, service.clone())?;
+    }
+    thread::Builder::new()
+        .name("pwmledd-signals".into())
+        .spawn(move || shutdown_on_signal(signals, service, socket))?;
+    server.serve()?;
+    Ok(())
+}
+
+// Waits for SIGINT or SIGTERM, stops the loop with the `[shutdown]` policy
+// and exits. Exits anyway if the LEDs cannot be settled in time.
+fn shutdown_on_signal(signals: ShutdownSignals, service: Service, socket: PathBuf) {
+    match signals.wait() {
+        Ok(signal) => println!("Received {}, shutting down", signal_name(signal)),
+        Err(err) => {
+            eprintln!("Error: cannot wait for signals: {}", err);
+            return;
+        }
+    }
+    let code = match service.shutdown() {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
+    };
+    // The server is still blocked accepting clients and never drops
+    let _ = fs::remove_file(&socket);
+    process::exit(code);
+}
+
+#[cfg(feature = "http")]
+fn serve_http(addr: SocketAddr, service: Service) -> Result<(), Box<dyn Error>> {
+    let server = pwm_led::HttpServer::bind(addr, service)?;
+    println!("HTTP API on http://{}", server.local_addr()?);
+    thread::spawn(move || {
+        if let Err(err) = server.serve() {
+            eprintln!("HTTP server stopped: {}", err);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn serve_http(_addr: SocketAddr, _service: Service) -> Result<(), Box<dyn Error>> {
+    Err("pwmledd was built without the http feature".into())
+}
@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pwm_led::{Client, FakeSysfsBackend, Mode};
+
+// Kills the daemon when the test ends, even on failure.
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn(fake: &FakeSysfsBackend, config: &Path, socket: &Path) -> Daemon {
+    let child = Command::new(env!("CARGO_BIN_EXE_pwmledd"))
+        .arg("--backend")
+        .arg("sysfs")
+        .arg("--sysfs-path")
+        .arg(fake.path())
+        .arg("--config")
+        .arg(config)
+        .arg("--socket")
+        .arg(socket)
+        .spawn()
+        .unwrap();
+    Daemon(child)
+}
+
+fn wait_for(what: &str, mut check: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !check() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn controls_the_fake_sysfs() {
+    let fake = FakeSysfsBackend::new().unwrap();
+    fake.set_speed(10.0).unwrap();
+    let config = fake.path().join("pwmledd.toml");
+    fs::write(
+        &config,
+        "[daemon]\npoll_interval_ms = 10\n\n[profiles.dim]\n\
+         [[profiles.dim.led]]\nstart = 0.0\nslope = 0.0\nfloor = 1\n\
+         [[profiles.dim.led]]\nstart = 0.0\nslope = 0.0\nfloor = 2\n\
+         [[profiles.dim.led]]\nstart = 0.0\nslope = 0.0\nfloor = 3\n",
+    )
+    .unwrap();
+    let socket: PathBuf = env::temp_dir().join(format!("pwmledd-e2e-{}.sock", process::id()));
+    let _daemon = spawn(&fake, &config, &socket);
+
+    wait_for("socket", || socket.exists());
+    let mut client = Client::connect(&socket).unwrap();
+    let shows = |expected: [u32; 3]| fake.duties().is_ok_and(|duties| duties == expected);
+    wait_for("auto duty", || shows([100, 100, 100]));
+
+    client.set_override([20, 30, 40]).unwrap();
+    wait_for("override", || shows([20, 30, 40]));
+
+    client.set_profile("dim").unwrap();
+    let status = client.resume().unwrap();
+    assert_eq!(status.mode, Mode::Auto);
+    assert_eq!(status.profile, "dim");
+    wait_for("dim profile", || shows([1, 2, 3]));
+
+    assert!(client.set_profile("missing").is_err());
+    assert_eq!(client.status().unwrap().speed, Some(10.0));
+}